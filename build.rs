@@ -0,0 +1,22 @@
+use std::process::Command;
+
+fn command_output(program: &str, args: &[&str]) -> String {
+	Command::new(program)
+		.args(args)
+		.output()
+		.ok()
+		.filter(|output| output.status.success())
+		.and_then(|output| String::from_utf8(output.stdout).ok())
+		.map(|output| output.trim().to_string())
+		.unwrap_or_else(|| "unknown".to_string())
+}
+
+fn main() {
+	let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+
+	println!("cargo:rustc-env=KFS_GIT_HASH={}", command_output("git", &["rev-parse", "--short", "HEAD"]));
+	println!("cargo:rustc-env=KFS_BUILD_DATE={}", command_output("date", &["-u", "+%Y-%m-%d"]));
+	println!("cargo:rustc-env=KFS_RUSTC_VERSION={}", command_output(&rustc, &["--version"]));
+	println!("cargo:rerun-if-changed=.git/HEAD");
+	println!("cargo:rerun-if-changed=src");
+}
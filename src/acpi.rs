@@ -0,0 +1,149 @@
+use core::fmt;
+use core::ptr::read_unaligned;
+use crate::sync::Mutex;
+use crate::multiboot;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Bytes covered by the checksum of ACPI 1.0, and by the extended one.
+const RSDP_V1_LENGTH: usize = 20;
+const RSDP_V2_LENGTH: usize = 36;
+const SDT_HEADER_LENGTH: usize = 36;
+/// Real-mode segment of the Extended BIOS Data Area, in the BIOS data area.
+const EBDA_SEGMENT_POINTER: usize = 0x40e;
+const EBDA_SCAN_LENGTH: usize = 1024;
+const BIOS_AREA: (usize, usize) = (0xe0000, 0x100000);
+
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_ACPI_NEW: u32 = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+	Multiboot,
+	Bios,
+}
+
+/// What the RSDP points at. Tables are read at their physical address, so
+/// they have to sit in identity-mapped memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Rsdp {
+	pub revision: u8,
+	pub rsdt: u32,
+	/// 0 before ACPI 2.0.
+	pub xsdt: u64,
+	pub source: Source,
+}
+
+static RSDP: Mutex<Option<Rsdp>> = Mutex::new(None);
+
+pub fn rsdp() -> Option<Rsdp> {
+	*RSDP.lock()
+}
+
+fn read_u32(address: usize) -> u32 {
+	unsafe { read_unaligned(address as *const u32) }
+}
+
+fn read_u64(address: usize) -> u64 {
+	unsafe { read_unaligned(address as *const u64) }
+}
+
+fn checksum(address: usize, length: usize) -> bool {
+	let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, length) };
+	bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// The RSDP at `address` when its signature and checksums hold. `available`
+/// bounds the bytes that may be read, as a multiboot tag does.
+fn parse_rsdp(address: usize, available: usize, source: Source) -> Option<Rsdp> {
+	if available < RSDP_V1_LENGTH {
+		return None;
+	}
+	let signature = unsafe { &*(address as *const [u8; 8]) };
+	if signature != RSDP_SIGNATURE || !checksum(address, RSDP_V1_LENGTH) {
+		return None;
+	}
+	let revision = unsafe { *((address + 15) as *const u8) };
+	let rsdt = read_u32(address + 16);
+	let mut xsdt = 0;
+	if revision >= 2 && available >= RSDP_V2_LENGTH {
+		let length = read_u32(address + 20) as usize;
+		if length < RSDP_V2_LENGTH || length > available || !checksum(address, length) {
+			return None;
+		}
+		xsdt = read_u64(address + 24);
+	}
+	Some(Rsdp { revision, rsdt, xsdt, source })
+}
+
+/// The RSDP sits on a 16-byte boundary.
+fn scan(start: usize, end: usize) -> Option<Rsdp> {
+	(start..end).step_by(16).find_map(|address| parse_rsdp(address, end - address, Source::Bios))
+}
+
+/// Takes the RSDP the boot loader copied into a multiboot tag, the ACPI 2.0
+/// one first, and falls back to the BIOS areas the specification lists: the
+/// first KB of the EBDA, then 0xe0000 to 0xfffff.
+pub fn init() {
+	let from_tag = [TAG_ACPI_NEW, TAG_ACPI_OLD].into_iter()
+		.filter_map(multiboot::kept_tag)
+		.find_map(|tag| parse_rsdp(tag.address as usize + 8, tag.size as usize - 8, Source::Multiboot));
+	let found = from_tag.or_else(|| {
+		let ebda = (unsafe { read_unaligned(EBDA_SEGMENT_POINTER as *const u16) } as usize) << 4;
+		let in_ebda = if ebda != 0 { scan(ebda, ebda + EBDA_SCAN_LENGTH) } else { None };
+		in_ebda.or_else(|| scan(BIOS_AREA.0, BIOS_AREA.1))
+	});
+	match found {
+		Some(rsdp) => log!(Info, "acpi: RSDP revision {} from {:?}, RSDT {:#x}, XSDT {:#x}", rsdp.revision, rsdp.source, rsdp.rsdt, rsdp.xsdt),
+		None => log!(Warning, "acpi: no RSDP found"),
+	}
+	*RSDP.lock() = found;
+}
+
+/// Addresses of the tables listed by the XSDT, or the RSDT without one.
+/// XSDT entries above 4 GB are skipped, they cannot be reached.
+fn tables() -> impl Iterator<Item = usize> {
+	let rsdp = rsdp();
+	let (root, entry_size) = match rsdp {
+		Some(rsdp) if rsdp.xsdt != 0 && rsdp.xsdt < 1 << 32 => (rsdp.xsdt as usize, 8),
+		Some(rsdp) => (rsdp.rsdt as usize, 4),
+		None => (0, 4),
+	};
+	let valid = root != 0 && valid_table(root);
+	let length = if valid { read_u32(root + 4) as usize } else { 0 };
+	let count = length.saturating_sub(SDT_HEADER_LENGTH) / entry_size;
+	(0..count).filter_map(move |index| {
+		let entry = root + SDT_HEADER_LENGTH + index * entry_size;
+		let address = if entry_size == 8 { read_u64(entry) } else { read_u32(entry) as u64 };
+		(address != 0 && address < 1 << 32).then_some(address as usize)
+	})
+}
+
+/// Address of the first table with `wanted` as its signature and a good
+/// checksum.
+pub fn find_table(wanted: &[u8; 4]) -> Option<usize> {
+	tables().find(|&address| signature(address) == *wanted && valid_table(address))
+}
+
+fn valid_table(address: usize) -> bool {
+	let length = read_u32(address + 4) as usize;
+	length >= SDT_HEADER_LENGTH && checksum(address, length)
+}
+
+fn signature(address: usize) -> [u8; 4] {
+	unsafe { read_unaligned(address as *const [u8; 4]) }
+}
+
+/// /proc/acpi: where the RSDP came from and the tables it leads to.
+pub fn write_tables(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let rsdp = match rsdp() {
+		Some(rsdp) => rsdp,
+		None => return writeln!(writer, "no RSDP"),
+	};
+	writeln!(writer, "RSDP revision {} ({:?}) RSDT {:#010x} XSDT {:#x}", rsdp.revision, rsdp.source, rsdp.rsdt, rsdp.xsdt)?;
+	for address in tables() {
+		let name = signature(address);
+		let state = if valid_table(address) { "" } else { " (bad checksum)" };
+		writeln!(writer, "{}  {:#010x}{}", core::str::from_utf8(&name).unwrap_or("????"), address, state)?;
+	}
+	Ok(())
+}
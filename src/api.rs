@@ -0,0 +1,133 @@
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use crate::sync::Mutex;
+use crate::collections::{ FixedString, FixedVec };
+use crate::error::KernelError;
+use crate::interrupts::{ self, InterruptGuard };
+
+/// KernelApi fields are only ever appended, which bumps the minor version.
+/// Anything else bumps the major one, and an extension built against
+/// another major version must refuse to run.
+pub const API_VERSION_MAJOR: u16 = 1;
+pub const API_VERSION_MINOR: u16 = 0;
+pub const API_VERSION: u32 = (API_VERSION_MAJOR as u32) << 16 | API_VERSION_MINOR as u32;
+
+const MAX_COMMANDS: usize = 8;
+const MAX_COMMAND_NAME: usize = 16;
+/// IRQ9 to IRQ11, the lines no device of the PC claims.
+const FREE_IRQS: core::ops::RangeInclusive<u32> = 9..=11;
+
+/// Called with the text after the command name; a non-zero status is
+/// reported by the shell.
+pub type CommandHandler = extern "C" fn(arguments: *const u8, length: usize) -> i32;
+
+/// Functions handed to applets and future modules, all cdecl. Calls that
+/// can fail return 0 or a negated errno.
+#[repr(C)]
+pub struct KernelApi {
+	pub version: u32,
+	/// size_of::<KernelApi>(), telling which fields this kernel has.
+	pub size: u32,
+	pub print: extern "C" fn(text: *const u8, length: usize),
+	pub uptime_ms: extern "C" fn() -> u64,
+	/// Null until there is a kernel heap to allocate from.
+	pub alloc: extern "C" fn(size: usize) -> *mut u8,
+	pub free: extern "C" fn(pointer: *mut u8),
+	pub register_command: extern "C" fn(name: *const u8, length: usize, handler: CommandHandler) -> i32,
+	/// Only the free lines, IRQ9 to IRQ11, can be taken.
+	pub register_irq: extern "C" fn(line: u32, handler: extern "C" fn()) -> i32,
+}
+
+pub static KERNEL_API: KernelApi = KernelApi {
+	version: API_VERSION,
+	size: core::mem::size_of::<KernelApi>() as u32,
+	print,
+	uptime_ms,
+	alloc,
+	free,
+	register_command,
+	register_irq,
+};
+
+#[derive(Clone, Copy)]
+struct Command {
+	name: FixedString<MAX_COMMAND_NAME>,
+	handler: CommandHandler,
+}
+
+static COMMANDS: Mutex<FixedVec<Command, MAX_COMMANDS>> = Mutex::new(FixedVec::new());
+/// Handler address for each free IRQ line, 0 when the line is not taken.
+static IRQ_HANDLERS: [AtomicUsize; 3] = [const { AtomicUsize::new(0) }; 3];
+
+fn errno(error: KernelError) -> i32 {
+	-error.errno()
+}
+
+extern "C" fn print(text: *const u8, length: usize) {
+	let bytes = unsafe { core::slice::from_raw_parts(text, length) };
+	print!("{}", core::str::from_utf8(bytes).unwrap_or("?"));
+}
+
+extern "C" fn uptime_ms() -> u64 {
+	crate::timer::uptime_ms()
+}
+
+extern "C" fn alloc(_size: usize) -> *mut u8 {
+	core::ptr::null_mut()
+}
+
+extern "C" fn free(_pointer: *mut u8) {}
+
+extern "C" fn register_command(name: *const u8, length: usize, handler: CommandHandler) -> i32 {
+	let name = match core::str::from_utf8(unsafe { core::slice::from_raw_parts(name, length) }) {
+		Ok(name) if !name.is_empty() && length <= MAX_COMMAND_NAME && !name.contains(char::is_whitespace) => name,
+		_ => return errno(KernelError::Invalid),
+	};
+	if crate::shell::find_command(name).is_some() || find_command(name).is_some() {
+		return errno(KernelError::Busy);
+	}
+	let _guard = InterruptGuard::new();
+	match COMMANDS.lock().push(Command { name: FixedString::from(name), handler }) {
+		Ok(()) => 0,
+		Err(_) => errno(KernelError::NoMemory),
+	}
+}
+
+extern "C" fn register_irq(line: u32, handler: extern "C" fn()) -> i32 {
+	if !FREE_IRQS.contains(&line) {
+		return errno(KernelError::Invalid);
+	}
+	let slot = &IRQ_HANDLERS[(line - *FREE_IRQS.start()) as usize];
+	if slot.compare_exchange(0, handler as usize, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+		return errno(KernelError::Busy);
+	}
+	interrupts::enable_irq(line as usize);
+	0
+}
+
+/// A command registered through the API, for the shell to run.
+pub fn find_command(name: &str) -> Option<CommandHandler> {
+	let _guard = InterruptGuard::new();
+	let commands = COMMANDS.lock();
+	commands.iter().find(|command| command.name.as_str() == name).map(|command| command.handler)
+}
+
+/// Called from the interrupt of a free line.
+pub fn interrupt(line: usize) {
+	let handler = IRQ_HANDLERS[line - *FREE_IRQS.start() as usize].load(Ordering::SeqCst);
+	if handler != 0 {
+		let handler: extern "C" fn() = unsafe { core::mem::transmute(handler) };
+		handler();
+	}
+}
+
+/// Drops every command and IRQ handler registered, before the code they
+/// point into is overwritten.
+pub fn forget_all() {
+	let _guard = InterruptGuard::new();
+	*COMMANDS.lock() = FixedVec::new();
+	for (line, slot) in FREE_IRQS.zip(IRQ_HANDLERS.iter()) {
+		if slot.swap(0, Ordering::SeqCst) != 0 {
+			interrupts::disable_irq(line as usize);
+		}
+	}
+}
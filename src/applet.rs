@@ -0,0 +1,69 @@
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use crate::api::{ self, KernelApi, KERNEL_API };
+use crate::checksum;
+use crate::error::{ KernelError, KernelResult };
+use crate::vfs::{ self, FILES };
+
+/// Applets are flat binaries linked to run from here, where linker.ld puts
+/// the .applet section. Their entry point is their first byte.
+pub const APPLET_BASE: usize = 0x0080_0000;
+pub const APPLET_SIZE: usize = 64 * 1024;
+
+#[link_section = ".applet"]
+static mut APPLET_AREA: [u8; APPLET_SIZE] = [0; APPLET_SIZE];
+
+/// Size of the applet in APPLET_AREA, 0 when none is loaded.
+static LOADED: AtomicUsize = AtomicUsize::new(0);
+
+/// cdecl, taking the kernel API table and returning a status the shell
+/// reports.
+type Entry = extern "C" fn(api: *const KernelApi) -> i32;
+
+fn area() -> &'static mut [u8; APPLET_SIZE] {
+	unsafe { &mut *core::ptr::addr_of_mut!(APPLET_AREA) }
+}
+
+/// Copies `path` to APPLET_BASE and checks it against `expected_crc32`.
+/// Whatever was loaded before is gone, even when this fails, along with
+/// the commands and IRQ handlers it registered.
+pub fn load(path: &str, expected_crc32: usize) -> KernelResult<usize> {
+	if core::ptr::addr_of!(APPLET_AREA) as usize != APPLET_BASE {
+		log!(Error, "applet: area linked at {:#x}, not {:#x}", core::ptr::addr_of!(APPLET_AREA) as usize, APPLET_BASE);
+		return Err(KernelError::Fault);
+	}
+	LOADED.store(0, Ordering::SeqCst);
+	api::forget_all();
+	let descriptor = FILES.lock().open(path)?;
+	let area = area();
+	let mut size = 0;
+	let result = loop {
+		let mut probe = [0];
+		let buffer = if size < APPLET_SIZE { &mut area[size..] } else { &mut probe[..] };
+		let read = vfs::read(descriptor, buffer);
+		match read {
+			Ok(0) => break Ok(()),
+			Ok(_) if size == APPLET_SIZE => break Err(KernelError::NoMemory),
+			Ok(count) => size += count,
+			Err(error) => break Err(error.into()),
+		}
+	};
+	let _ = FILES.lock().close(descriptor);
+	result?;
+	if size == 0 {
+		return Err(KernelError::Invalid);
+	}
+	if !checksum::verify(path, &area[..size], Some(expected_crc32)) {
+		return Err(KernelError::Invalid);
+	}
+	LOADED.store(size, Ordering::SeqCst);
+	Ok(size)
+}
+
+/// Calls the loaded applet in ring 0. Nothing protects the kernel from it.
+pub fn run() -> KernelResult<i32> {
+	if LOADED.load(Ordering::SeqCst) == 0 {
+		return Err(KernelError::NotFound);
+	}
+	let entry: Entry = unsafe { core::mem::transmute(APPLET_BASE as *const ()) };
+	Ok(entry(&KERNEL_API))
+}
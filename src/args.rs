@@ -0,0 +1,115 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgError {
+	Missing(usize),
+	Invalid(usize),
+	OutOfRange(usize),
+	TooMany,
+	UnknownFlag(char),
+}
+
+/// Command line split into the command name (index 0), flags (`-x`) and
+/// positional arguments (index 1 and up). Flags come first: the first
+/// positional argument, or `--`, ends them.
+pub struct Args<'a> {
+	line: &'a str,
+}
+
+impl<'a> Args<'a> {
+	pub fn new(line: &'a str) -> Args<'a> {
+		Args { line }
+	}
+
+	pub fn name(&self) -> &'a str {
+		self.line.split_whitespace().next().unwrap_or("")
+	}
+
+	fn flag_tokens(&self) -> impl Iterator<Item = &'a str> {
+		self.line.split_whitespace().skip(1).take_while(|token| is_flag(token))
+	}
+
+	fn positionals(&self) -> impl Iterator<Item = &'a str> {
+		let mut tokens = self.line.split_whitespace().skip(1 + self.flag_tokens().count()).peekable();
+		if tokens.peek() == Some(&"--") {
+			tokens.next();
+		}
+		tokens
+	}
+
+	pub fn len(&self) -> usize {
+		self.positionals().count()
+	}
+
+	pub fn opt(&self, index: usize) -> Option<&'a str> {
+		if index == 0 {
+			return Some(self.name());
+		}
+		self.positionals().nth(index - 1)
+	}
+
+	pub fn get(&self, index: usize) -> Result<&'a str, ArgError> {
+		self.opt(index).ok_or(ArgError::Missing(index))
+	}
+
+	/// Parses a decimal or `0x` prefixed hexadecimal integer.
+	pub fn get_int(&self, index: usize) -> Result<usize, ArgError> {
+		parse_int(self.get(index)?).ok_or(ArgError::Invalid(index))
+	}
+
+	/// Raw text starting at the `index`-th positional argument, inner
+	/// whitespace preserved.
+	pub fn rest(&self, index: usize) -> Result<&'a str, ArgError> {
+		let token = self.get(index)?;
+		let offset = token.as_ptr() as usize - self.line.as_ptr() as usize;
+		Ok(self.line[offset..].trim_end())
+	}
+
+	pub fn flags(&self) -> impl Iterator<Item = char> + 'a {
+		self.flag_tokens().flat_map(|token| token[1..].chars())
+	}
+
+	pub fn expect_max(&self, count: usize) -> Result<(), ArgError> {
+		if self.len() > count {
+			return Err(ArgError::TooMany);
+		}
+		Ok(())
+	}
+
+	pub fn report(&self, error: ArgError, usage: &str) {
+		let name = self.name();
+		match error {
+			ArgError::Missing(_) => println!("{}: missing argument", name),
+			ArgError::Invalid(index) => println!("{}: invalid argument '{}'", name, self.opt(index).unwrap_or("")),
+			ArgError::OutOfRange(index) => println!("{}: argument '{}' out of range", name, self.opt(index).unwrap_or("")),
+			ArgError::TooMany => println!("{}: too many arguments", name),
+			ArgError::UnknownFlag(flag) => println!("{}: invalid option -- '{}'", name, flag),
+		}
+		println!("usage: {}", usage);
+	}
+}
+
+/// `-` followed by letters only, so negative numbers and expressions such
+/// as `-(1)` stay positional.
+fn is_flag(token: &str) -> bool {
+	token.strip_prefix('-').is_some_and(|letters| !letters.is_empty() && letters.bytes().all(|byte| byte.is_ascii_alphabetic()))
+}
+
+pub fn parse_int(s: &str) -> Option<usize> {
+	match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+		Some(hex) => usize::from_str_radix(hex, 16).ok(),
+		None => s.parse::<usize>().ok(),
+	}
+}
+
+/// Value of `key=value` in a kernel command line.
+pub fn cmdline_option<'a>(cmdline: &'a str, key: &str) -> Option<&'a str> {
+	cmdline
+		.split_whitespace()
+		.filter_map(|option| option.split_once('='))
+		.find(|(name, _)| *name == key)
+		.map(|(_, value)| value)
+}
+
+/// Whether a bare `flag` (no value) is present in a kernel command line.
+pub fn cmdline_flag(cmdline: &str, flag: &str) -> bool {
+	cmdline.split_whitespace().any(|option| option == flag)
+}
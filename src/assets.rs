@@ -0,0 +1,27 @@
+/// Art and text embedded from src/assets, looked up by name instead of
+/// being spelled out in println! calls.
+pub struct Asset {
+	pub name: &'static str,
+	pub data: &'static [u8],
+}
+
+pub static ASSETS: [Asset; 3] = [
+	Asset { name: "welcome", data: include_bytes!("assets/welcome.txt") },
+	Asset { name: "miao", data: include_bytes!("assets/miao.txt") },
+	Asset { name: "splash", data: include_bytes!("assets/splash.txt") },
+];
+
+/// Panics on an unknown name: every caller names an asset of this table.
+pub fn get(name: &str) -> &'static Asset {
+	ASSETS.iter().find(|asset| asset.name == name).expect("unknown asset")
+}
+
+impl Asset {
+	pub fn text(&self) -> &'static str {
+		core::str::from_utf8(self.data).unwrap_or("")
+	}
+
+	pub fn lines(&self) -> core::str::Lines<'static> {
+		self.text().lines()
+	}
+}
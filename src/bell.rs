@@ -0,0 +1,94 @@
+use core::sync::atomic::{ AtomicBool, AtomicU32, Ordering };
+use crate::config::{ self, Key };
+use crate::driver;
+use crate::interrupts::{ TICKS, TICKS_PER_SECOND };
+use crate::io::{ inb, outb };
+use crate::video_graphics_array::WRITER;
+
+/// Values of `sysctl bell`, 0 keeping the bell silent.
+pub const BELL_BEEP: usize = 1;
+pub const BELL_FLASH: usize = 2;
+pub const BELL_BOTH: usize = 3;
+
+const PIT_FREQUENCY: u32 = 1193182;
+const BEEP_FREQUENCY: u32 = 880;
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+const SPEAKER_PORT: u16 = 0x61;
+/// About 110 ms, what two ticks of the old 18 Hz timer lasted.
+const BELL_TICKS: u32 = TICKS_PER_SECOND * 110 / 1000;
+
+static BEEPING: AtomicBool = AtomicBool::new(false);
+static FLASHED: AtomicBool = AtomicBool::new(false);
+static BELL_UNTIL: AtomicU32 = AtomicU32::new(0);
+
+/// Starts the bell according to the policy. Returns whether the caller,
+/// which holds the writer, must invert the screen now; the timer puts it
+/// back.
+pub fn ring() -> bool {
+	let policy = config::get(Key::Bell);
+	BELL_UNTIL.store(TICKS.load(Ordering::SeqCst) + BELL_TICKS, Ordering::SeqCst);
+	if (policy == BELL_BEEP || policy == BELL_BOTH) && !BEEPING.swap(true, Ordering::SeqCst) {
+		speaker_on();
+	}
+	(policy == BELL_FLASH || policy == BELL_BOTH) && !FLASHED.swap(true, Ordering::SeqCst)
+}
+
+/// Feedback for a refused line edit, silenced by `sysctl prompt_bell 0`.
+pub fn prompt_alert() {
+	if config::get(Key::PromptBell) == 0 {
+		return;
+	}
+	if ring() {
+		let _guard = crate::interrupts::InterruptGuard::new();
+		WRITER.lock().invert_colors();
+	}
+}
+
+/// Called from the timer interrupt. The writer may be held by the code the
+/// interrupt stopped, in which case the flash is undone on a later tick.
+pub fn tick(now: u32) {
+	if now < BELL_UNTIL.load(Ordering::SeqCst) {
+		return;
+	}
+	if BEEPING.swap(false, Ordering::SeqCst) {
+		speaker_off();
+	}
+	if FLASHED.load(Ordering::SeqCst) {
+		if let Some(mut writer) = WRITER.try_lock() {
+			writer.invert_colors();
+			FLASHED.store(false, Ordering::SeqCst);
+		}
+	}
+}
+
+/// The bell is stopped by the timer tick, so it is silenced before the
+/// timer goes away.
+pub fn init() {
+	let driver = driver::Driver { name: "bell", depends_on: &[], suspend: Some(silence), resume: None, shutdown: Some(silence) };
+	if let Err(error) = driver::register(driver) {
+		log!(Warning, "bell: no power hooks: {}", error.as_str());
+	}
+}
+
+fn silence() {
+	if BEEPING.swap(false, Ordering::SeqCst) {
+		speaker_off();
+	}
+}
+
+fn speaker_on() {
+	let divisor = PIT_FREQUENCY / BEEP_FREQUENCY;
+	unsafe {
+		outb(PIT_COMMAND, 0xb6);
+		outb(PIT_CHANNEL_2, divisor as u8);
+		outb(PIT_CHANNEL_2, (divisor >> 8) as u8);
+		outb(SPEAKER_PORT, inb(SPEAKER_PORT) | 0x03);
+	}
+}
+
+fn speaker_off() {
+	unsafe {
+		outb(SPEAKER_PORT, inb(SPEAKER_PORT) & !0x03);
+	}
+}
@@ -0,0 +1,148 @@
+use core::fmt;
+use crate::collections::FixedVec;
+
+pub const MAX_FIELDS: usize = 32;
+
+/// A named bit, or the bits from `low` to `high` inclusive read as one
+/// number.
+#[derive(Debug, Clone, Copy)]
+pub struct Field<'a> {
+	pub low: u8,
+	pub high: u8,
+	pub name: &'a str,
+}
+
+impl<'a> Field<'a> {
+	pub const fn bit(bit: u8, name: &'a str) -> Field<'a> {
+		Field { low: bit, high: bit, name }
+	}
+
+	pub const fn range(low: u8, high: u8, name: &'a str) -> Field<'a> {
+		Field { low, high, name }
+	}
+
+	pub fn extract(&self, value: u32) -> u32 {
+		let width = (self.high - self.low + 1) as u32;
+		(value >> self.low) & (u32::MAX >> (32 - width))
+	}
+}
+
+pub static CR0: &[Field] = &[
+	Field::bit(0, "PE"),
+	Field::bit(1, "MP"),
+	Field::bit(2, "EM"),
+	Field::bit(3, "TS"),
+	Field::bit(4, "ET"),
+	Field::bit(5, "NE"),
+	Field::bit(16, "WP"),
+	Field::bit(18, "AM"),
+	Field::bit(29, "NW"),
+	Field::bit(30, "CD"),
+	Field::bit(31, "PG"),
+];
+
+pub static CR4: &[Field] = &[
+	Field::bit(0, "VME"),
+	Field::bit(1, "PVI"),
+	Field::bit(2, "TSD"),
+	Field::bit(3, "DE"),
+	Field::bit(4, "PSE"),
+	Field::bit(5, "PAE"),
+	Field::bit(6, "MCE"),
+	Field::bit(7, "PGE"),
+	Field::bit(8, "PCE"),
+	Field::bit(9, "OSFXSR"),
+	Field::bit(10, "OSXMMEXCPT"),
+];
+
+pub static EFLAGS: &[Field] = &[
+	Field::bit(0, "CF"),
+	Field::bit(2, "PF"),
+	Field::bit(4, "AF"),
+	Field::bit(6, "ZF"),
+	Field::bit(7, "SF"),
+	Field::bit(8, "TF"),
+	Field::bit(9, "IF"),
+	Field::bit(10, "DF"),
+	Field::bit(11, "OF"),
+	Field::range(12, 13, "IOPL"),
+	Field::bit(14, "NT"),
+	Field::bit(16, "RF"),
+	Field::bit(17, "VM"),
+	Field::bit(18, "AC"),
+	Field::bit(19, "VIF"),
+	Field::bit(20, "VIP"),
+	Field::bit(21, "ID"),
+];
+
+/// Page fault error code: a clear P means the page was not present, a
+/// clear W a read, a clear U a kernel access.
+pub static PAGE_FAULT_ERROR: &[Field] = &[
+	Field::bit(0, "P"),
+	Field::bit(1, "W"),
+	Field::bit(2, "U"),
+	Field::bit(3, "RSVD"),
+	Field::bit(4, "I"),
+];
+
+/// The tables `bits` accepts by name in place of a spec.
+pub static NAMED: &[(&str, &[Field])] = &[
+	("cr0", CR0),
+	("cr4", CR4),
+	("eflags", EFLAGS),
+	("pf", PAGE_FAULT_ERROR),
+];
+
+/// Parses "0:PE,1:MP,31:PG", where a field may also span bits as
+/// "12-13:IOPL". None if an entry is malformed or past bit 31.
+pub fn parse_spec(spec: &str) -> Option<FixedVec<Field<'_>, MAX_FIELDS>> {
+	let mut fields = FixedVec::new();
+	for entry in spec.split(',').filter(|entry| !entry.is_empty()) {
+		let (bits, name) = entry.split_once(':')?;
+		let (low, high) = match bits.split_once('-') {
+			Some((low, high)) => (low.parse::<u8>().ok()?, high.parse::<u8>().ok()?),
+			None => {
+				let bit = bits.parse::<u8>().ok()?;
+				(bit, bit)
+			}
+		};
+		if low > high || high > 31 || name.is_empty() {
+			return None;
+		}
+		fields.push(Field::range(low, high, name)).ok()?;
+	}
+	Some(fields)
+}
+
+/// Names of the set bits and `NAME=value` for non-zero wider fields,
+/// space separated, as printed next to a register.
+pub struct Decoded<'a> {
+	value: u32,
+	fields: &'a [Field<'a>],
+}
+
+pub fn decode<'a>(value: u32, fields: &'a [Field<'a>]) -> Decoded<'a> {
+	Decoded { value, fields }
+}
+
+impl fmt::Display for Decoded<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut first = true;
+		for field in self.fields.iter() {
+			let value = field.extract(self.value);
+			if value == 0 {
+				continue;
+			}
+			if !first {
+				write!(f, " ")?;
+			}
+			if field.low == field.high {
+				write!(f, "{}", field.name)?;
+			} else {
+				write!(f, "{}={}", field.name, value)?;
+			}
+			first = false;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,39 @@
+use core::sync::atomic::{ AtomicBool, AtomicU32, Ordering };
+use crate::config::{ self, Key };
+use crate::interrupts::{ TICKS, TICKS_PER_SECOND };
+use crate::video_graphics_array::{ ScreenOwner, WRITER };
+
+static LAST_INPUT: AtomicU32 = AtomicU32::new(0);
+/// Input tick the screen was blanked at, so new input is seen on the next tick.
+static BLANKED_AT: AtomicU32 = AtomicU32::new(0);
+static BLANKED: AtomicBool = AtomicBool::new(false);
+
+/// Called from the keyboard interrupt for every byte.
+pub fn input() {
+	LAST_INPUT.store(TICKS.load(Ordering::SeqCst), Ordering::SeqCst);
+}
+
+/// Called from the timer interrupt: blanks the screen after `sysctl
+/// blank_minutes` without input, unless an animation owns the screen, and
+/// turns it back on at the first input. The writer may be held by the code
+/// the interrupt stopped, in which case this waits for a later tick.
+pub fn tick(now: u32) {
+	let last_input = LAST_INPUT.load(Ordering::SeqCst);
+	let blanked = BLANKED.load(Ordering::SeqCst);
+	if blanked && last_input == BLANKED_AT.load(Ordering::SeqCst) {
+		return;
+	}
+	let minutes = config::get(Key::BlankMinutes) as u32;
+	let idle = now.wrapping_sub(last_input) >= minutes.saturating_mul(60 * TICKS_PER_SECOND);
+	if blanked == (minutes != 0 && idle) {
+		return;
+	}
+	if let Some(mut writer) = WRITER.try_lock() {
+		if !blanked && writer.current_owner() == ScreenOwner::Animation {
+			return;
+		}
+		writer.set_blanked(!blanked);
+		BLANKED_AT.store(last_input, Ordering::SeqCst);
+		BLANKED.store(!blanked, Ordering::SeqCst);
+	}
+}
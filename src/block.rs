@@ -0,0 +1,14 @@
+pub const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+	OutOfRange(usize),
+}
+
+/// Fixed size block storage, addressed by block index.
+pub trait BlockDevice {
+	fn block_count(&self) -> usize;
+	fn read_block(&self, index: usize, buffer: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError>;
+	#[allow(dead_code)]
+	fn write_block(&mut self, index: usize, buffer: &[u8; BLOCK_SIZE]) -> Result<(), BlockError>;
+}
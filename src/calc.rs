@@ -0,0 +1,161 @@
+/// Integer expression evaluator behind `calc`. Arithmetic wraps at 32 bits
+/// like the machine's, and operators bind as in C:
+/// unary `- ~`, then `* / %`, `+ -`, `<< >>`, `&`, `^`, `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalcError {
+	Syntax(usize),
+	DivideByZero,
+	Overflow(usize),
+}
+
+impl CalcError {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			CalcError::Syntax(_) => "syntax error",
+			CalcError::DivideByZero => "division by zero",
+			CalcError::Overflow(_) => "literal too large",
+		}
+	}
+
+	/// Byte offset in the expression, when the error points at one.
+	pub fn position(self) -> Option<usize> {
+		match self {
+			CalcError::Syntax(position) | CalcError::Overflow(position) => Some(position),
+			CalcError::DivideByZero => None,
+		}
+	}
+}
+
+/// Each '(' costs a stack frame per precedence level, and the shell task's
+/// stack has no guard page.
+const MAX_NESTING: usize = 32;
+
+/// Binary operators by increasing precedence.
+const LEVELS: &[&[&str]] = &[&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+struct Parser<'a> {
+	input: &'a [u8],
+	position: usize,
+	depth: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn skip_spaces(&mut self) {
+		while self.input.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+			self.position += 1;
+		}
+	}
+
+	fn eat(&mut self, token: &str) -> bool {
+		self.skip_spaces();
+		if self.input[self.position..].starts_with(token.as_bytes()) {
+			self.position += token.len();
+			return true;
+		}
+		false
+	}
+
+	fn binary(&mut self, level: usize) -> Result<u32, CalcError> {
+		if level == LEVELS.len() {
+			return self.unary();
+		}
+		let mut value = self.binary(level + 1)?;
+		'operators: loop {
+			for &operator in LEVELS[level] {
+				if self.eat(operator) {
+					let right = self.binary(level + 1)?;
+					value = apply(operator, value, right)?;
+					continue 'operators;
+				}
+			}
+			return Ok(value);
+		}
+	}
+
+	fn unary(&mut self) -> Result<u32, CalcError> {
+		if self.eat("-") {
+			return Ok(self.unary()?.wrapping_neg());
+		}
+		if self.eat("~") {
+			return Ok(!self.unary()?);
+		}
+		if self.eat("+") {
+			return self.unary();
+		}
+		if self.eat("(") {
+			if self.depth == MAX_NESTING {
+				return Err(CalcError::Syntax(self.position - 1));
+			}
+			self.depth += 1;
+			let value = self.binary(0)?;
+			self.depth -= 1;
+			if !self.eat(")") {
+				return Err(CalcError::Syntax(self.position));
+			}
+			return Ok(value);
+		}
+		self.literal()
+	}
+
+	/// `42`, `0x2a`, `0b101010` or `0o52`, optionally followed by a K, M or
+	/// G multiplier (powers of 1024).
+	fn literal(&mut self) -> Result<u32, CalcError> {
+		self.skip_spaces();
+		let start = self.position;
+		while self.input.get(self.position).is_some_and(|byte| byte.is_ascii_alphanumeric() || *byte == b'_') {
+			self.position += 1;
+		}
+		let token = core::str::from_utf8(&self.input[start..self.position]).map_err(|_| CalcError::Syntax(start))?;
+		let (token, shift) = match token.as_bytes().last().map(u8::to_ascii_uppercase) {
+			Some(b'K') => (&token[..token.len() - 1], 10),
+			Some(b'M') => (&token[..token.len() - 1], 20),
+			Some(b'G') => (&token[..token.len() - 1], 30),
+			_ => (token, 0),
+		};
+		let (digits, radix) = match token.get(..2) {
+			Some("0x") | Some("0X") => (&token[2..], 16),
+			Some("0b") | Some("0B") => (&token[2..], 2),
+			Some("0o") | Some("0O") => (&token[2..], 8),
+			_ => (token, 10),
+		};
+		let mut value: u32 = 0;
+		let mut any = false;
+		for digit in digits.chars().filter(|&digit| digit != '_') {
+			let digit = digit.to_digit(radix).ok_or(CalcError::Syntax(start))?;
+			value = value
+				.checked_mul(radix)
+				.and_then(|value| value.checked_add(digit))
+				.ok_or(CalcError::Overflow(start))?;
+			any = true;
+		}
+		if !any {
+			return Err(CalcError::Syntax(start));
+		}
+		value.checked_mul(1 << shift).ok_or(CalcError::Overflow(start))
+	}
+}
+
+fn apply(operator: &str, left: u32, right: u32) -> Result<u32, CalcError> {
+	Ok(match operator {
+		"|" => left | right,
+		"^" => left ^ right,
+		"&" => left & right,
+		"<<" => left.checked_shl(right).unwrap_or(0),
+		">>" => left.checked_shr(right).unwrap_or(0),
+		"+" => left.wrapping_add(right),
+		"-" => left.wrapping_sub(right),
+		"*" => left.wrapping_mul(right),
+		"/" => left.checked_div(right).ok_or(CalcError::DivideByZero)?,
+		_ => left.checked_rem(right).ok_or(CalcError::DivideByZero)?,
+	})
+}
+
+pub fn evaluate(expression: &str) -> Result<u32, CalcError> {
+	let mut parser = Parser { input: expression.as_bytes(), position: 0, depth: 0 };
+	let value = parser.binary(0)?;
+	parser.skip_spaces();
+	if parser.position != expression.len() {
+		return Err(CalcError::Syntax(parser.position));
+	}
+	Ok(value)
+}
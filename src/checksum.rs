@@ -0,0 +1,65 @@
+const CRC32_POLYNOMIAL: u32 = 0xedb88320;
+const ADLER_MODULO: u32 = 65521;
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+	let mut table = [0; 256];
+	let mut index = 0;
+	while index < 256 {
+		let mut crc = index as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32_POLYNOMIAL } else { crc >> 1 };
+			bit += 1;
+		}
+		table[index] = crc;
+		index += 1;
+	}
+	table
+}
+
+/// CRC-32 as computed by zlib and `crc32(1)`, resumable by passing the
+/// previous result back as `crc` (0 to start).
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+	let mut crc = !crc;
+	for byte in data {
+		crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+	}
+	!crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+	crc32_update(0, data)
+}
+
+#[allow(dead_code)]
+pub fn adler32(data: &[u8]) -> u32 {
+	let (mut a, mut b) = (1u32, 0u32);
+	// 5552 bytes is the most that can be summed before b may overflow
+	for chunk in data.chunks(5552) {
+		for byte in chunk {
+			a += *byte as u32;
+			b += a;
+		}
+		a %= ADLER_MODULO;
+		b %= ADLER_MODULO;
+	}
+	b << 16 | a
+}
+
+/// Checks `data` against an expected CRC-32 and reports the outcome; data
+/// without an expected value passes.
+pub fn verify(name: &str, data: &[u8], expected: Option<usize>) -> bool {
+	let expected = match expected {
+		Some(expected) => expected as u32,
+		None => return true,
+	};
+	let actual = crc32(data);
+	if actual != expected {
+		log!(Error, "{}: crc32 mismatch, expected {:#010x} got {:#010x}, not loaded", name, expected, actual);
+		return false;
+	}
+	log!(Info, "{}: crc32 {:#010x} ok", name, actual);
+	true
+}
@@ -0,0 +1,170 @@
+use core::fmt;
+use core::mem::MaybeUninit;
+
+/// The last N values pushed, the oldest overwritten once full.
+#[derive(Clone, Copy)]
+pub struct RingBuffer<T: Copy, const N: usize> {
+	items: [MaybeUninit<T>; N],
+	/// Slot the next push goes to.
+	head: usize,
+	length: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+	pub const fn new() -> RingBuffer<T, N> {
+		RingBuffer { items: [const { MaybeUninit::uninit() }; N], head: 0, length: 0 }
+	}
+
+	pub fn len(&self) -> usize {
+		self.length
+	}
+
+	pub fn clear(&mut self) {
+		self.head = 0;
+		self.length = 0;
+	}
+
+	pub fn push(&mut self, item: T) {
+		self.items[self.head] = MaybeUninit::new(item);
+		self.head = (self.head + 1) % N;
+		self.length = (self.length + 1).min(N);
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.length == 0
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.length == N
+	}
+
+	/// Takes out the oldest item, for a buffer used as a queue.
+	pub fn pop_oldest(&mut self) -> Option<T> {
+		if self.length == 0 {
+			return None;
+		}
+		let item = unsafe { self.items[(self.head + N - self.length) % N].assume_init() };
+		self.length -= 1;
+		Some(item)
+	}
+
+	/// The `index`-th most recent item, 0 being the newest.
+	pub fn recent(&self, index: usize) -> Option<&T> {
+		if index >= self.length {
+			return None;
+		}
+		// Only the `length` slots before `head` were ever written
+		Some(unsafe { self.items[(self.head + N - 1 - index) % N].assume_init_ref() })
+	}
+
+	/// Oldest item first.
+	pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + '_ {
+		(0..self.length).rev().filter_map(move |index| self.recent(index))
+	}
+}
+
+/// Up to N values in insertion order, without a heap.
+#[derive(Clone, Copy)]
+pub struct FixedVec<T: Copy, const N: usize> {
+	items: [MaybeUninit<T>; N],
+	length: usize,
+}
+
+impl<T: Copy, const N: usize> FixedVec<T, N> {
+	pub const fn new() -> FixedVec<T, N> {
+		FixedVec { items: [const { MaybeUninit::uninit() }; N], length: 0 }
+	}
+
+	/// Hands the item back when full.
+	pub fn push(&mut self, item: T) -> Result<(), T> {
+		if self.length == N {
+			return Err(item);
+		}
+		self.items[self.length] = MaybeUninit::new(item);
+		self.length += 1;
+		Ok(())
+	}
+
+	pub fn as_slice(&self) -> &[T] {
+		// The first `length` slots were written by push
+		unsafe { core::slice::from_raw_parts(self.items.as_ptr() as *const T, self.length) }
+	}
+
+	pub fn as_mut_slice(&mut self) -> &mut [T] {
+		unsafe { core::slice::from_raw_parts_mut(self.items.as_mut_ptr() as *mut T, self.length) }
+	}
+
+	pub fn iter(&self) -> core::slice::Iter<'_, T> {
+		self.as_slice().iter()
+	}
+
+	/// Takes out the item at `index`, keeping the others in order.
+	pub fn remove(&mut self, index: usize) -> T {
+		let item = self.as_slice()[index];
+		self.items.copy_within(index + 1..self.length, index);
+		self.length -= 1;
+		item
+	}
+}
+
+/// UTF-8 text of at most N bytes. Writes past the end are cut at the last
+/// character that fits, so formatting into one never fails.
+#[derive(Clone, Copy)]
+pub struct FixedString<const N: usize> {
+	bytes: [u8; N],
+	length: usize,
+}
+
+impl<const N: usize> FixedString<N> {
+	pub const fn new() -> FixedString<N> {
+		FixedString { bytes: [0; N], length: 0 }
+	}
+
+	pub fn as_str(&self) -> &str {
+		// Only whole characters are ever appended
+		unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.length]) }
+	}
+
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.bytes[..self.length]
+	}
+
+	/// False when the character did not fit.
+	pub fn push(&mut self, c: char) -> bool {
+		let mut encoded = [0; 4];
+		self.push_str(c.encode_utf8(&mut encoded))
+	}
+
+	/// Appends what fits, false when `s` was cut.
+	pub fn push_str(&mut self, s: &str) -> bool {
+		let mut count = s.len().min(N - self.length);
+		while !s.is_char_boundary(count) {
+			count -= 1;
+		}
+		self.bytes[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+		self.length += count;
+		count == s.len()
+	}
+}
+
+impl<const N: usize> From<&str> for FixedString<N> {
+	/// Cut like any other write.
+	fn from(s: &str) -> FixedString<N> {
+		let mut string = FixedString::new();
+		string.push_str(s);
+		string
+	}
+}
+
+impl<const N: usize> fmt::Write for FixedString<N> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		self.push_str(s);
+		Ok(())
+	}
+}
+
+impl<const N: usize> PartialEq for FixedString<N> {
+	fn eq(&self, other: &FixedString<N>) -> bool {
+		self.as_str() == other.as_str()
+	}
+}
@@ -0,0 +1,90 @@
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use crate::error::{ KernelError, KernelResult };
+
+pub struct Setting {
+	pub name: &'static str,
+	pub description: &'static str,
+	value: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(usize)]
+pub enum Key {
+	VerboseIrq,
+	NullGuard,
+	Bell,
+	IrqBudget,
+	PanicCoredump,
+	PromptBell,
+	ClockSource,
+	BlankMinutes,
+	TimeSlice,
+}
+
+pub static SETTINGS: [Setting; 9] = [
+	Setting {
+		name: "verbose_irq",
+		description: "log every keyboard interrupt (needs the verbose-irq feature)",
+		value: AtomicUsize::new(cfg!(feature = "verbose-irq") as usize),
+	},
+	Setting {
+		name: "null_guard",
+		description: "bytes from 0 reported as NULL dereferences",
+		value: AtomicUsize::new(0x1000),
+	},
+	Setting {
+		name: "bell",
+		description: "BEL: 0 none, 1 beep, 2 flash, 3 both",
+		value: AtomicUsize::new(crate::bell::BELL_BEEP),
+	},
+	Setting {
+		name: "irq_budget",
+		description: "us an IRQ handler or cli section may take before a warning (0 off)",
+		value: AtomicUsize::new(5000),
+	},
+	Setting {
+		name: "panic_coredump",
+		description: "stream a core dump on COM2 when the kernel panics",
+		value: AtomicUsize::new(0),
+	},
+	Setting {
+		name: "prompt_bell",
+		description: "ring the bell on edits the prompt cannot make",
+		value: AtomicUsize::new(1),
+	},
+	Setting {
+		name: "clocksource",
+		description: "timer interrupts and clock: 0 PIT, 1 HPET when ACPI lists one",
+		value: AtomicUsize::new(crate::timer::CLOCK_HPET),
+	},
+	Setting {
+		name: "blank_minutes",
+		description: "minutes without a key before the screen is blanked (0 never)",
+		value: AtomicUsize::new(10),
+	},
+	Setting {
+		name: "time_slice",
+		description: "timer ticks a task runs before the next is switched in (0 cooperative)",
+		value: AtomicUsize::new(20),
+	},
+];
+
+pub fn get(key: Key) -> usize {
+	SETTINGS[key as usize].value.load(Ordering::SeqCst)
+}
+
+pub fn set(name: &str, value: usize) -> KernelResult<()> {
+	let index = SETTINGS.iter().position(|setting| setting.name == name).ok_or(KernelError::NotFound)?;
+	SETTINGS[index].value.store(value, Ordering::SeqCst);
+	// The clock source is switched now rather than read on the next use
+	if index == Key::ClockSource as usize {
+		crate::timer::select();
+	}
+	Ok(())
+}
+
+pub fn print() {
+	for setting in SETTINGS.iter() {
+		println!("{:16} = {:<6} {}", setting.name, setting.value.load(Ordering::SeqCst), setting.description);
+	}
+}
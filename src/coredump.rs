@@ -0,0 +1,120 @@
+use core::arch::asm;
+use crate::frame::{ Channel, Frame };
+
+const ELF_HEADER_SIZE: usize = 52;
+const PROGRAM_HEADER_SIZE: usize = 32;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RW: u32 = 6;
+const NT_PRSTATUS: u32 = 1;
+/// `struct elf_prstatus` of Linux i386, which GDB expects in a core note.
+const PRSTATUS_SIZE: usize = 144;
+const PRSTATUS_REGISTERS: usize = 72;
+const NOTE_SIZE: usize = 12 + 8 + PRSTATUS_SIZE;
+
+/// Selectors gdt::load_segment_registers sets.
+const KERNEL_CODE: u32 = 0x08;
+const KERNEL_DATA: u32 = 0x10;
+const KERNEL_STACK: u32 = 0x18;
+
+const VGA_TEXT: (usize, usize) = (0xb8000, 0xb8000 + 80 * 25 * 2);
+/// IDT and GDT, linked below 1M.
+const DESCRIPTOR_TABLES: (usize, usize) = (0, 0x1000);
+
+/// Memory is identity mapped, so each region is both the address GDB sees
+/// and where it is read from.
+fn regions() -> [(usize, usize); 3] {
+	[DESCRIPTOR_TABLES, crate::librs::kernel_image(), VGA_TEXT]
+}
+
+/// Streams an ELF32 core of the descriptor tables, the kernel image (its
+/// .bss holds the boot stack) and the text screen as one CrashDump frame
+/// on COM2. The note carries eip, esp and ebp of the caller, enough for
+/// `gdb kernel.bin core` to print a backtrace.
+pub fn stream() -> usize {
+	let regions = regions();
+	let headers = ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * (regions.len() + 1);
+	let data: usize = regions.iter().map(|(start, end)| end - start).sum();
+	let total = headers + NOTE_SIZE + data;
+	let mut frame = Frame::begin(Channel::CrashDump, total);
+
+	let mut header = [0u8; ELF_HEADER_SIZE];
+	header[..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+	put16(&mut header, 16, 4); // ET_CORE
+	put16(&mut header, 18, 3); // EM_386
+	put32(&mut header, 20, 1);
+	put32(&mut header, 28, ELF_HEADER_SIZE as u32);
+	put16(&mut header, 40, ELF_HEADER_SIZE as u16);
+	put16(&mut header, 42, PROGRAM_HEADER_SIZE as u16);
+	put16(&mut header, 44, (regions.len() + 1) as u16);
+	frame.write(&header);
+
+	let mut offset = headers;
+	frame.write(&program_header(PT_NOTE, offset, 0, NOTE_SIZE, 0));
+	offset += NOTE_SIZE;
+	for &(start, end) in regions.iter() {
+		frame.write(&program_header(PT_LOAD, offset, start, end - start, PF_RW));
+		offset += end - start;
+	}
+
+	frame.write(&note());
+	for &(start, end) in regions.iter() {
+		let mut chunk = [0u8; 64];
+		for address in (start..end).step_by(chunk.len()) {
+			let length = chunk.len().min(end - address);
+			for (index, byte) in chunk[..length].iter_mut().enumerate() {
+				// Address 0 is valid memory here, but not as a Rust slice
+				*byte = unsafe { core::ptr::read_volatile((address + index) as *const u8) };
+			}
+			frame.write(&chunk[..length]);
+		}
+	}
+	frame.end();
+	total
+}
+
+fn program_header(kind: u32, offset: usize, address: usize, size: usize, flags: u32) -> [u8; PROGRAM_HEADER_SIZE] {
+	let mut header = [0u8; PROGRAM_HEADER_SIZE];
+	put32(&mut header, 0, kind);
+	put32(&mut header, 4, offset as u32);
+	put32(&mut header, 8, address as u32);
+	put32(&mut header, 12, address as u32);
+	put32(&mut header, 16, size as u32);
+	put32(&mut header, 20, size as u32);
+	put32(&mut header, 24, flags);
+	put32(&mut header, 28, 1);
+	header
+}
+
+/// NT_PRSTATUS with the registers GDB unwinds from. pr_reg follows the
+/// user_regs_struct order: ebx ecx edx esi edi ebp eax ds es fs gs
+/// orig_eax eip cs eflags esp ss.
+fn note() -> [u8; NOTE_SIZE] {
+	let (eip, esp, ebp): (u32, u32, u32);
+	unsafe {
+		asm!("call 2f", "2:", "pop {}", "mov {}, esp", "mov {}, ebp", out(reg) eip, out(reg) esp, out(reg) ebp, options(preserves_flags));
+	}
+	let mut note = [0u8; NOTE_SIZE];
+	put32(&mut note, 0, 5);
+	put32(&mut note, 4, PRSTATUS_SIZE as u32);
+	put32(&mut note, 8, NT_PRSTATUS);
+	note[12..17].copy_from_slice(b"CORE\0");
+	let registers = 20 + PRSTATUS_REGISTERS;
+	put32(&mut note, registers + 5 * 4, ebp);
+	for segment in 7..11 {
+		put32(&mut note, registers + segment * 4, KERNEL_DATA);
+	}
+	put32(&mut note, registers + 12 * 4, eip);
+	put32(&mut note, registers + 13 * 4, KERNEL_CODE);
+	put32(&mut note, registers + 15 * 4, esp);
+	put32(&mut note, registers + 16 * 4, KERNEL_STACK);
+	note
+}
+
+fn put16(buffer: &mut [u8], offset: usize, value: u16) {
+	buffer[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut [u8], offset: usize, value: u32) {
+	buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
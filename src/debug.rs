@@ -1,51 +1,224 @@
+use crate::collections::RingBuffer;
+use crate::interrupts::InterruptGuard;
 use crate::io::{inb, outb};
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::sync::Mutex;
 
-const SERIAL_PORT: u16 = 0x3f8;
+pub const COM1: u16 = 0x3f8;
+pub const COM2: u16 = 0x2f8;
+
+/// Bytes the 16550 takes at once when its transmitter is empty.
+const FIFO_SIZE: usize = 16;
+const TRANSMIT_CAPACITY: usize = 4096;
+
+/// Bytes waiting for COM1, sent FIFO_SIZE at a time from its transmit
+/// interrupt. Only touched with interrupts off, the handler included.
+static TRANSMIT: Mutex<RingBuffer<u8, TRANSMIT_CAPACITY>> = Mutex::new(RingBuffer::new());
 
 lazy_static! {
-	pub static ref DEBUG: Mutex<Debug> = Mutex::new(Debug);
+	pub static ref DEBUG: Mutex<Debug> = Mutex::new(Debug::new(COM1));
+}
+
+/// How a serial port treats the bytes going through it. The defaults suit a
+/// terminal emulator; `raw` hands every byte over untouched, as a debugger
+/// protocol sharing the port would need.
+#[derive(Debug, Clone, Copy)]
+pub struct LineDiscipline {
+	pub echo: bool,
+	pub crlf: bool,
+	pub raw: bool,
 }
 
-pub struct Debug;
+pub struct Debug {
+	port: u16,
+	pub discipline: LineDiscipline,
+	/// Writes are queued for the transmit interrupt instead of waiting on
+	/// the port. Only DEBUG gets it, once its interrupt is enabled.
+	buffered: bool,
+}
 
 impl Debug {
+	pub const fn new(port: u16) -> Debug {
+		Debug {
+			port,
+			discipline: LineDiscipline {
+				echo: true,
+				crlf: true,
+				raw: false,
+			},
+			buffered: false,
+		}
+	}
+
 	fn is_transmit_empty(&self) -> bool {
-		unsafe { (inb(SERIAL_PORT + 5) & 0x20) != 0 }
+		unsafe { (inb(self.port + 5) & 0x20) != 0 }
 	}
 
-	fn write_byte_serial(&self, byte: u8) {
+	pub fn write_byte_serial(&self, byte: u8) {
+		if self.buffered {
+			return self.queue_byte(byte);
+		}
 		while !self.is_transmit_empty() {}
 		unsafe {
-			outb(SERIAL_PORT, byte);
+			outb(self.port, byte);
+		}
+	}
+
+	/// Nothing drains the queue while interrupts are off, so a full queue is
+	/// emptied by hand first.
+	fn queue_byte(&self, byte: u8) {
+		let _guard = InterruptGuard::new();
+		let mut queue = TRANSMIT.lock();
+		while queue.is_full() {
+			while !self.is_transmit_empty() {}
+			self.fill_fifo(&mut queue);
+		}
+		queue.push(byte);
+		// The interrupt only comes when the FIFO empties, so an idle
+		// transmitter needs the first batch
+		if self.is_transmit_empty() {
+			self.fill_fifo(&mut queue);
+		}
+	}
+
+	/// Call with the transmitter empty.
+	fn fill_fifo(&self, queue: &mut RingBuffer<u8, TRANSMIT_CAPACITY>) {
+		for _ in 0..FIFO_SIZE {
+			match queue.pop_oldest() {
+				Some(byte) => unsafe { outb(self.port, byte) },
+				None => break,
+			}
+		}
+	}
+
+	/// Blocks until a byte arrives; used where interrupts are off.
+	pub fn read_byte_serial(&self) -> u8 {
+		loop {
+			if let Some(byte) = self.try_read_byte_serial() {
+				return byte;
+			}
+		}
+	}
+
+	pub fn try_read_byte_serial(&self) -> Option<u8> {
+		if unsafe { inb(self.port + 5) } & 0x01 == 0 {
+			return None;
 		}
+		Some(unsafe { inb(self.port) })
 	}
 
 	pub fn write_string_serial(&self, s: &str) {
 		for byte in s.bytes() {
+			if byte == b'\n' && self.discipline.crlf && !self.discipline.raw {
+				self.write_byte_serial(b'\r');
+			}
 			self.write_byte_serial(byte);
 		}
 	}
+
+	/// Reads up to a line terminator (not stored) and returns its length.
+	/// CR is taken as the terminator when `crlf` is set, 0x7f and 0x08 erase
+	/// the previous byte. In raw mode a single byte is returned as is.
+	pub fn read_line(&self, line: &mut [u8]) -> usize {
+		if self.discipline.raw {
+			line[0] = self.read_byte_serial();
+			return 1;
+		}
+		let mut length = 0;
+		loop {
+			let byte = match self.read_byte_serial() {
+				b'\r' if self.discipline.crlf => b'\n',
+				byte => byte,
+			};
+			match byte {
+				b'\n' => {
+					if self.discipline.echo {
+						self.write_string_serial("\n");
+					}
+					return length;
+				}
+				0x08 | 0x7f => {
+					if length > 0 {
+						length -= 1;
+						if self.discipline.echo {
+							self.write_string_serial("\x08 \x08");
+						}
+					}
+				}
+				0x20..=0x7e if length < line.len() => {
+					line[length] = byte;
+					length += 1;
+					if self.discipline.echo {
+						self.write_byte_serial(byte);
+					}
+				}
+				_ => {}
+			}
+		}
+	}
 }
 
 impl fmt::Write for Debug {
 	fn write_str(&mut self, s: &str) -> fmt::Result {
 		self.write_string_serial(s);
-		self.write_byte_serial(b'\r');
 		Ok(())
 	}
 }
 
+/// COM1 carries the log and the debugger, COM2 the binary frames of the
+/// frame module.
 pub fn init_serial_port() {
+	init_port(DEBUG.lock().port);
+	init_port(COM2);
+}
+
+/// Switches COM1 output to the transmit queue. Needs the PIC set up.
+pub fn enable_transmit_interrupt() {
+	let mut debug = DEBUG.lock();
+	crate::interrupts::enable_irq(crate::interrupts::InterruptIndex::Com1.as_usize() - crate::interrupts::PIC_1_OFFSET as usize);
+	unsafe {
+		outb(debug.port + 1, 0x02);
+	}
+	debug.buffered = true;
+}
+
+/// Sends what is still queued for COM1, waiting on the port, before the
+/// machine stops taking interrupts for good. Gives up if the queue is
+/// locked, since the panic handler calls it.
+pub fn flush() {
+	let debug = Debug::new(COM1);
+	let _guard = InterruptGuard::new();
+	if let Some(mut queue) = TRANSMIT.try_lock() {
+		while !queue.is_empty() {
+			while !debug.is_transmit_empty() {}
+			debug.fill_fifo(&mut queue);
+		}
+	}
+}
+
+/// Called from the COM1 interrupt: reading IIR acknowledges it, then the
+/// next batch goes out if the FIFO has room for it.
+pub fn transmit_interrupt() {
+	let debug = Debug::new(COM1);
+	unsafe {
+		inb(COM1 + 2);
+	}
+	if debug.is_transmit_empty() {
+		if let Some(mut queue) = TRANSMIT.try_lock() {
+			debug.fill_fifo(&mut queue);
+		}
+	}
+}
+
+fn init_port(port: u16) {
 	unsafe {
-		outb(SERIAL_PORT + 1, 0x00);
-		outb(SERIAL_PORT + 3, 0x80);
-		outb(SERIAL_PORT + 0, 0x03);
-		outb(SERIAL_PORT + 1, 0x00);
-		outb(SERIAL_PORT + 3, 0x03);
-		outb(SERIAL_PORT + 2, 0xc7);
-		outb(SERIAL_PORT + 4, 0x0b);
+		outb(port + 1, 0x00);
+		outb(port + 3, 0x80);
+		outb(port + 0, 0x03);
+		outb(port + 1, 0x00);
+		outb(port + 3, 0x03);
+		outb(port + 2, 0xc7);
+		outb(port + 4, 0x0b);
 	}
 }
@@ -0,0 +1,226 @@
+use core::arch::asm;
+use core::fmt::Write;
+use crate::args::Args;
+use crate::debug::{ Debug, COM1 };
+use crate::error::{ KernelError, KernelResult };
+use crate::interrupts::{ InterruptStackFrame, SavedRegisters };
+use crate::sync::Mutex;
+
+const TRAP_FLAG: u32 = 1 << 8;
+const RESUME_FLAG: u32 = 1 << 16;
+const DR6_SINGLE_STEP: u32 = 1 << 14;
+const DR6_WATCHPOINTS: u32 = 0xf;
+const WATCHPOINTS: usize = 4;
+const LINE_LENGTH: usize = 80;
+const DEFAULT_DUMP_WORDS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WatchKind {
+	Execute = 0b00,
+	Write = 0b01,
+	ReadWrite = 0b11,
+}
+
+impl WatchKind {
+	pub fn parse(name: &str) -> Option<WatchKind> {
+		match name {
+			"x" => Some(WatchKind::Execute),
+			"w" => Some(WatchKind::Write),
+			"rw" => Some(WatchKind::ReadWrite),
+			_ => None,
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			WatchKind::Execute => "x",
+			WatchKind::Write => "w",
+			WatchKind::ReadWrite => "rw",
+		}
+	}
+}
+
+/// Addresses programmed into DR0-DR3, by slot.
+static WATCHES: Mutex<[Option<(usize, WatchKind)>; WATCHPOINTS]> = Mutex::new([None; WATCHPOINTS]);
+
+/// Registers as they were when `step` resumed execution, to report what
+/// the stepped instruction changed.
+static LAST_STEP: Mutex<Option<SavedRegisters>> = Mutex::new(None);
+
+/// Serial console entered from int3 and from single-step traps. Interrupts
+/// are off the whole time, so input is polled and the `DEBUG` lock is
+/// bypassed in case the breakpoint hit while it was held.
+pub fn enter(reason: &str, frame: &mut InterruptStackFrame) {
+	let mut serial = Debug::new(COM1);
+	println_async!("{} at {:#x}, debugger waiting on serial", reason, frame.instruction_pointer);
+	let _ = writeln!(serial, "\n{} at {:#010x}", reason, frame.instruction_pointer);
+	command_loop(&mut serial, frame);
+}
+
+/// Debug exception raised by the trap flag: shows where the step landed
+/// and which registers moved, then goes back to the prompt.
+fn single_step(frame: &mut InterruptStackFrame) {
+	let mut serial = Debug::new(COM1);
+	let _ = writeln!(serial, "step: eip={:#010x}", frame.instruction_pointer);
+	if let Some(before) = LAST_STEP.lock().take() {
+		let after = frame.registers();
+		for ((name, old), (_, new)) in named(&before).iter().zip(named(&after).iter()) {
+			if old != new {
+				let _ = writeln!(serial, "  {} {:#010x} -> {:#010x}", name, old, new);
+			}
+		}
+	}
+	command_loop(&mut serial, frame);
+}
+
+fn command_loop(serial: &mut Debug, frame: &mut InterruptStackFrame) {
+	let mut line = [0u8; LINE_LENGTH];
+	loop {
+		serial.write_string_serial("kdb> ");
+		let length = serial.read_line(&mut line);
+		let args = Args::new(core::str::from_utf8(&line[..length]).unwrap_or(""));
+		match args.name() {
+			"" => {}
+			"r" | "regs" => print_registers(serial, frame),
+			"x" | "dump" => match (args.get_int(1), args.opt(2).map(|_| args.get_int(2))) {
+				(Ok(address), None) => dump(serial, address, DEFAULT_DUMP_WORDS),
+				(Ok(address), Some(Ok(words))) => dump(serial, address, words),
+				_ => serial.write_string_serial("usage: x <address> [words]\n"),
+			},
+			"s" | "step" => {
+				*LAST_STEP.lock() = Some(frame.registers());
+				frame.cpu_flags |= TRAP_FLAG;
+				return;
+			}
+			"c" | "continue" => {
+				*LAST_STEP.lock() = None;
+				frame.cpu_flags &= !TRAP_FLAG;
+				return;
+			}
+			"h" | "help" => serial.write_string_serial(
+				concat!(
+					"r                 show registers\n",
+					"x <addr> [words]  dump memory\n",
+					"s                 single step\n",
+					"c                 continue\n",
+				)
+			),
+			name => {
+				let _ = writeln!(serial, "{}: unknown command, try 'help'", name);
+			}
+		}
+	}
+}
+
+/// Entry point of the debug exception: tells a single step apart from a
+/// watchpoint hit by DR6, which is cleared since the CPU never resets its
+/// status bits itself. Returns false when neither caused the exception.
+pub fn handle_debug_exception(frame: &mut InterruptStackFrame) -> bool {
+	let status: u32;
+	unsafe {
+		asm!("mov {}, dr6", out(reg) status, options(nomem, nostack, preserves_flags));
+		asm!("mov dr6, {}", in(reg) 0u32, options(nomem, nostack, preserves_flags));
+	}
+
+	if status & DR6_WATCHPOINTS != 0 {
+		let watches = *WATCHES.lock();
+		for (slot, watch) in watches.iter().enumerate() {
+			if let (true, Some((address, kind))) = (status & (1 << slot) != 0, watch) {
+				log!(Warning, "watchpoint {} ({}) on {:#010x} hit, eip={:#010x}", slot, kind.name(), address, frame.instruction_pointer);
+				if *kind == WatchKind::Execute {
+					// Instruction breakpoints are faults: skip the check once on return
+					frame.cpu_flags |= RESUME_FLAG;
+				}
+			}
+		}
+	}
+	if status & DR6_SINGLE_STEP != 0 {
+		single_step(frame);
+	}
+	status & (DR6_WATCHPOINTS | DR6_SINGLE_STEP) != 0
+}
+
+/// Programs the first free debug register; data watchpoints cover the
+/// aligned dword holding `address`. Returns the slot used.
+pub fn set_watchpoint(address: usize, kind: WatchKind) -> KernelResult<usize> {
+	let mut watches = WATCHES.lock();
+	let slot = watches.iter().position(|watch| watch.is_none()).ok_or(KernelError::Busy)?;
+	let address = if kind == WatchKind::Execute { address } else { address & !0x3 };
+	watches[slot] = Some((address, kind));
+	write_address(slot, address);
+	write_control(&watches);
+	Ok(slot)
+}
+
+pub fn clear_watchpoint(slot: usize) -> KernelResult<()> {
+	let mut watches = WATCHES.lock();
+	if slot >= WATCHPOINTS || watches[slot].take().is_none() {
+		return Err(KernelError::Invalid);
+	}
+	write_control(&watches);
+	Ok(())
+}
+
+pub fn watchpoints() -> [Option<(usize, WatchKind)>; WATCHPOINTS] {
+	*WATCHES.lock()
+}
+
+fn write_address(slot: usize, address: usize) {
+	unsafe {
+		match slot {
+			0 => asm!("mov dr0, {}", in(reg) address, options(nomem, nostack, preserves_flags)),
+			1 => asm!("mov dr1, {}", in(reg) address, options(nomem, nostack, preserves_flags)),
+			2 => asm!("mov dr2, {}", in(reg) address, options(nomem, nostack, preserves_flags)),
+			_ => asm!("mov dr3, {}", in(reg) address, options(nomem, nostack, preserves_flags)),
+		}
+	}
+}
+
+/// DR7: local enable bit 2n, then R/W at 16+4n and LEN at 18+4n (dword for
+/// data, byte for instructions).
+fn write_control(watches: &[Option<(usize, WatchKind)>; WATCHPOINTS]) {
+	let mut control: usize = 0;
+	for (slot, watch) in watches.iter().enumerate() {
+		if let Some((_, kind)) = watch {
+			let length = if *kind == WatchKind::Execute { 0b00 } else { 0b11 };
+			control |= 1 << (slot * 2);
+			control |= (*kind as usize | length << 2) << (16 + slot * 4);
+		}
+	}
+	unsafe {
+		asm!("mov dr7, {}", in(reg) control, options(nomem, nostack, preserves_flags));
+	}
+}
+
+fn named(registers: &SavedRegisters) -> [(&'static str, u32); 7] {
+	[
+		("eax", registers.eax),
+		("ebx", registers.ebx),
+		("ecx", registers.ecx),
+		("edx", registers.edx),
+		("esi", registers.esi),
+		("edi", registers.edi),
+		("ebp", registers.ebp),
+	]
+}
+
+fn print_registers(serial: &mut Debug, frame: &InterruptStackFrame) {
+	let registers = frame.registers();
+	let _ = writeln!(serial, "eip={:#010x} cs={:#06x} eflags={:#010x}", frame.instruction_pointer, frame.code_segment, frame.cpu_flags);
+	let _ = writeln!(serial, "eax={:#010x} ebx={:#010x} ecx={:#010x} edx={:#010x}", registers.eax, registers.ebx, registers.ecx, registers.edx);
+	let _ = writeln!(serial, "esi={:#010x} edi={:#010x} ebp={:#010x} esp={:#010x}", registers.esi, registers.edi, registers.ebp, registers.esp);
+}
+
+fn dump(serial: &mut Debug, address: usize, words: usize) {
+	let address = address & !0x3;
+	for line in 0..words.div_ceil(4) {
+		let start = address + line * 16;
+		let _ = write!(serial, "{:#010x}:", start);
+		for word in 0..4.min(words - line * 4) {
+			let value = unsafe { core::ptr::read_volatile((start + word * 4) as *const u32) };
+			let _ = write!(serial, " {:08x}", value);
+		}
+		serial.write_string_serial("\n");
+	}
+}
@@ -0,0 +1,112 @@
+use core::arch::asm;
+use crate::sync::Mutex;
+use crate::tty::{ self, Tty };
+use crate::vfs::{ DirEntry, FileSystem, FsError, Node, NodeKind };
+
+struct Device {
+	name: &'static str,
+	read: fn(&mut [u8]) -> Result<usize, FsError>,
+	write: fn(&[u8]) -> Result<usize, FsError>,
+}
+
+static DEVICES: [Device; 6] = [
+	Device { name: "console", read: |buffer| tty::read(&tty::CONSOLE, buffer), write: |buffer| write_tty(&tty::CONSOLE, buffer) },
+	Device { name: "ttyS0", read: |buffer| tty::read(&tty::SERIAL, buffer), write: |buffer| write_tty(&tty::SERIAL, buffer) },
+	Device { name: "keyboard", read: read_keyboard, write: write_read_only },
+	Device { name: "null", read: read_nothing, write: |buffer| Ok(buffer.len()) },
+	Device { name: "zero", read: read_zero, write: |buffer| Ok(buffer.len()) },
+	Device { name: "random", read: read_random, write: write_read_only },
+];
+
+const ROOT_INODE: usize = usize::MAX;
+
+/// Kernel devices as files, inodes being indexes in DEVICES.
+pub struct DevFs;
+
+pub static DEVFS: DevFs = DevFs;
+
+fn device_node(inode: usize) -> Node {
+	Node { inode, kind: NodeKind::Device, size: 0 }
+}
+
+impl FileSystem for DevFs {
+	fn name(&self) -> &'static str {
+		"devfs"
+	}
+
+	fn lookup(&self, path: &str) -> Result<Node, FsError> {
+		if path.is_empty() {
+			return Ok(Node { inode: ROOT_INODE, kind: NodeKind::Directory, size: 0 });
+		}
+		DEVICES
+			.iter()
+			.position(|device| device.name == path)
+			.map(device_node)
+			.ok_or(FsError::NotFound)
+	}
+
+	fn read(&self, node: Node, _offset: usize, buffer: &mut [u8]) -> Result<usize, FsError> {
+		(DEVICES.get(node.inode).ok_or(FsError::NotFound)?.read)(buffer)
+	}
+
+	fn write(&self, node: Node, _offset: usize, buffer: &[u8]) -> Result<usize, FsError> {
+		(DEVICES.get(node.inode).ok_or(FsError::NotFound)?.write)(buffer)
+	}
+
+	fn read_dir(&self, node: Node, index: usize) -> Result<Option<DirEntry>, FsError> {
+		if node.inode != ROOT_INODE {
+			return Err(FsError::NotADirectory);
+		}
+		match DEVICES.get(index) {
+			Some(device) => Ok(Some(DirEntry::new(device.name, device_node(index))?)),
+			None => Ok(None),
+		}
+	}
+}
+
+fn read_nothing(_buffer: &mut [u8]) -> Result<usize, FsError> {
+	Ok(0)
+}
+
+fn write_read_only(_buffer: &[u8]) -> Result<usize, FsError> {
+	Err(FsError::ReadOnly)
+}
+
+/// The console goes straight to the screen, even while the shell
+/// redirects its output.
+fn write_tty(tty: &Mutex<Tty>, buffer: &[u8]) -> Result<usize, FsError> {
+	tty.lock().write(buffer);
+	Ok(buffer.len())
+}
+
+/// Raw scancodes, taken from the queue the shell reads from.
+fn read_keyboard(buffer: &mut [u8]) -> Result<usize, FsError> {
+	Ok(crate::keyboard::read_scancodes(buffer))
+}
+
+fn read_zero(buffer: &mut [u8]) -> Result<usize, FsError> {
+	buffer.fill(0);
+	Ok(buffer.len())
+}
+
+/// xorshift32, seeded from the time stamp counter on first use. Not fit for
+/// anything needing real entropy.
+static RANDOM_STATE: Mutex<u32> = Mutex::new(0);
+
+fn read_random(buffer: &mut [u8]) -> Result<usize, FsError> {
+	let mut state = RANDOM_STATE.lock();
+	if *state == 0 {
+		let (low, high): (u32, u32);
+		unsafe {
+			asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+		}
+		*state = (low ^ high) | 1;
+	}
+	for byte in buffer.iter_mut() {
+		*state ^= *state << 13;
+		*state ^= *state >> 17;
+		*state ^= *state << 5;
+		*byte = *state as u8;
+	}
+	Ok(buffer.len())
+}
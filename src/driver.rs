@@ -0,0 +1,113 @@
+use core::fmt;
+use crate::sync::Mutex;
+use crate::collections::{ FixedString, FixedVec };
+use crate::error::{ KernelError, KernelResult };
+use crate::tsc;
+
+const MAX_DRIVERS: usize = 16;
+
+/// Power management callbacks of a driver. Suspend and shutdown run for the
+/// drivers that depend on others first, resume in the opposite order.
+#[derive(Clone, Copy)]
+pub struct Driver {
+	pub name: &'static str,
+	/// Names of drivers this one relies on; those not registered are ignored.
+	pub depends_on: &'static [&'static str],
+	pub suspend: Option<fn()>,
+	pub resume: Option<fn()>,
+	pub shutdown: Option<fn()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hook {
+	Suspend,
+	Resume,
+	Shutdown,
+}
+
+static DRIVERS: Mutex<FixedVec<Driver, MAX_DRIVERS>> = Mutex::new(FixedVec::new());
+
+pub fn register(driver: Driver) -> KernelResult<()> {
+	let mut drivers = DRIVERS.lock();
+	if drivers.iter().any(|registered| registered.name == driver.name) {
+		return Err(KernelError::Busy);
+	}
+	drivers.push(driver).map_err(|_| KernelError::NoMemory)
+}
+
+/// Drivers with what they depend on before them. When only a cycle is
+/// left, its first driver in registration order is taken as it is.
+fn dependency_order(drivers: &[Driver]) -> FixedVec<Driver, MAX_DRIVERS> {
+	let mut ordered: FixedVec<Driver, MAX_DRIVERS> = FixedVec::new();
+	let placed = |ordered: &FixedVec<Driver, MAX_DRIVERS>, name: &str| ordered.iter().any(|driver| driver.name == name);
+	let registered = |name: &str| drivers.iter().any(|driver| driver.name == name);
+	while ordered.as_slice().len() < drivers.len() {
+		let mut left = drivers.iter().filter(|driver| !placed(&ordered, driver.name));
+		let ready = left.clone().find(|driver| driver.depends_on.iter().all(|&name| placed(&ordered, name) || !registered(name)));
+		let next = match ready {
+			Some(&driver) => driver,
+			None => {
+				let driver = *left.next().unwrap_or(&drivers[0]);
+				log!(Warning, "driver: {} is in a dependency cycle", driver.name);
+				driver
+			}
+		};
+		let _ = ordered.push(next);
+	}
+	ordered
+}
+
+fn run(hook: Hook) {
+	// Copied out so a hook can log, or register a driver, without the lock
+	let drivers = *DRIVERS.lock();
+	let mut ordered = dependency_order(drivers.as_slice());
+	if hook != Hook::Resume {
+		ordered.as_mut_slice().reverse();
+	}
+	let khz = tsc::cached_frequency_khz().max(1) as u64;
+	for driver in ordered.iter() {
+		let callback = match hook {
+			Hook::Suspend => driver.suspend,
+			Hook::Resume => driver.resume,
+			Hook::Shutdown => driver.shutdown,
+		};
+		if let Some(callback) = callback {
+			let start = tsc::read();
+			callback();
+			log!(Debug, "driver: {} {:?} took {} us", driver.name, hook, (tsc::read() - start) * 1000 / khz);
+		}
+	}
+}
+
+pub fn suspend_all() {
+	run(Hook::Suspend);
+}
+
+pub fn resume_all() {
+	run(Hook::Resume);
+}
+
+/// Called before a reboot or power off.
+pub fn shutdown_all() {
+	run(Hook::Shutdown);
+}
+
+/// /proc/drivers: registered drivers in resume order, with their hooks.
+pub fn write_drivers(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let drivers = *DRIVERS.lock();
+	writeln!(writer, "NAME        HOOKS                     DEPENDS ON")?;
+	for driver in dependency_order(drivers.as_slice()).iter() {
+		let mut hooks = FixedString::<32>::new();
+		for (present, name) in [(driver.suspend.is_some(), "suspend "), (driver.resume.is_some(), "resume "), (driver.shutdown.is_some(), "shutdown ")] {
+			if present {
+				hooks.push_str(name);
+			}
+		}
+		write!(writer, "{:10}  {:24}  ", driver.name, hooks.as_str())?;
+		for name in driver.depends_on {
+			write!(writer, "{} ", name)?;
+		}
+		writeln!(writer)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::block::BlockError;
+use crate::vfs::FsError;
+
+/// Error returned by the kernel's public APIs. Subsystems keep their own
+/// detailed error types and convert into this one at their boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum KernelError {
+	NoMemory,
+	Io,
+	NotFound,
+	Busy,
+	Fault,
+	Invalid,
+	TimedOut,
+	Unsupported,
+	Fs(FsError),
+}
+
+pub type KernelResult<T> = Result<T, KernelError>;
+
+impl KernelError {
+	/// Linux i386 errno, returned negated by syscalls.
+	pub fn errno(self) -> i32 {
+		match self {
+			KernelError::NotFound => 2,
+			KernelError::Io => 5,
+			KernelError::NoMemory => 12,
+			KernelError::Fault => 14,
+			KernelError::Busy => 16,
+			KernelError::Invalid => 22,
+			KernelError::Unsupported => 38,
+			KernelError::TimedOut => 110,
+			KernelError::Fs(error) => error.errno(),
+		}
+	}
+
+	pub fn as_str(self) -> &'static str {
+		match self {
+			KernelError::NotFound => "not found",
+			KernelError::Io => "input/output error",
+			KernelError::NoMemory => "out of memory",
+			KernelError::Fault => "bad address",
+			KernelError::Busy => "device or resource busy",
+			KernelError::Invalid => "invalid argument",
+			KernelError::Unsupported => "not supported",
+			KernelError::TimedOut => "timed out",
+			KernelError::Fs(error) => error.as_str(),
+		}
+	}
+}
+
+impl From<FsError> for KernelError {
+	fn from(error: FsError) -> KernelError {
+		KernelError::Fs(error)
+	}
+}
+
+impl From<BlockError> for KernelError {
+	fn from(error: BlockError) -> KernelError {
+		match error {
+			BlockError::OutOfRange(_) => KernelError::Io,
+		}
+	}
+}
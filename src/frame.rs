@@ -0,0 +1,74 @@
+use crate::sync::Mutex;
+use crate::checksum::crc32_update;
+use crate::debug::{ Debug, COM2 };
+use crate::interrupts;
+
+/// Frames on COM2: FRAME_START, then escaped channel (1 byte), payload
+/// length (u32 LE), payload and CRC-32 of channel, length and payload
+/// (u32 LE). FRAME_START and FRAME_ESCAPE inside a frame are sent as
+/// FRAME_ESCAPE followed by the byte xor ESCAPE_XOR, so a reader can
+/// always resynchronise on the next FRAME_START.
+const FRAME_START: u8 = 0x7e;
+const FRAME_ESCAPE: u8 = 0x7d;
+const ESCAPE_XOR: u8 = 0x20;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum Channel {
+	Trace = 1,
+	CrashDump = 2,
+	Gdb = 3,
+}
+
+static PORT: Mutex<Debug> = Mutex::new(Debug::new(COM2));
+
+/// A frame being sent: the length is announced up front so the payload can
+/// be streamed in pieces without a buffer.
+pub struct Frame {
+	remaining: usize,
+	crc: u32,
+}
+
+impl Frame {
+	pub fn begin(channel: Channel, length: usize) -> Frame {
+		transmit(&[FRAME_START], false);
+		let mut frame = Frame { remaining: length + 5, crc: 0 };
+		frame.write(&[channel as u8]);
+		frame.write(&(length as u32).to_le_bytes());
+		frame
+	}
+
+	pub fn write(&mut self, bytes: &[u8]) {
+		let bytes = &bytes[..bytes.len().min(self.remaining)];
+		self.remaining -= bytes.len();
+		self.crc = crc32_update(self.crc, bytes);
+		write_escaped(bytes);
+	}
+
+	/// Pads a payload that came out shorter than announced with zeros,
+	/// then sends the CRC.
+	pub fn end(mut self) {
+		while self.remaining > 0 {
+			self.write(&[0]);
+		}
+		write_escaped(&self.crc.to_le_bytes());
+	}
+}
+
+fn write_escaped(bytes: &[u8]) {
+	transmit(bytes, true);
+}
+
+fn transmit(bytes: &[u8], escape: bool) {
+	let _guard = interrupts::InterruptGuard::new();
+	let port = PORT.lock();
+	for &byte in bytes {
+		if escape && (byte == FRAME_START || byte == FRAME_ESCAPE) {
+			port.write_byte_serial(FRAME_ESCAPE);
+			port.write_byte_serial(byte ^ ESCAPE_XOR);
+		} else {
+			port.write_byte_serial(byte);
+		}
+	}
+}
@@ -43,6 +43,9 @@ struct GdtRegister {
 	offset: u32,
 }
 
+const _: () = assert!(core::mem::size_of::<GdtEntry>() == 8);
+const _: () = assert!(core::mem::size_of::<GdtRegister>() == 6);
+
 unsafe fn load_gdt() {
 	let gdt_register = GdtRegister {
 		size: (core::mem::size_of_val(&*GDT) - 1) as u16,
@@ -76,3 +79,31 @@ pub fn init() {
 		load_segment_registers();
 	}
 }
+
+/// Base and limit the CPU holds in GDTR.
+pub fn read_register() -> (u32, u16) {
+	let mut register = GdtRegister { size: 0, offset: 0 };
+	unsafe {
+		asm!("sgdt [{}]", in(reg) &mut register, options(nostack, preserves_flags));
+	}
+	(register.offset, register.size)
+}
+
+/// Reads GDTR and the segment registers back after `init` and panics if
+/// they are not what was loaded.
+pub fn validate() {
+	let expected = (GDT.as_ptr() as u32, (core::mem::size_of_val(&*GDT) - 1) as u16);
+	let (base, limit) = read_register();
+	if (base, limit) != expected {
+		panic!("gdt: gdtr is {:#010x}/{:#06x}, expected {:#010x}/{:#06x}", base, limit, expected.0, expected.1);
+	}
+	let (code, data, stack): (u16, u16, u16);
+	unsafe {
+		asm!("mov {:x}, cs", out(reg) code, options(nomem, nostack, preserves_flags));
+		asm!("mov {:x}, ds", out(reg) data, options(nomem, nostack, preserves_flags));
+		asm!("mov {:x}, ss", out(reg) stack, options(nomem, nostack, preserves_flags));
+	}
+	if (code, data, stack) != (0x08, 0x10, 0x18) {
+		panic!("gdt: cs={:#06x} ds={:#06x} ss={:#06x}, expected 0x08 0x10 0x18", code, data, stack);
+	}
+}
@@ -0,0 +1,154 @@
+use core::fmt;
+use core::ptr::{ read_volatile, write_volatile };
+use crate::sync::Mutex;
+use crate::{ acpi, driver };
+
+/// Offsets into the HPET table after the common header.
+const TABLE_ADDRESS_SPACE: usize = 40;
+const TABLE_ADDRESS: usize = 44;
+const ADDRESS_SPACE_MEMORY: u8 = 0;
+
+const CAPABILITIES: usize = 0x00;
+const CONFIGURATION: usize = 0x10;
+const MAIN_COUNTER: usize = 0xf0;
+const TIMER_0_CONFIGURATION: usize = 0x100;
+const TIMER_0_COMPARATOR: usize = 0x108;
+
+const CAPABILITY_64_BIT: u32 = 1 << 13;
+const CAPABILITY_LEGACY_ROUTE: u32 = 1 << 15;
+const ENABLE: u32 = 1 << 0;
+const LEGACY_REPLACEMENT: u32 = 1 << 1;
+const TIMER_INTERRUPT_ENABLE: u32 = 1 << 2;
+const TIMER_PERIODIC: u32 = 1 << 3;
+const TIMER_PERIODIC_CAPABLE: u32 = 1 << 4;
+const TIMER_VALUE_SET: u32 = 1 << 6;
+
+/// The specification caps the period at 100 ns.
+const MAX_PERIOD_FS: u32 = 100_000_000;
+const FEMTOSECONDS_PER_NANOSECOND: u128 = 1_000_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+	/// Registers are reached at their physical address, which has to be
+	/// identity mapped like the ACPI tables.
+	pub base: usize,
+	/// Femtoseconds per main counter increment.
+	pub period_fs: u32,
+	pub timers: u32,
+	/// Timer 0 can stand in for the PIT on IRQ0: it is periodic and the
+	/// legacy replacement route exists.
+	pub can_tick: bool,
+}
+
+static HPET: Mutex<Option<Hpet>> = Mutex::new(None);
+
+pub fn hpet() -> Option<Hpet> {
+	*HPET.lock()
+}
+
+fn read(base: usize, register: usize) -> u32 {
+	unsafe { read_volatile((base + register) as *const u32) }
+}
+
+fn write(base: usize, register: usize, value: u32) {
+	unsafe { write_volatile((base + register) as *mut u32, value) }
+}
+
+/// Looks for the HPET table and starts the main counter. Only 64-bit
+/// counters are used, a 32-bit one wraps every few minutes.
+pub fn init() {
+	let table = match acpi::find_table(b"HPET") {
+		Some(table) => table,
+		None => {
+			log!(Info, "hpet: not listed by ACPI");
+			return;
+		}
+	};
+	let space = unsafe { read_volatile((table + TABLE_ADDRESS_SPACE) as *const u8) };
+	let address = unsafe { core::ptr::read_unaligned((table + TABLE_ADDRESS) as *const u64) };
+	if space != ADDRESS_SPACE_MEMORY || address == 0 || address >= 1 << 32 {
+		log!(Warning, "hpet: registers at {:#x} in address space {} cannot be reached", address, space);
+		return;
+	}
+	let base = address as usize;
+	let capabilities = read(base, CAPABILITIES);
+	let period_fs = read(base, CAPABILITIES + 4);
+	if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+		log!(Warning, "hpet: bad counter period {} fs", period_fs);
+		return;
+	}
+	if capabilities & CAPABILITY_64_BIT == 0 {
+		log!(Warning, "hpet: 32-bit main counter, keeping the PIT");
+		return;
+	}
+	let timers = (capabilities >> 8 & 0x1f) + 1;
+	let periodic = read(base, TIMER_0_CONFIGURATION) & TIMER_PERIODIC_CAPABLE != 0;
+	let can_tick = periodic && capabilities & CAPABILITY_LEGACY_ROUTE != 0;
+	write(base, CONFIGURATION, read(base, CONFIGURATION) & !(ENABLE | LEGACY_REPLACEMENT));
+	write(base, MAIN_COUNTER, 0);
+	write(base, MAIN_COUNTER + 4, 0);
+	write(base, CONFIGURATION, read(base, CONFIGURATION) | ENABLE);
+	log!(Info, "hpet: {} timers at {:#x}, {} fs period{}", timers, base, period_fs, if can_tick { "" } else { ", cannot drive IRQ0" });
+	*HPET.lock() = Some(Hpet { base, period_fs, timers, can_tick });
+	// Firmware and the next kernel expect the PIT on IRQ0
+	let driver = driver::Driver { name: "hpet", depends_on: &[], suspend: None, resume: None, shutdown: Some(stop_periodic) };
+	if let Err(error) = driver::register(driver) {
+		log!(Warning, "hpet: no power hooks: {}", error.as_str());
+	}
+}
+
+/// The main counter, reading the high half again until it holds still
+/// since the two halves are read apart.
+pub fn counter() -> Option<u64> {
+	let base = hpet()?.base;
+	loop {
+		let high = read(base, MAIN_COUNTER + 4);
+		let low = read(base, MAIN_COUNTER);
+		if read(base, MAIN_COUNTER + 4) == high {
+			return Some((high as u64) << 32 | low as u64);
+		}
+	}
+}
+
+pub fn nanoseconds() -> Option<u64> {
+	let period_fs = hpet()?.period_fs as u128;
+	Some((counter()? as u128 * period_fs / FEMTOSECONDS_PER_NANOSECOND) as u64)
+}
+
+/// Makes timer 0 fire every `period_ns` on IRQ0 in place of the PIT. The
+/// legacy replacement route also takes IRQ8 from the RTC.
+pub fn start_periodic(period_ns: u64) -> bool {
+	let hpet = match hpet() {
+		Some(hpet) if hpet.can_tick => hpet,
+		_ => return false,
+	};
+	let period = (period_ns as u128 * FEMTOSECONDS_PER_NANOSECOND / hpet.period_fs as u128) as u64;
+	let _guard = crate::interrupts::InterruptGuard::new();
+	let configuration = read(hpet.base, TIMER_0_CONFIGURATION);
+	write(hpet.base, TIMER_0_CONFIGURATION, configuration | TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC | TIMER_VALUE_SET);
+	// With the value-set bit, the first write is the first deadline and
+	// the second the period added after each interrupt
+	let first = counter().unwrap_or(0) + period;
+	write(hpet.base, TIMER_0_COMPARATOR, first as u32);
+	write(hpet.base, TIMER_0_COMPARATOR + 4, (first >> 32) as u32);
+	write(hpet.base, TIMER_0_COMPARATOR, period as u32);
+	write(hpet.base, TIMER_0_COMPARATOR + 4, (period >> 32) as u32);
+	write(hpet.base, CONFIGURATION, read(hpet.base, CONFIGURATION) | LEGACY_REPLACEMENT);
+	true
+}
+
+/// Hands IRQ0 back to the PIT. The main counter keeps running.
+pub fn stop_periodic() {
+	if let Some(hpet) = hpet() {
+		let _guard = crate::interrupts::InterruptGuard::new();
+		write(hpet.base, CONFIGURATION, read(hpet.base, CONFIGURATION) & !LEGACY_REPLACEMENT);
+		let configuration = read(hpet.base, TIMER_0_CONFIGURATION);
+		write(hpet.base, TIMER_0_CONFIGURATION, configuration & !(TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC));
+	}
+}
+
+impl fmt::Display for Hpet {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "hpet at {:#x}, {} timers, {} fs", self.base, self.timers, self.period_fs)
+	}
+}
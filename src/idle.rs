@@ -0,0 +1,67 @@
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use crate::sync::Mutex;
+use crate::interrupts::TICKS;
+use crate::{ librs, tsc };
+
+const CPUID_MONITOR: u32 = 1 << 3;
+
+struct Stats {
+	boot: u64,
+	idle: u64,
+	halts: u64,
+	mwaits: u64,
+}
+
+static STATS: Mutex<Stats> = Mutex::new(Stats { boot: 0, idle: 0, halts: 0, mwaits: 0 });
+static MWAIT: AtomicBool = AtomicBool::new(false);
+
+/// Starts the busy/idle accounting; mwait is used when the CPU has it and
+/// the command line does not say `idle=halt`.
+pub fn init(cmdline: &str) {
+	STATS.lock().boot = tsc::read();
+	let supported = librs::cpuid(1).1 & CPUID_MONITOR != 0;
+	let halt_only = crate::args::cmdline_option(cmdline, "idle") == Some("halt");
+	MWAIT.store(supported && !halt_only, Ordering::SeqCst);
+}
+
+/// Called by the main loop with interrupts enabled: sleeps until the next
+/// interrupt, counting the cycles spent asleep.
+pub fn enter() {
+	let start = tsc::read();
+	let mwait = MWAIT.load(Ordering::Relaxed);
+	trace!(IdleEnter, mwait, 0);
+	if mwait {
+		// Any interrupt ends mwait; monitoring TICKS is only for the armed range
+		unsafe {
+			asm!("monitor", in("eax") TICKS.as_ptr(), in("ecx") 0, in("edx") 0, options(nostack, preserves_flags));
+			asm!("mwait", in("eax") 0, in("ecx") 0, options(nostack, preserves_flags));
+		}
+	} else {
+		librs::hlt();
+	}
+	let slept = tsc::read() - start;
+	trace!(IdleExit, slept.min(u32::MAX as u64), 0);
+	let mut stats = STATS.lock();
+	stats.idle += slept;
+	if mwait {
+		stats.mwaits += 1;
+	} else {
+		stats.halts += 1;
+	}
+}
+
+/// Shared by `powertop` and /proc/idle.
+pub fn write_stats(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let (boot, idle, halts, mwaits) = {
+		let stats = STATS.lock();
+		(stats.boot, stats.idle, stats.halts, stats.mwaits)
+	};
+	let total = (tsc::read() - boot).max(1);
+	let idle_permille = idle * 1000 / total;
+	writeln!(writer, "idle {}.{}%  busy {}.{}%", idle_permille / 10, idle_permille % 10, (1000 - idle_permille) / 10, (1000 - idle_permille) % 10)?;
+	writeln!(writer, "C1 hlt   {} entries", halts)?;
+	writeln!(writer, "C1 mwait {} entries{}", mwaits, if MWAIT.load(Ordering::Relaxed) { "" } else { " (not in use)" })?;
+	writeln!(writer, "{} idle cycles of {}", idle, total)
+}
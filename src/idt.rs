@@ -1,7 +1,7 @@
 use core::arch::asm;
 use lazy_static::lazy_static;
-use crate::interrupts::InterruptIndex;
-use crate::interrupts::{ divide_by_zero, debug, non_maskable_interrupt, breakpoint, overflow, bound_range_exceeded, invalid_opcode, coprocessor_not_available, double_fault, coprocessor_segment_overrun, invalid_task_state_segment, segment_not_present, stack_fault, general_protection_fault, page_fault, reserved, math_fault, alignment_check, machine_check, simd_floating_point_exception, virtualization_exception, timer_interrupt, keyboard_interrupt };
+use crate::interrupts::{ InterruptIndex, SYSCALL_VECTOR, syscall };
+use crate::interrupts::{ divide_by_zero, debug, non_maskable_interrupt, breakpoint, overflow, bound_range_exceeded, invalid_opcode, coprocessor_not_available, double_fault, coprocessor_segment_overrun, invalid_task_state_segment, segment_not_present, stack_fault, general_protection_fault, page_fault, reserved, math_fault, alignment_check, machine_check, simd_floating_point_exception, virtualization_exception, timer_interrupt, keyboard_interrupt, com1_interrupt, rtc_interrupt, free1_interrupt, free2_interrupt, free3_interrupt };
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed)]
@@ -33,21 +33,27 @@ static OVERFLOW: extern "C" fn() = handler!(overflow);
 static BOUND_RANGE_EXCEEDED: extern "C" fn() = handler!(bound_range_exceeded);
 static INVALID_OPCODE: extern "C" fn() = handler!(invalid_opcode);
 static COPROCESSOR_NOT_AVAILABLE: extern "C" fn() = handler!(coprocessor_not_available);
-static DOUBLE_FAULT: extern "C" fn() = handler!(double_fault);
+static DOUBLE_FAULT: extern "C" fn() = handler!(double_fault, error_code);
 static COPROCESSOR_SEGMENT_OVERRUN: extern "C" fn() = handler!(coprocessor_segment_overrun);
-static INVALID_TASK_STATE_SEGMENT: extern "C" fn() = handler!(invalid_task_state_segment);
-static SEGMENT_NOT_PRESENT: extern "C" fn() = handler!(segment_not_present);
-static STACK_FAULT: extern "C" fn() = handler!(stack_fault);
-static GENERAL_PROTECTION_FAULT: extern "C" fn() = handler!(general_protection_fault);
-static PAGE_FAULT: extern "C" fn() = handler!(page_fault);
+static INVALID_TASK_STATE_SEGMENT: extern "C" fn() = handler!(invalid_task_state_segment, error_code);
+static SEGMENT_NOT_PRESENT: extern "C" fn() = handler!(segment_not_present, error_code);
+static STACK_FAULT: extern "C" fn() = handler!(stack_fault, error_code);
+static GENERAL_PROTECTION_FAULT: extern "C" fn() = handler!(general_protection_fault, error_code);
+static PAGE_FAULT: extern "C" fn() = handler!(page_fault, error_code);
 static RESERVED: extern "C" fn() = handler!(reserved);
 static MATH_FAULT: extern "C" fn() = handler!(math_fault);
-static ALIGNMENT_CHECK: extern "C" fn() = handler!(alignment_check);
+static ALIGNMENT_CHECK: extern "C" fn() = handler!(alignment_check, error_code);
 static MACHINE_CHECK: extern "C" fn() = handler!(machine_check);
 static SIMD_FLOATING_POINT_EXCEPTION: extern "C" fn() = handler!(simd_floating_point_exception);
 static VIRTUALIZATION_EXCEPTION: extern "C" fn() = handler!(virtualization_exception);
 static TIMER_INTERRUPT: extern "C" fn() = handler!(timer_interrupt);
 static KEYBOARD_INTERRUPT: extern "C" fn() = handler!(keyboard_interrupt);
+static COM1_INTERRUPT: extern "C" fn() = handler!(com1_interrupt);
+static RTC_INTERRUPT: extern "C" fn() = handler!(rtc_interrupt);
+static FREE1_INTERRUPT: extern "C" fn() = handler!(free1_interrupt);
+static FREE2_INTERRUPT: extern "C" fn() = handler!(free2_interrupt);
+static FREE3_INTERRUPT: extern "C" fn() = handler!(free3_interrupt);
+static SYSCALL: extern "C" fn() = syscall_handler!(syscall);
 
 lazy_static! {
 	#[link_section = ".idt"]
@@ -77,13 +83,12 @@ lazy_static! {
 		idt[20] = IdtDescriptor::new(VIRTUALIZATION_EXCEPTION as u32, 0x08, 0x8e);
 		idt[InterruptIndex::Timer.as_usize()] = IdtDescriptor::new(TIMER_INTERRUPT as u32, 0x08, 0x8e);
 		idt[InterruptIndex::Keyboard.as_usize()] = IdtDescriptor::new(KEYBOARD_INTERRUPT as u32, 0x08, 0x8e);
-		/*
-		idt[InterruptIndex::Rtc.as_usize()] = IdtDescriptor::new(
-			rtc_interrupt as u32,
-			0x08,
-			0x8e
-		);
-		 */
+		idt[InterruptIndex::Com1.as_usize()] = IdtDescriptor::new(COM1_INTERRUPT as u32, 0x08, 0x8e);
+		idt[InterruptIndex::Rtc.as_usize()] = IdtDescriptor::new(RTC_INTERRUPT as u32, 0x08, 0x8e);
+		idt[InterruptIndex::Free1.as_usize()] = IdtDescriptor::new(FREE1_INTERRUPT as u32, 0x08, 0x8e);
+		idt[InterruptIndex::Free2.as_usize()] = IdtDescriptor::new(FREE2_INTERRUPT as u32, 0x08, 0x8e);
+		idt[InterruptIndex::Free3.as_usize()] = IdtDescriptor::new(FREE3_INTERRUPT as u32, 0x08, 0x8e);
+		idt[SYSCALL_VECTOR] = IdtDescriptor::new(SYSCALL as u32, 0x08, 0xee);
 		idt
 	};
 }
@@ -94,6 +99,11 @@ struct IdtRegister {
 	offset: u32,
 }
 
+const _: () = assert!(core::mem::size_of::<IdtDescriptor>() == 8);
+const _: () = assert!(core::mem::size_of::<IdtRegister>() == 6);
+// linker.ld gives the .idt section 0x800 bytes
+const _: () = assert!(core::mem::size_of::<[IdtDescriptor; 256]>() == 0x800);
+
 pub fn init() {
 	unsafe {
 		let idt_register = IdtRegister {
@@ -104,3 +114,35 @@ pub fn init() {
 		asm!("lidt [{}]", in(reg) &idt_register, options(readonly, nostack, preserves_flags));
 	}
 }
+
+/// Base and limit the CPU holds in IDTR.
+pub fn read_register() -> (u32, u16) {
+	let mut register = IdtRegister { size: 0, offset: 0 };
+	unsafe {
+		asm!("sidt [{}]", in(reg) &mut register, options(nostack, preserves_flags));
+	}
+	(register.offset, register.size)
+}
+
+/// Reads IDTR back after `init` and checks the syscall gate still points
+/// at its handler and can be called from ring 3; panics otherwise.
+pub fn validate() {
+	let expected = (IDT.as_ptr() as u32, (core::mem::size_of::<[IdtDescriptor; 256]>() - 1) as u16);
+	let (base, limit) = read_register();
+	if (base, limit) != expected {
+		panic!("idt: idtr is {:#010x}/{:#06x}, expected {:#010x}/{:#06x}", base, limit, expected.0, expected.1);
+	}
+	let gate = IDT[SYSCALL_VECTOR];
+	let offset = gate.offset_low as u32 | (gate.offset_high as u32) << 16;
+	if offset != SYSCALL as u32 || gate.selector != 0x08 || gate.type_attributes != 0xee {
+		panic!("idt: syscall gate is {:#010x} {:#06x} {:#04x}", offset, { gate.selector }, { gate.type_attributes });
+	}
+}
+
+/// Flips the present bit of a gate, so `crash doublefault` can make the
+/// delivery of a fault fail.
+pub unsafe fn set_gate_present(vector: usize, present: bool) {
+	let descriptor = (IDT.as_ptr() as *mut IdtDescriptor).add(vector);
+	let attributes = (*descriptor).type_attributes;
+	(*descriptor).type_attributes = if present { attributes | 0x80 } else { attributes & !0x80 };
+}
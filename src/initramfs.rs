@@ -0,0 +1,155 @@
+use crate::sync::Mutex;
+use crate::vfs::{ DirEntry, FileSystem, FsError, Node, NodeKind };
+
+const NEWC_MAGIC: &[u8] = b"070701";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_DIRECTORY: u32 = 0o040000;
+const ROOT_INODE: usize = usize::MAX;
+
+/// cpio (newc) archive handed over as a multiboot module. Entries are read
+/// in place, there is no copy to unpack into yet.
+static ARCHIVE: Mutex<Option<&'static [u8]>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+	pub name: &'static str,
+	pub mode: u32,
+	pub data: &'static [u8],
+}
+
+impl Entry {
+	pub fn is_directory(&self) -> bool {
+		self.mode & MODE_TYPE_MASK == MODE_DIRECTORY
+	}
+
+	/// Directory holding the entry, "" for the archive root.
+	pub fn parent(&self) -> &'static str {
+		self.name.rsplit_once('/').map_or("", |(parent, _)| parent)
+	}
+}
+
+pub struct Entries {
+	archive: &'static [u8],
+	offset: usize,
+}
+
+impl Iterator for Entries {
+	type Item = Entry;
+
+	fn next(&mut self) -> Option<Entry> {
+		let header = self.archive.get(self.offset..self.offset + HEADER_SIZE)?;
+		if &header[..6] != NEWC_MAGIC {
+			return None;
+		}
+		let field = |index: usize| {
+			let start = 6 + index * 8;
+			core::str::from_utf8(&header[start..start + 8]).ok().and_then(|hex| u32::from_str_radix(hex, 16).ok())
+		};
+		let mode = field(1)?;
+		let file_size = field(6)? as usize;
+		let name_size = field(11)? as usize;
+
+		let name_start = self.offset + HEADER_SIZE;
+		// The name size counts its terminating NUL
+		let name = self.archive.get(name_start..name_start + name_size.checked_sub(1)?)?;
+		let name = core::str::from_utf8(name).ok()?;
+		if name == TRAILER {
+			return None;
+		}
+		let data_start = align4(name_start + name_size);
+		let data = self.archive.get(data_start..data_start + file_size)?;
+		self.offset = align4(data_start + file_size);
+
+		Some(Entry {
+			name: name.trim_start_matches("./"),
+			mode,
+			data,
+		})
+	}
+}
+
+fn align4(offset: usize) -> usize {
+	(offset + 3) & !3
+}
+
+/// Keeps the module if it starts like a newc archive.
+pub fn init_from_module(start: usize, end: usize) {
+	let archive = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+	if !archive.starts_with(NEWC_MAGIC) {
+		return;
+	}
+	*ARCHIVE.lock() = Some(archive);
+	log!(Info, "initramfs: {} entries", entries().count());
+	if let Err(error) = crate::vfs::mount("/", &INITRAMFS) {
+		log!(Error, "initramfs: mount failed: {}", error.as_str());
+	}
+}
+
+pub fn entries() -> Entries {
+	Entries {
+		archive: ARCHIVE.lock().unwrap_or(&[]),
+		offset: 0,
+	}
+}
+
+/// Read-only view of the archive for the VFS, inodes being entry indexes.
+pub struct Initramfs;
+
+pub static INITRAMFS: Initramfs = Initramfs;
+
+fn entry_node(inode: usize, entry: &Entry) -> Node {
+	Node {
+		inode,
+		kind: if entry.is_directory() { NodeKind::Directory } else { NodeKind::File },
+		size: entry.data.len(),
+	}
+}
+
+impl FileSystem for Initramfs {
+	fn name(&self) -> &'static str {
+		"initramfs"
+	}
+
+	fn lookup(&self, path: &str) -> Result<Node, FsError> {
+		if path.is_empty() {
+			return Ok(Node { inode: ROOT_INODE, kind: NodeKind::Directory, size: 0 });
+		}
+		entries()
+			.enumerate()
+			.find(|(_, entry)| entry.name == path)
+			.map(|(inode, entry)| entry_node(inode, &entry))
+			.ok_or(FsError::NotFound)
+	}
+
+	fn read(&self, node: Node, offset: usize, buffer: &mut [u8]) -> Result<usize, FsError> {
+		let entry = entries().nth(node.inode).ok_or(FsError::NotFound)?;
+		let data = entry.data.get(offset..).unwrap_or(&[]);
+		let count = data.len().min(buffer.len());
+		buffer[..count].copy_from_slice(&data[..count]);
+		Ok(count)
+	}
+
+	fn write(&self, _node: Node, _offset: usize, _buffer: &[u8]) -> Result<usize, FsError> {
+		Err(FsError::ReadOnly)
+	}
+
+	fn read_dir(&self, node: Node, index: usize) -> Result<Option<DirEntry>, FsError> {
+		let directory = match node.inode {
+			ROOT_INODE => "",
+			inode => entries().nth(inode).ok_or(FsError::NotFound)?.name,
+		};
+		let child = entries()
+			.enumerate()
+			.filter(|(_, entry)| entry.parent() == directory && entry.name != ".")
+			.nth(index);
+		match child {
+			Some((inode, entry)) => {
+				let name = entry.name.rsplit('/').next().unwrap_or(entry.name);
+				Ok(Some(DirEntry::new(name, entry_node(inode, &entry))?))
+			}
+			None => Ok(None),
+		}
+	}
+}
@@ -1,8 +1,18 @@
+use crate::bits;
+use crate::error::{ KernelError, KernelResult };
 use crate::io::inb;
 use crate::pic8259::ChainedPics;
-use spin::Mutex;
+use crate::vfs::{ FsError, FILES, MAX_PATH };
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{ AtomicU32, Ordering };
+use crate::sync::Mutex;
 
 pub const PIC_1_OFFSET: u8 = 32;
+pub const TICKS_PER_SECOND: u32 = crate::timer::HZ;
+const INTERRUPT_FLAG: usize = 1 << 9;
+
+pub static TICKS: AtomicU32 = AtomicU32::new(0);
 
 pub static PICS: Mutex<ChainedPics> =
 	Mutex::new(unsafe { ChainedPics::new_contiguous(PIC_1_OFFSET) });
@@ -38,23 +48,90 @@ impl InterruptIndex {
 	}
 }
 
-#[derive(Debug)]
+/// What the CPU (and the handler! stub) leave on the stack. The stub pushes
+/// a zero error code for vectors that have none so every handler sees the
+/// same layout; `stack_pointer` and `stack_segment` are only pushed by the
+/// CPU when the interrupt came from a less privileged ring.
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub struct InterruptStackFrame {
-	instruction_pointer: u32,
-	code_segment: u32,
-	cpu_flags: u32,
+	pub error_code: u32,
+	pub instruction_pointer: u32,
+	pub code_segment: u32,
+	pub cpu_flags: u32,
 	stack_pointer: u32,
 	stack_segment: u32,
 }
 
+/// Registers in the order `pushad` leaves them on the stack.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SavedRegisters {
+	pub edi: u32,
+	pub esi: u32,
+	pub ebp: u32,
+	pub esp: u32,
+	pub ebx: u32,
+	pub edx: u32,
+	pub ecx: u32,
+	pub eax: u32,
+}
+
+impl InterruptStackFrame {
+	/// The handler! wrapper pushes ebp then runs pushad, so the saved
+	/// registers sit 9 words below the frame.
+	pub fn registers(&self) -> SavedRegisters {
+		unsafe { *((self as *const Self as *const u32).sub(9) as *const SavedRegisters) }
+	}
+
+	pub fn is_user_mode(&self) -> bool {
+		self.code_segment & 0x3 != 0
+	}
+
+	pub fn stack_pointer(&self) -> Option<u32> {
+		self.is_user_mode().then_some(self.stack_pointer)
+	}
+
+	pub fn stack_segment(&self) -> Option<u32> {
+		self.is_user_mode().then_some(self.stack_segment)
+	}
+}
+
+impl fmt::Debug for InterruptStackFrame {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut frame = f.debug_struct("InterruptStackFrame");
+		frame
+			.field("error_code", &self.error_code)
+			.field("instruction_pointer", &self.instruction_pointer)
+			.field("code_segment", &self.code_segment)
+			.field("cpu_flags", &self.cpu_flags);
+		if let (Some(stack_pointer), Some(stack_segment)) = (self.stack_pointer(), self.stack_segment()) {
+			frame.field("stack_pointer", &stack_pointer).field("stack_segment", &stack_segment);
+		}
+		frame.finish()
+	}
+}
+
+static SYSRQ_SNAPSHOT: Mutex<Option<(InterruptStackFrame, SavedRegisters)>> = Mutex::new(None);
+
+/// `handler!(name)` for vectors without an error code, `handler!(name,
+/// error_code)` for the ones where the CPU pushes one (8, 10-14, 17).
 #[macro_export]
 macro_rules! handler {
-	($name: ident) => {{
+	($name: ident) => {
+		$crate::handler!(@wrap $name, "push 0")
+	};
+	($name: ident, error_code) => {
+		$crate::handler!(@wrap $name, "")
+	};
+	(@wrap $name: ident, $error_code: literal) => {{
 		#[naked]
 		extern "C" fn wrapper() {
 			unsafe {
 				asm!(
+					// Give every frame an error code slot
+					$error_code,
+
 					// Set up stack frame
 					"push ebp",
 					"mov ebp, esp",
@@ -64,7 +141,7 @@ macro_rules! handler {
 
 					// Calculate the correct stack frame pointer
 					"mov eax, esp",
-					"add eax, 36", // Adjust for 'pushad' and possibly other pushed registers
+					"add eax, 36", // Skip 'pushad' and ebp to reach the error code
 					"push eax", // Push stack frame pointer
 
 					// Call the actual interrupt handler
@@ -74,8 +151,9 @@ macro_rules! handler {
 					"pop eax", // Clean up the stack
 					"popad",
 
-					// Restore base pointer and return from interrupt
+					// Restore base pointer, drop the error code and return from interrupt
 					"pop ebp",
+					"add esp, 4",
 					"iretd",
 					sym $name,
 					options(noreturn)
@@ -86,108 +164,591 @@ macro_rules! handler {
 	}};
 }
 
+/// Entry stub for `int 0x80`: the caller's registers are pushed before any
+/// Rust code runs, `$name` receives them as a `&mut SavedRegisters` and
+/// whatever it leaves in `eax` is what the caller gets back.
+#[macro_export]
+macro_rules! syscall_handler {
+	($name: ident) => {{
+		#[naked]
+		extern "C" fn wrapper() {
+			unsafe {
+				asm!(
+					"pushad",
+					"mov eax, esp",
+					"push eax", // Push pointer to the saved registers
+					"call {}",
+					"pop eax",
+					"popad",
+					"iretd",
+					sym $name,
+					options(noreturn)
+				);
+			}
+		}
+		wrapper as extern "C" fn()
+	}};
+}
 
 pub extern "C" fn divide_by_zero(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: DIVIDE BY ZERO\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: DIVIDE BY ZERO\n{:#x?}", _stack_frame);
 }
 
 pub extern "C" fn debug(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: DEBUG\n{:#x?}", _stack_frame);
+	if crate::debugger::handle_debug_exception(_stack_frame) {
+		return;
+	}
+	println_async!("EXCEPTION: DEBUG\n{:#x?}", _stack_frame);
 }
 
 pub extern "C" fn non_maskable_interrupt(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: NON MASKABLE INTERRUPT\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: NON MASKABLE INTERRUPT\n{:#x?}", _stack_frame);
 }
 
 pub extern "C" fn breakpoint(_stack_frame: &mut InterruptStackFrame) {
-	let stack_frame = &mut *_stack_frame;
-	println!("EXCEPTION: BREAKPOINT at {:#x}\n{:#x?}", stack_frame.instruction_pointer, stack_frame);
+	crate::debugger::enter("breakpoint", _stack_frame);
 }
 
 pub fn overflow(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: OVERFLOW\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: OVERFLOW\n{:#x?}", _stack_frame);
 }
 
 pub fn bound_range_exceeded(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: BOUND RANGE EXCEEDED\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: BOUND RANGE EXCEEDED\n{:#x?}", _stack_frame);
 }
 
 pub fn invalid_opcode(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: INVALID OPCODE\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: INVALID OPCODE\n{:#x?}", _stack_frame);
 }
 
 pub fn coprocessor_not_available(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: COPROCESSOR NOT AVAILABLE\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: COPROCESSOR NOT AVAILABLE\n{:#x?}", _stack_frame);
 }
 
+/// An abort: the faulting instruction cannot be restarted.
 pub fn double_fault(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: DOUBLE FAULT\n{:#x?}", _stack_frame);
+	panic!("EXCEPTION: DOUBLE FAULT\n{:#x?}", _stack_frame);
 }
 
 pub fn coprocessor_segment_overrun(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: COPROCESSOR SEGMENT OVERRUN\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: COPROCESSOR SEGMENT OVERRUN\n{:#x?}", _stack_frame);
 }
 
 pub fn invalid_task_state_segment(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: INVALID TASK STATE SEGMENT\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: INVALID TASK STATE SEGMENT\n{:#x?}", _stack_frame);
 }
 
 pub fn segment_not_present(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: SEGMENT NOT PRESENT\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: SEGMENT NOT PRESENT\n{:#x?}", _stack_frame);
 }
 
 pub fn stack_fault(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: STACK FAULT\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: STACK FAULT\n{:#x?}", _stack_frame);
 }
 
 pub fn general_protection_fault(stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#x?}", stack_frame);
+	println_async!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#x?}", stack_frame);
 }
 
-pub fn page_fault(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: PAGE FAULT\n{:#x?}", _stack_frame);
+pub fn page_fault(stack_frame: &mut InterruptStackFrame) {
+	use core::arch::asm;
+	let faulting_address: u32;
+	unsafe {
+		asm!("mov {}, cr2", out(reg) faulting_address, options(nomem, nostack, preserves_flags));
+	}
+	log_memory!("page fault at {:#x}", faulting_address);
+	if (faulting_address as usize) < crate::config::get(crate::config::Key::NullGuard) {
+		// Returning would run the faulting instruction again, forever
+		panic!("NULL dereference at EIP {:#x} (address {:#x})", stack_frame.instruction_pointer, faulting_address);
+	}
+	println_async!(
+		"EXCEPTION: PAGE FAULT at {:#x} (error {:#x} [{}])\n{:#x?}",
+		faulting_address, stack_frame.error_code, bits::decode(stack_frame.error_code, bits::PAGE_FAULT_ERROR), stack_frame
+	);
 }
 
 pub fn reserved(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: RESERVED\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: RESERVED\n{:#x?}", _stack_frame);
 }
 
 pub fn math_fault(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: MATH FAULT\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: MATH FAULT\n{:#x?}", _stack_frame);
 }
 
 pub fn alignment_check(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: ALIGNMENT CHECK\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: ALIGNMENT CHECK\n{:#x?}", _stack_frame);
 }
 
 pub fn machine_check(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: MACHINE CHECK\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: MACHINE CHECK\n{:#x?}", _stack_frame);
 }
 
 pub fn simd_floating_point_exception(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: SIMD FLOATING POINT EXCEPTION\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: SIMD FLOATING POINT EXCEPTION\n{:#x?}", _stack_frame);
 }
 
 pub fn virtualization_exception(_stack_frame: &mut InterruptStackFrame) {
-	println!("EXCEPTION: VIRTUALIZATION EXCEPTION\n{:#x?}", _stack_frame);
+	println_async!("EXCEPTION: VIRTUALIZATION EXCEPTION\n{:#x?}", _stack_frame);
 }
 
-pub fn timer_interrupt(_stack_frame: &mut InterruptStackFrame) {
+/// IRQ policy: handlers are reached through interrupt gates, so they run
+/// with interrupts off and never turn them back on (anything they call uses
+/// disable/restore); IRQs therefore do not nest. Each handler acknowledges
+/// the PIC as its very last step, so a line cannot fire again before the
+/// previous interrupt on it has been fully handled.
+///
+/// `entered` is the TSC read on entry, for the per-line latency statistics.
+fn end_of_interrupt(index: InterruptIndex, entered: u64, eip: u32) {
+	let line = index.as_usize() - PIC_1_OFFSET as usize;
+	trace!(IrqExit, line, 0);
+	IRQ_COUNTS[line].fetch_add(1, Ordering::Relaxed);
 	unsafe {
-		PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+		PICS.lock().notify_end_of_interrupt(index.as_u8());
+	}
+	let cycles = crate::tsc::read() - entered;
+	IRQ_LATENCY.lock()[line].add(cycles);
+	if over_budget(cycles) {
+		SLOW_IRQ_CYCLES.store(cycles.min(u32::MAX as u64) as u32, Ordering::SeqCst);
+		SLOW_IRQ_EIP.store(eip, Ordering::SeqCst);
+		SLOW_IRQ.store(line as u32 + 1, Ordering::SeqCst);
 	}
 }
 
+/// Last handler that went over the irq_budget, line + 1 (0 for none), for
+/// `report_over_budget` to log: logging takes DEBUG, which the interrupted
+/// code may hold.
+static SLOW_IRQ: AtomicU32 = AtomicU32::new(0);
+static SLOW_IRQ_CYCLES: AtomicU32 = AtomicU32::new(0);
+static SLOW_IRQ_EIP: AtomicU32 = AtomicU32::new(0);
+/// Last interrupts-off section over the budget, for the same reason.
+static SLOW_SECTION: Mutex<Option<(u64, &'static Location<'static>)>> = Mutex::new(None);
+
+/// Called from log::process_pending: logs what went over the irq_budget
+/// since the last call.
+pub fn report_over_budget() {
+	let line = SLOW_IRQ.swap(0, Ordering::SeqCst);
+	if line != 0 {
+		let line = line as usize - 1;
+		let (cycles, eip) = (SLOW_IRQ_CYCLES.load(Ordering::SeqCst), SLOW_IRQ_EIP.load(Ordering::SeqCst));
+		log!(Warning, "irq{} ({}) took {} cycles, interrupted eip {:#010x}", line, IRQ_NAMES[line], cycles, eip);
+	}
+	let section = {
+		let _guard = InterruptGuard::new();
+		SLOW_SECTION.lock().take()
+	};
+	if let Some((cycles, location)) = section {
+		log!(Warning, "interrupts off for {} cycles from {}:{}", cycles, location.file(), location.line());
+	}
+}
+
+/// Whether `cycles` exceeds the irq_budget setting. Never true before the
+/// TSC is calibrated, since handlers cannot wait for that.
+fn over_budget(cycles: u64) -> bool {
+	let budget = crate::config::get(crate::config::Key::IrqBudget) as u64;
+	let khz = crate::tsc::cached_frequency_khz() as u64;
+	budget != 0 && khz != 0 && cycles > budget * khz / 1000
+}
+
+/// Where the current interrupts-off section started, to catch the ones
+/// running past the budget.
+static CRITICAL_SECTION: Mutex<(u64, Option<&'static Location<'static>>)> = Mutex::new((0, None));
+
+fn check_critical_section() {
+	let (entered, location) = *CRITICAL_SECTION.lock();
+	let cycles = crate::tsc::read() - entered;
+	if let Some(location) = location.filter(|_| over_budget(cycles)) {
+		*SLOW_SECTION.lock() = Some((cycles, location));
+	}
+}
+
+pub fn timer_interrupt(_stack_frame: &mut InterruptStackFrame) {
+	let entered = crate::tsc::read();
+	trace!(IrqEntry, 0, _stack_frame.instruction_pointer);
+	let now = TICKS.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+	crate::timer::tick(now);
+	crate::bell::tick(now);
+	crate::blank::tick(now);
+	crate::rtc::tick(now);
+	end_of_interrupt(InterruptIndex::Timer, entered, _stack_frame.instruction_pointer);
+	crate::task::tick();
+}
+
 pub fn keyboard_interrupt(_stack_frame: &mut InterruptStackFrame) {
-	use crate::keyboard::{KEYBOARD_INTERRUPT_RECEIVED, LAST_SCANCODE};
-	use core::sync::atomic::Ordering;
+	let entered = crate::tsc::read();
+	trace!(IrqEntry, 1, _stack_frame.instruction_pointer);
 	let scancode: u8 = unsafe { inb(0x60) };
+	log_irq!("irq1: scancode {:#04x}", scancode);
+
+	if scancode == SYSRQ_SCANCODE && crate::keyboard::ctrl_alt_pressed() {
+		*SYSRQ_SNAPSHOT.lock() = Some((*_stack_frame, _stack_frame.registers()));
+	}
+
+	crate::keyboard::push_scancode(scancode);
+	crate::blank::input();
+	end_of_interrupt(InterruptIndex::Keyboard, entered, _stack_frame.instruction_pointer);
+}
 
-	*LAST_SCANCODE.lock() = scancode;
-	KEYBOARD_INTERRUPT_RECEIVED.store(true, Ordering::SeqCst);
+pub fn rtc_interrupt(_stack_frame: &mut InterruptStackFrame) {
+	let entered = crate::tsc::read();
+	trace!(IrqEntry, 8, _stack_frame.instruction_pointer);
+	crate::rtc::interrupt();
+	end_of_interrupt(InterruptIndex::Rtc, entered, _stack_frame.instruction_pointer);
+}
 
+pub fn com1_interrupt(_stack_frame: &mut InterruptStackFrame) {
+	let entered = crate::tsc::read();
+	trace!(IrqEntry, 4, _stack_frame.instruction_pointer);
+	crate::debug::transmit_interrupt();
+	end_of_interrupt(InterruptIndex::Com1, entered, _stack_frame.instruction_pointer);
+}
+
+/// IRQ9 to IRQ11 go to whatever the kernel API registered for them.
+pub fn free1_interrupt(stack_frame: &mut InterruptStackFrame) {
+	api_interrupt(InterruptIndex::Free1, stack_frame);
+}
+
+pub fn free2_interrupt(stack_frame: &mut InterruptStackFrame) {
+	api_interrupt(InterruptIndex::Free2, stack_frame);
+}
+
+pub fn free3_interrupt(stack_frame: &mut InterruptStackFrame) {
+	api_interrupt(InterruptIndex::Free3, stack_frame);
+}
+
+fn api_interrupt(index: InterruptIndex, stack_frame: &InterruptStackFrame) {
+	let entered = crate::tsc::read();
+	let line = index.as_usize() - PIC_1_OFFSET as usize;
+	trace!(IrqEntry, line, stack_frame.instruction_pointer);
+	crate::api::interrupt(line);
+	end_of_interrupt(index, entered, stack_frame.instruction_pointer);
+}
+
+pub const SYSCALL_VECTOR: usize = 0x80;
+
+const SYS_READ: u32 = 3;
+const SYS_WRITE: u32 = 4;
+const SYS_OPEN: u32 = 5;
+const SYS_CLOSE: u32 = 6;
+const SYS_GETPID: u32 = 20;
+const SYS_DUP: u32 = 41;
+const SYS_PIPE: u32 = 42;
+const SYS_DUP2: u32 = 63;
+const SYS_SYSINFO: u32 = 116;
+const SYS_NANOSLEEP: u32 = 162;
+/// Not in Linux: waits ebx milliseconds for a key and returns its character.
+const SYS_GETCHAR: u32 = 200;
+/// Not in Linux: serialized time stamp counter, high half in edx.
+const SYS_CYCLES: u32 = 201;
+
+/// Linux i386 convention: number in eax, arguments in ebx, ecx, edx, result
+/// (or a negated errno) back in eax.
+pub extern "C" fn syscall(registers: &mut SavedRegisters) {
+	log_irq!("syscall {} ({:#x}, {:#x}, {:#x})", registers.eax, registers.ebx, registers.ecx, registers.edx);
+	let number = registers.eax;
+	trace!(SyscallEntry, number, registers.ebx);
+	let (first, second, third) = (registers.ebx as usize, registers.ecx as usize, registers.edx as usize);
+	let result = match number {
+		SYS_READ => user_buffer(second, third).and_then(|buffer| crate::vfs::read(first, buffer).map_err(KernelError::from)),
+		SYS_WRITE => user_buffer(second, third).and_then(|buffer| FILES.lock().write(first, buffer).map_err(KernelError::from)),
+		SYS_OPEN => sys_open(first as *const u8),
+		SYS_CLOSE => FILES.lock().close(first).map(|_| 0).map_err(KernelError::from),
+		SYS_GETPID => Ok(crate::task::current_id()),
+		SYS_DUP => FILES.lock().dup(first).map_err(KernelError::from),
+		SYS_PIPE => sys_pipe(first as *mut [u32; 2]),
+		SYS_DUP2 => FILES.lock().dup2(first, second).map_err(KernelError::from),
+		SYS_SYSINFO => sys_sysinfo(first as *mut SysInfo),
+		SYS_NANOSLEEP => sys_nanosleep(first as *const Timespec, second as *mut Timespec),
+		SYS_GETCHAR => crate::keyboard::read_char_timeout(first as u32).map(usize::from).ok_or(KernelError::TimedOut),
+		SYS_CYCLES => {
+			let cycles = crate::tsc::read_serialized();
+			registers.edx = (cycles >> 32) as u32;
+			Ok(cycles as u32 as usize)
+		}
+		_ => Err(KernelError::Unsupported),
+	};
+	registers.eax = match result {
+		Ok(value) => value as u32,
+		Err(error) => -error.errno() as u32,
+	};
+	trace!(SyscallExit, number, registers.eax);
+}
+
+/// Linux `struct sysinfo`, sizes in KB (mem_unit 1024). There is no swap,
+/// no high memory and no load average yet.
+#[repr(C)]
+struct SysInfo {
+	uptime: u32,
+	loads: [u32; 3],
+	total_ram: u32,
+	free_ram: u32,
+	shared_ram: u32,
+	buffer_ram: u32,
+	total_swap: u32,
+	free_swap: u32,
+	procs: u16,
+	pad: u16,
+	total_high: u32,
+	free_high: u32,
+	mem_unit: u32,
+	reserved: [u8; 8],
+}
+
+fn sys_sysinfo(info: *mut SysInfo) -> KernelResult<usize> {
+	let info = unsafe { info.as_mut() }.ok_or(KernelError::Fault)?;
+	let (total, free) = crate::procfs::memory_kb();
+	*info = SysInfo {
+		uptime: TICKS.load(Ordering::SeqCst) / TICKS_PER_SECOND,
+		loads: [0; 3],
+		total_ram: total,
+		free_ram: free,
+		shared_ram: 0,
+		buffer_ram: 0,
+		total_swap: 0,
+		free_swap: 0,
+		procs: 1,
+		pad: 0,
+		total_high: 0,
+		free_high: 0,
+		mem_unit: 1024,
+		reserved: [0; 8],
+	};
+	Ok(0)
+}
+
+/// Linux `struct timespec`.
+#[repr(C)]
+struct Timespec {
+	seconds: i32,
+	nanoseconds: i32,
+}
+
+/// Sleeps through to the end: nothing interrupts a sleep yet, so what is
+/// left in `remaining` is always zero.
+fn sys_nanosleep(requested: *const Timespec, remaining: *mut Timespec) -> KernelResult<usize> {
+	let requested = unsafe { requested.as_ref() }.ok_or(KernelError::Fault)?;
+	if requested.seconds < 0 || !(0..1_000_000_000).contains(&requested.nanoseconds) {
+		return Err(KernelError::Invalid);
+	}
+	let duration = crate::timer::Duration::from_secs(requested.seconds as u64)
+		+ crate::timer::Duration::from_nanos(requested.nanoseconds as u64);
+	crate::timer::sleep(duration);
+	if let Some(remaining) = unsafe { remaining.as_mut() } {
+		*remaining = Timespec { seconds: 0, nanoseconds: 0 };
+	}
+	Ok(0)
+}
+
+fn sys_pipe(descriptors: *mut [u32; 2]) -> KernelResult<usize> {
+	let descriptors = unsafe { descriptors.as_mut() }.ok_or(KernelError::Fault)?;
+	let [read, write] = FILES.lock().pipe()?;
+	*descriptors = [read as u32, write as u32];
+	Ok(0)
+}
+
+fn sys_open(path: *const u8) -> KernelResult<usize> {
+	if path.is_null() {
+		return Err(KernelError::Fault);
+	}
+	let path = unsafe { c_string(path) };
+	Ok(FILES.lock().open(path.ok_or(FsError::NameTooLong)?)?)
+}
+
+/// The `length` bytes at `address`, or Fault when the range is null, wraps
+/// around the address space or is too long for a slice. Nothing checks yet
+/// that the caller may touch it.
+fn user_buffer<'a>(address: usize, length: usize) -> KernelResult<&'a mut [u8]> {
+	if length == 0 {
+		return Ok(&mut []);
+	}
+	if address == 0 || length > isize::MAX as usize || address.checked_add(length).is_none() {
+		return Err(KernelError::Fault);
+	}
+	Ok(unsafe { core::slice::from_raw_parts_mut(address as *mut u8, length) })
+}
+
+/// NUL terminated string of at most MAX_PATH bytes.
+unsafe fn c_string<'a>(pointer: *const u8) -> Option<&'a str> {
+	let length = (0..MAX_PATH).find(|&index| *pointer.add(index) == 0)?;
+	core::str::from_utf8(core::slice::from_raw_parts(pointer, length)).ok()
+}
+
+/// Ctrl+Alt+F12: logs the state the keyboard interrupt caught the CPU in.
+pub const SYSRQ_SCANCODE: u8 = 0x58;
+
+pub fn dump_sysrq_snapshot() {
+	let snapshot = {
+		let _guard = InterruptGuard::new();
+		SYSRQ_SNAPSHOT.lock().take()
+	};
+
+	let (frame, registers) = match snapshot {
+		Some(snapshot) => snapshot,
+		None => return,
+	};
+	log!(Notice, "sysrq: eip={:#010x} cs={:#06x} eflags={:#010x}", frame.instruction_pointer, frame.code_segment, frame.cpu_flags);
+	if let (Some(stack_pointer), Some(stack_segment)) = (frame.stack_pointer(), frame.stack_segment()) {
+		log!(Notice, "sysrq: user esp={:#010x} ss={:#06x}", stack_pointer, stack_segment);
+	}
+	log!(Notice, "sysrq: eax={:#010x} ebx={:#010x} ecx={:#010x} edx={:#010x}", registers.eax, registers.ebx, registers.ecx, registers.edx);
+	log!(Notice, "sysrq: esi={:#010x} edi={:#010x} ebp={:#010x} esp={:#010x}", registers.esi, registers.edi, registers.ebp, registers.esp);
+	log!(Notice, "sysrq: uptime {} ticks", TICKS.load(Ordering::SeqCst));
+}
+
+pub const IRQ_LINES: usize = 16;
+const CASCADE_LINE: usize = 2;
+
+pub const IRQ_NAMES: [&str; IRQ_LINES] = [
+	"timer", "keyboard", "cascade", "com2", "com1", "lpt2", "floppy", "lpt1",
+	"rtc", "free1", "free2", "free3", "ps2mouse", "fpu", "ata1", "ata2",
+];
+
+/// How many users asked for each line; a line is unmasked while its count
+/// is not zero, so one driver releasing a shared line does not cut off the
+/// other.
+static IRQ_REFERENCES: Mutex<[usize; IRQ_LINES]> = Mutex::new([0; IRQ_LINES]);
+
+pub fn enable_irq(line: usize) {
+	let _guard = InterruptGuard::new();
+	let mut references = IRQ_REFERENCES.lock();
+	references[line] += 1;
+	write_irq_masks(&references);
+}
+
+pub fn disable_irq(line: usize) {
+	let _guard = InterruptGuard::new();
+	let mut references = IRQ_REFERENCES.lock();
+	references[line] = references[line].saturating_sub(1);
+	write_irq_masks(&references);
+}
+
+static IRQ_COUNTS: [AtomicU32; IRQ_LINES] = [const { AtomicU32::new(0) }; IRQ_LINES];
+
+/// Cycles from handler entry to end of interrupt. Only handlers update it,
+/// with interrupts off.
+#[derive(Clone, Copy)]
+struct Latency {
+	min: u64,
+	max: u64,
+	total: u64,
+	samples: u64,
+}
+
+impl Latency {
+	fn add(&mut self, cycles: u64) {
+		self.min = if self.samples == 0 { cycles } else { self.min.min(cycles) };
+		self.max = self.max.max(cycles);
+		self.total += cycles;
+		self.samples += 1;
+	}
+}
+
+static IRQ_LATENCY: Mutex<[Latency; IRQ_LINES]> = Mutex::new([Latency { min: 0, max: 0, total: 0, samples: 0 }; IRQ_LINES]);
+
+/// Masks every line but `wake`, whoever asked for the others, until
+/// `restore_irq_masks`.
+pub fn mask_all_but(wake: &[usize]) {
+	let _guard = InterruptGuard::new();
+	let mut references = [0; IRQ_LINES];
+	for &line in wake {
+		references[line] = 1;
+	}
+	write_irq_masks(&references);
+}
+
+pub fn restore_irq_masks() {
+	let _guard = InterruptGuard::new();
+	write_irq_masks(&IRQ_REFERENCES.lock());
+}
+
+pub fn irq_references() -> [usize; IRQ_LINES] {
+	*IRQ_REFERENCES.lock()
+}
+
+/// Shared by `irqmask` and /proc/interrupts.
+pub fn write_irq_table(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let masked = irq_masked();
+	let references = irq_references();
+	let latency = {
+		let _guard = InterruptGuard::new();
+		*IRQ_LATENCY.lock()
+	};
+	let khz = (crate::tsc::frequency_khz() as u64).max(1);
+	let nanoseconds = |cycles: u64| cycles * 1_000_000 / khz;
+	for line in 0..IRQ_LINES {
+		write!(
+			writer,
+			"{:2} {:9} {:6} users {} count {}",
+			line, IRQ_NAMES[line], if masked[line] { "masked" } else { "open" }, references[line],
+			IRQ_COUNTS[line].load(Ordering::Relaxed)
+		)?;
+		let latency = latency[line];
+		if latency.samples > 0 {
+			write!(
+				writer,
+				" ns {}/{}/{}",
+				nanoseconds(latency.min), nanoseconds(latency.total / latency.samples), nanoseconds(latency.max)
+			)?;
+		}
+		writeln!(writer)?;
+	}
+	writeln!(writer, "latency: min/avg/max from handler entry to end of interrupt")
+}
+
+/// Shared by `uptime` and /proc/uptime.
+pub fn write_uptime(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let uptime = crate::timer::uptime();
+	let seconds = uptime.as_secs();
+	writeln!(
+		writer,
+		"up {}:{:02}:{:02}.{:03} ({} ticks)",
+		seconds / 3600, seconds / 60 % 60, seconds % 60, uptime.subsec_millis(), crate::timer::ticks()
+	)
+}
+
+/// Control registers, flags and descriptor table registers as they are
+/// now, for the panic report.
+pub fn write_cpu_state(writer: &mut dyn fmt::Write) -> fmt::Result {
+	use core::arch::asm;
+	let (cr0, cr2, cr3, cr4, flags): (usize, usize, usize, usize, usize);
+	unsafe {
+		asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+		asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack, preserves_flags));
+		asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+		asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+		asm!("pushf", "pop {}", out(reg) flags, options(preserves_flags));
+	}
+	let (gdt_base, gdt_limit) = crate::gdt::read_register();
+	let (idt_base, idt_limit) = crate::idt::read_register();
+	writeln!(writer, "cr0={:#010x} cr2={:#010x} cr3={:#010x} cr4={:#010x}", cr0, cr2, cr3, cr4)?;
+	writeln!(writer, "eflags={:#010x} gdtr={:#010x}/{:#06x} idtr={:#010x}/{:#06x}", flags, gdt_base, gdt_limit, idt_base, idt_limit)?;
+	writeln!(writer, "cr0=[{}] cr4=[{}]", bits::decode(cr0 as u32, bits::CR0), bits::decode(cr4 as u32, bits::CR4))?;
+	writeln!(writer, "eflags=[{}]", bits::decode(flags as u32, bits::EFLAGS))
+}
+
+/// Lines of the current PIC masks, true meaning masked.
+pub fn irq_masked() -> [bool; IRQ_LINES] {
+	let masks = {
+		let _guard = InterruptGuard::new();
+		unsafe { PICS.lock().read_masks() }
+	};
+	let mask = masks[0] as u16 | (masks[1] as u16) << 8;
+	core::array::from_fn(|line| mask & (1 << line) != 0)
+}
+
+/// Masks every line nobody asked for; the cascade stays open while any
+/// line of the secondary PIC is in use.
+fn write_irq_masks(references: &[usize; IRQ_LINES]) {
+	let mut mask: u16 = 0xffff;
+	for (line, count) in references.iter().enumerate() {
+		if *count > 0 {
+			mask &= !(1 << line);
+		}
+	}
+	if mask & 0xff00 != 0xff00 {
+		mask &= !(1 << CASCADE_LINE);
+	}
 	unsafe {
-		PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+		PICS.lock().write_masks(mask as u8, (mask >> 8) as u8);
 	}
 }
 
@@ -195,19 +756,61 @@ pub fn init() {
 	unsafe {
 		PICS.lock().initialize();
 	}
+	write_irq_masks(&IRQ_REFERENCES.lock());
+	enable_irq(InterruptIndex::Timer.as_usize() - PIC_1_OFFSET as usize);
+	enable_irq(InterruptIndex::Keyboard.as_usize() - PIC_1_OFFSET as usize);
 	enable();
 }
 
+/// Ends the current interrupts-off section without checking it: code that
+/// opens a window this way (to halt, say) did not hold interrupts off.
 pub fn enable() {
 	use core::arch::asm;
+	CRITICAL_SECTION.lock().1 = None;
 	unsafe {
 		asm!("sti", options(preserves_flags, nostack));
 	}
 }
 
-pub fn disable() {
+/// Returns whether interrupts were enabled, to be handed back to `restore`
+/// so code reached from an interrupt handler never turns them back on.
+#[track_caller]
+pub fn disable() -> bool {
 	use core::arch::asm;
+	let flags: usize;
 	unsafe {
-		asm!("cli", options(preserves_flags, nostack));
+		asm!("pushf", "pop {}", "cli", out(reg) flags, options(preserves_flags));
+	}
+	let enabled = flags & INTERRUPT_FLAG != 0;
+	if enabled {
+		*CRITICAL_SECTION.lock() = (crate::tsc::read(), Some(Location::caller()));
+	}
+	enabled
+}
+
+pub fn restore(enabled: bool) {
+	if enabled {
+		check_critical_section();
+		enable();
+	}
+}
+
+/// Keeps interrupts disabled until dropped, then puts EFLAGS.IF back the way
+/// it found it. Declare it before the locks it protects so they are released
+/// first.
+pub struct InterruptGuard {
+	enabled: bool,
+}
+
+impl InterruptGuard {
+	#[track_caller]
+	pub fn new() -> Self {
+		InterruptGuard { enabled: disable() }
+	}
+}
+
+impl Drop for InterruptGuard {
+	fn drop(&mut self) {
+		restore(self.enabled);
 	}
 }
@@ -1,17 +1,66 @@
-use core::sync::atomic::{ AtomicBool, Ordering };
-use spin::Mutex;
-use crate::{ prompt, shell::HISTORY, shell::print_welcome_message };
-use crate::video_graphics_array;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{ AtomicBool, AtomicU8, AtomicU32, AtomicUsize, Ordering };
+use lazy_static::lazy_static;
+use crate::sync::Mutex;
+use crate::{ driver, librs, log, prompt, shell, shell::HISTORY, shell::print_welcome_message, timer, tty };
+use crate::collections::{ FixedString, FixedVec, RingBuffer };
+use crate::interrupts::{ self, TICKS, TICKS_PER_SECOND };
+use crate::video_graphics_array::{ self, ScreenOwner };
 
-pub static KEYBOARD_INTERRUPT_RECEIVED: AtomicBool = AtomicBool::new(false);
-pub static LAST_SCANCODE: Mutex<u8> = Mutex::new(0);
+const SCANCODE_BUFFER_SIZE: usize = 256;
+
+/// Scancodes between the keyboard interrupt and whoever decodes them. The
+/// handler is the only one to push and so the only writer of `head`, the
+/// decoders pop and are the only writers of `tail`: a slot is never written
+/// and read at the same time, so scancodes arriving faster than the main
+/// loop polls are queued, not lost. Unlike a collections::RingBuffer it
+/// takes no lock, and drops new scancodes rather than old ones.
+struct ScancodeQueue {
+	buffer: UnsafeCell<[u8; SCANCODE_BUFFER_SIZE]>,
+	head: AtomicUsize,
+	tail: AtomicUsize,
+}
+
+unsafe impl Sync for ScancodeQueue {}
+
+impl ScancodeQueue {
+	const fn new() -> ScancodeQueue {
+		ScancodeQueue { buffer: UnsafeCell::new([0; SCANCODE_BUFFER_SIZE]), head: AtomicUsize::new(0), tail: AtomicUsize::new(0) }
+	}
+
+	/// Drops the scancode when the queue is full.
+	fn push(&self, scancode: u8) {
+		let head = self.head.load(Ordering::SeqCst);
+		let next = (head + 1) % SCANCODE_BUFFER_SIZE;
+		if next == self.tail.load(Ordering::SeqCst) {
+			return;
+		}
+		unsafe {
+			(*self.buffer.get())[head] = scancode;
+		}
+		self.head.store(next, Ordering::SeqCst);
+	}
+
+	fn pop(&self) -> Option<u8> {
+		let tail = self.tail.load(Ordering::SeqCst);
+		if tail == self.head.load(Ordering::SeqCst) {
+			return None;
+		}
+		let scancode = unsafe { (*self.buffer.get())[tail] };
+		self.tail.store((tail + 1) % SCANCODE_BUFFER_SIZE, Ordering::SeqCst);
+		Some(scancode)
+	}
+}
+
+static SCANCODES: ScancodeQueue = ScancodeQueue::new();
 
 static SHIFT_PRESSED: AtomicBool = AtomicBool::new(false);
 static CTRL_PRESSED: AtomicBool = AtomicBool::new(false);
 static NUM_LOCK_PRESSED: AtomicBool = AtomicBool::new(false);
 static CAPS_LOCK_PRESSED: AtomicBool = AtomicBool::new(false);
+static ALT_PRESSED: AtomicBool = AtomicBool::new(false);
 static ALT_GR_PRESSED: AtomicBool = AtomicBool::new(false);
-static INSERT_PRESSED: AtomicBool = AtomicBool::new(false);
 static FOREGROUND: bool = true;
 static BACKGROUND: bool = false;
 
@@ -19,53 +68,347 @@ static QWERTY: bool = true;
 static AZERTY: bool = false;
 static KEYBOARD_LAYOUT: AtomicBool = AtomicBool::new(QWERTY);
 
-pub fn process_keyboard_input() {
-	static mut SCANCODE_BUFFER: [u8; 256] = [0; 256];
-	static mut BUFFER_HEAD: usize = 0;
-	static mut BUFFER_TAIL: usize = 0;
+const CIRCUMFLEX: u8 = b'^';
+const DIAERESIS: u8 = b'"';
+/// Accent waiting for the next key, 0 when none.
+static DEAD_KEY: AtomicU8 = AtomicU8::new(0);
 
-	if KEYBOARD_INTERRUPT_RECEIVED.load(Ordering::SeqCst) {
-		unsafe {
-			SCANCODE_BUFFER[BUFFER_HEAD] = *LAST_SCANCODE.lock();
-			BUFFER_HEAD = (BUFFER_HEAD + 1) % SCANCODE_BUFFER.len();
+const EXTENDED_PREFIX: u8 = 0xe0;
+const PAUSE_PREFIX: u8 = 0xe1;
+/// Bytes following 0xe1 in the Pause sequence (e1 1d 45 e1 9d c5).
+const PAUSE_SEQUENCE_LENGTH: usize = 5;
+const POWER_CONFIRM_TICKS: u32 = 5 * TICKS_PER_SECOND;
+
+/// Key detection error and buffer overrun, sent instead of a scancode.
+const KEY_ERROR: u8 = 0x00;
+const OVERRUN: u8 = 0xff;
+const RECENT_SCANCODES: usize = 8;
+
+static EXTENDED: AtomicBool = AtomicBool::new(false);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+/// Last scancodes decoded, for the error diagnostics.
+static RECENT: Mutex<RingBuffer<u8, RECENT_SCANCODES>> = Mutex::new(RingBuffer::new());
+static PAUSE_REMAINING: AtomicUsize = AtomicUsize::new(0);
+/// Tick of the first power key press, 0 when no shutdown is pending.
+static POWER_PRESSED_AT: AtomicU32 = AtomicU32::new(0);
+
+/// `keymap=azerty` or `keymap=qwerty` on the command line; F10 still
+/// switches at run time.
+pub fn init(cmdline: &str) {
+	match crate::args::cmdline_option(cmdline, "keymap") {
+		Some("azerty") => KEYBOARD_LAYOUT.store(AZERTY, Ordering::SeqCst),
+		Some("qwerty") | None => (),
+		Some(keymap) => log!(Warning, "keyboard: unknown keymap {}, using qwerty", keymap),
+	}
+	let driver = driver::Driver {
+		name: "keyboard",
+		depends_on: &[],
+		suspend: Some(|| while read_scancodes(&mut [0; 16]) != 0 {}),
+		resume: Some(release_keys),
+		shutdown: None,
+	};
+	if let Err(error) = driver::register(driver) {
+		log!(Warning, "keyboard: no power hooks: {}", error.as_str());
+	}
+}
+
+/// Forgets a half-read sequence and the modifiers held, whose release may
+/// have been lost.
+fn release_keys() {
+	EXTENDED.store(false, Ordering::SeqCst);
+	PAUSE_REMAINING.store(0, Ordering::SeqCst);
+	for pressed in [&SHIFT_PRESSED, &CTRL_PRESSED, &ALT_PRESSED, &ALT_GR_PRESSED] {
+		pressed.store(false, Ordering::SeqCst);
+	}
+	video_graphics_array::refresh_status_bar();
+}
+
+/// The key typed after a dead key, with its accent when the code page has
+/// the combination. A space gives the accent itself, anything else is
+/// typed without it.
+fn compose(dead: u8, c: u8) -> u8 {
+	match (dead, c) {
+		(CIRCUMFLEX, b'a') => 0x04,
+		(CIRCUMFLEX, b'e') => 0x08,
+		(CIRCUMFLEX, b'i') => 0x0d,
+		(CIRCUMFLEX, b'o') => 0x10,
+		(CIRCUMFLEX, b'u') => 0x12,
+		(DIAERESIS, b'a') => 0x05,
+		(DIAERESIS, b'A') => 0x0e,
+		(DIAERESIS, b'e') => 0x09,
+		(DIAERESIS, b'i') => 0x0c,
+		(DIAERESIS, b'o') => 0x11,
+		(DIAERESIS, b'O') => 0x14,
+		(DIAERESIS, b'u') => 0x02,
+		(DIAERESIS, b'U') => 0x15,
+		(_, b' ') => dead,
+		_ => c,
+	}
+}
+
+/// Modifier keys held and lock keys on, as of the last decoded scancode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+	pub shift: bool,
+	pub ctrl: bool,
+	/// Left Alt.
+	pub alt: bool,
+	/// Right Alt.
+	pub alt_gr: bool,
+	pub caps_lock: bool,
+	pub num_lock: bool,
+}
+
+pub fn modifiers() -> Modifiers {
+	Modifiers {
+		shift: SHIFT_PRESSED.load(Ordering::SeqCst),
+		ctrl: CTRL_PRESSED.load(Ordering::SeqCst),
+		alt: ALT_PRESSED.load(Ordering::SeqCst),
+		alt_gr: ALT_GR_PRESSED.load(Ordering::SeqCst),
+		caps_lock: CAPS_LOCK_PRESSED.load(Ordering::SeqCst),
+		num_lock: NUM_LOCK_PRESSED.load(Ordering::SeqCst),
+	}
+}
+
+pub fn ctrl_alt_pressed() -> bool {
+	let modifiers = modifiers();
+	modifiers.ctrl && (modifiers.alt || modifiers.alt_gr)
+}
+
+const MAX_BINDINGS: usize = 32;
+
+/// What a key chord can be bound to with `bind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	Screen(usize),
+	Welcome,
+	SwitchLayout,
+	ForegroundColor,
+	BackgroundColor,
+}
+
+impl Action {
+	pub fn parse(name: &str) -> Option<Action> {
+		match name {
+			"welcome" => Some(Action::Welcome),
+			"layout" => Some(Action::SwitchLayout),
+			"foreground" => Some(Action::ForegroundColor),
+			"background" => Some(Action::BackgroundColor),
+			_ => match name.strip_prefix("screen")?.parse::<usize>() {
+				Ok(number) if (1..=video_graphics_array::NUM_SCREENS).contains(&number) => Some(Action::Screen(number - 1)),
+				_ => None,
+			},
 		}
-		KEYBOARD_INTERRUPT_RECEIVED.store(false, Ordering::SeqCst);
 	}
 
-	unsafe {
-		while BUFFER_TAIL != BUFFER_HEAD {
-			let scancode = SCANCODE_BUFFER[BUFFER_TAIL];
-			BUFFER_TAIL = (BUFFER_TAIL + 1) % SCANCODE_BUFFER.len();
+	fn run(self) {
+		match self {
+			Action::Screen(screen) => video_graphics_array::change_display(screen),
+			Action::Welcome => print_welcome_message(),
+			Action::SwitchLayout => change_keyboard_layout(),
+			Action::ForegroundColor => video_graphics_array::change_color(FOREGROUND),
+			Action::BackgroundColor => video_graphics_array::change_color(BACKGROUND),
+		}
+	}
+}
 
-			update_modifier_state(scancode);
-			let c = scancode_to_char(scancode);
-			let ctrl = CTRL_PRESSED.load(Ordering::SeqCst);
-			if c != b'\0' && !ctrl {
-				prompt::PROMPT.lock().insert_char(c as u8, INSERT_PRESSED.load(Ordering::SeqCst));
-			}
+impl fmt::Display for Action {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Action::Screen(screen) => write!(f, "screen{}", screen + 1),
+			Action::Welcome => f.write_str("welcome"),
+			Action::SwitchLayout => f.write_str("layout"),
+			Action::ForegroundColor => f.write_str("foreground"),
+			Action::BackgroundColor => f.write_str("background"),
 		}
 	}
+}
 
-	fn update_modifier_state(scancode: u8) {
-		match scancode {
-			0x2a | 0x36 => SHIFT_PRESSED.store(true, Ordering::SeqCst),
-			0xaa | 0xb6 => SHIFT_PRESSED.store(false, Ordering::SeqCst),
-			//0x1d => CTRL_PRESSED.store(true, Ordering::SeqCst),
-			//0x9d => CTRL_PRESSED.store(false, Ordering::SeqCst),
-			0x45 => {
-				let num_lock = NUM_LOCK_PRESSED.load(Ordering::SeqCst);
-				NUM_LOCK_PRESSED.store(!num_lock, Ordering::SeqCst);
+/// Names `bind` accepts for keys, by their position on a QWERTY keyboard.
+const KEY_NAMES: [(&str, u8); 48] = [
+	("f1", 0x3b), ("f2", 0x3c), ("f3", 0x3d), ("f4", 0x3e), ("f5", 0x3f), ("f6", 0x40),
+	("f7", 0x41), ("f8", 0x42), ("f9", 0x43), ("f10", 0x44), ("f11", 0x57), ("f12", 0x58),
+	("1", 0x02), ("2", 0x03), ("3", 0x04), ("4", 0x05), ("5", 0x06),
+	("6", 0x07), ("7", 0x08), ("8", 0x09), ("9", 0x0a), ("0", 0x0b),
+	("q", 0x10), ("w", 0x11), ("e", 0x12), ("r", 0x13), ("t", 0x14),
+	("y", 0x15), ("u", 0x16), ("i", 0x17), ("o", 0x18), ("p", 0x19),
+	("a", 0x1e), ("s", 0x1f), ("d", 0x20), ("f", 0x21), ("g", 0x22),
+	("h", 0x23), ("j", 0x24), ("k", 0x25), ("l", 0x26),
+	("z", 0x2c), ("x", 0x2d), ("c", 0x2e), ("v", 0x2f), ("b", 0x30), ("n", 0x31), ("m", 0x32),
+];
+
+/// A key and the modifiers that must be held with it, no more and no less.
+/// Alt is the left one: AltGr keeps typing the third symbol of a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+	scancode: u8,
+	ctrl: bool,
+	alt: bool,
+	shift: bool,
+}
+
+impl Chord {
+	const fn key(scancode: u8) -> Chord {
+		Chord { scancode, ctrl: false, alt: false, shift: false }
+	}
+
+	const fn alt(scancode: u8) -> Chord {
+		Chord { scancode, ctrl: false, alt: true, shift: false }
+	}
+
+	/// `[ctrl+][alt+][shift+]key`, as in `alt+1` or `ctrl+f5`.
+	pub fn parse(text: &str) -> Option<Chord> {
+		let mut chord = Chord::key(0);
+		let mut parts = text.split('+').peekable();
+		while let Some(part) = parts.next() {
+			if parts.peek().is_none() {
+				chord.scancode = KEY_NAMES.iter().find(|(name, _)| *name == part)?.1;
+				return Some(chord);
 			}
-			0x3a => {
-				let caps_lock = CAPS_LOCK_PRESSED.load(Ordering::SeqCst);
-				CAPS_LOCK_PRESSED.store(!caps_lock, Ordering::SeqCst);
+			match part {
+				"ctrl" => chord.ctrl = true,
+				"alt" => chord.alt = true,
+				"shift" => chord.shift = true,
+				_ => return None,
+			}
+		}
+		None
+	}
+}
+
+impl fmt::Display for Chord {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (held, name) in [(self.ctrl, "ctrl+"), (self.alt, "alt+"), (self.shift, "shift+")] {
+			if held {
+				f.write_str(name)?;
 			}
-			0x38 => ALT_GR_PRESSED.store(true, Ordering::SeqCst),
-			0xb8 => ALT_GR_PRESSED.store(false, Ordering::SeqCst),
-			0x52 => {
-				let insert = INSERT_PRESSED.load(Ordering::SeqCst);
-				INSERT_PRESSED.store(!insert, Ordering::SeqCst);
+		}
+		match KEY_NAMES.iter().find(|(_, scancode)| *scancode == self.scancode) {
+			Some((name, _)) => f.write_str(name),
+			None => write!(f, "{:#04x}", self.scancode),
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Binding {
+	chord: Chord,
+	action: Action,
+}
+
+const DEFAULT_BINDINGS: [Binding; 12] = [
+	Binding { chord: Chord::key(0x3b), action: Action::Screen(0) },
+	Binding { chord: Chord::key(0x3c), action: Action::Screen(1) },
+	Binding { chord: Chord::key(0x3d), action: Action::Screen(2) },
+	Binding { chord: Chord::key(0x3e), action: Action::Screen(3) },
+	Binding { chord: Chord::alt(0x02), action: Action::Screen(0) },
+	Binding { chord: Chord::alt(0x03), action: Action::Screen(1) },
+	Binding { chord: Chord::alt(0x04), action: Action::Screen(2) },
+	Binding { chord: Chord::alt(0x05), action: Action::Screen(3) },
+	Binding { chord: Chord::key(0x43), action: Action::Welcome },
+	Binding { chord: Chord::key(0x44), action: Action::SwitchLayout },
+	Binding { chord: Chord::key(0x57), action: Action::ForegroundColor },
+	Binding { chord: Chord::key(0x58), action: Action::BackgroundColor },
+];
+
+lazy_static! {
+	static ref BINDINGS: Mutex<FixedVec<Binding, MAX_BINDINGS>> = {
+		let mut bindings = FixedVec::new();
+		for binding in DEFAULT_BINDINGS {
+			let _ = bindings.push(binding);
+		}
+		Mutex::new(bindings)
+	};
+}
+
+/// Binds `chord` to `action`, replacing what it did before, or unbinds it
+/// when `action` is None. Fails when the table is full.
+pub fn bind(chord: Chord, action: Option<Action>) -> Result<(), ()> {
+	let mut bindings = BINDINGS.lock();
+	let index = bindings.iter().position(|binding| binding.chord == chord);
+	match (index, action) {
+		(Some(index), Some(action)) => bindings.as_mut_slice()[index].action = action,
+		(Some(index), None) => {
+			bindings.remove(index);
+		}
+		(None, Some(action)) => bindings.push(Binding { chord, action }).map_err(|_| ())?,
+		(None, None) => (),
+	}
+	Ok(())
+}
+
+pub fn write_bindings(writer: &mut dyn fmt::Write) -> fmt::Result {
+	for binding in BINDINGS.lock().iter() {
+		let mut chord = FixedString::<24>::new();
+		let _ = fmt::Write::write_fmt(&mut chord, format_args!("{}", binding.chord));
+		writeln!(writer, "{:16} {}", chord.as_str(), binding.action)?;
+	}
+	Ok(())
+}
+
+/// The action bound to `scancode` with the modifiers held now.
+fn bound_action(scancode: u8) -> Option<Action> {
+	let modifiers = modifiers();
+	let chord = Chord { scancode, ctrl: modifiers.ctrl, alt: modifiers.alt, shift: modifiers.shift };
+	BINDINGS.lock().iter().find(|binding| binding.chord == chord).map(|binding| binding.action)
+}
+
+fn change_keyboard_layout() {
+	if KEYBOARD_LAYOUT.load(Ordering::SeqCst) == QWERTY {
+		KEYBOARD_LAYOUT.store(AZERTY, Ordering::SeqCst);
+	} else {
+		KEYBOARD_LAYOUT.store(QWERTY, Ordering::SeqCst);
+	}
+}
+
+/// Called from the keyboard interrupt.
+pub fn push_scancode(scancode: u8) {
+	SCANCODES.push(scancode);
+}
+
+fn pop_scancode() -> Option<u8> {
+	SCANCODES.pop()
+}
+
+/// Hands queued scancodes to a reader of /dev/keyboard instead of the shell.
+pub fn read_scancodes(buffer: &mut [u8]) -> usize {
+	let mut count = 0;
+	while count < buffer.len() {
+		match pop_scancode() {
+			Some(scancode) => buffer[count] = scancode,
+			None => break,
+		}
+		count += 1;
+	}
+	count
+}
+
+/// Stops after Enter, leaving the keys typed ahead queued until the command
+/// it finished has run.
+pub fn process_keyboard_input() {
+	decode_scancodes(Decode::Shell, |scancode, c| {
+		let ctrl = modifiers().ctrl;
+		match video_graphics_array::current_owner() {
+			ScreenOwner::Shell => {
+				handle_prompt_key(scancode);
+				if c != b'\0' && !ctrl {
+					prompt::PROMPT.lock().insert_char(c, video_graphics_array::overwrite());
+					return c != b'\n';
+				}
 			}
+			ScreenOwner::Logger => log::handle_key(scancode, c),
+			ScreenOwner::Animation => (),
+		}
+		true
+	});
+
+	fn handle_prompt_key(scancode: u8) {
+		let ctrl = modifiers().ctrl;
+		match scancode {
+			0x0e if ctrl => prompt::delete_word_left(),
+			0x4d if ctrl => prompt::word_right(),
+			0x4b if ctrl => prompt::word_left(),
+			0x53 if ctrl => prompt::delete_word_right(),
 			0x0e => prompt::backspace(),
 			0x0f => prompt::tab(),
 			0x4d => prompt::right_arrow(),
@@ -75,36 +418,215 @@ pub fn process_keyboard_input() {
 			0x48 => HISTORY.lock().scroll_up(),
 			0x50 => HISTORY.lock().scroll_down(),
 			0x53 => prompt::delete(),
-			0x3b => video_graphics_array::change_display(0),
-			0x3c => video_graphics_array::change_display(1),
-			0x3d => video_graphics_array::change_display(2),
-			0x3e => video_graphics_array::change_display(3),
-			// 0x3f F5
-			// 0x40 F6
-			// 0x41 F7
-			// 0x42 F8
-			0x43 => print_welcome_message(),
-			0x44 => change_keyboard_layout(),
-			0x57 => video_graphics_array::change_color(FOREGROUND),
-			0x58 => video_graphics_array::change_color(BACKGROUND),
 			_ => (),
 		}
 	}
+}
 
-	fn change_keyboard_layout() {
-		if KEYBOARD_LAYOUT.load(Ordering::SeqCst) == QWERTY {
-			KEYBOARD_LAYOUT.store(AZERTY, Ordering::SeqCst);
+/// Waits up to `ms` milliseconds for a typed character, halting between
+/// timer ticks. Keys that type nothing (modifiers, arrows) are consumed.
+pub fn read_char_timeout(ms: u32) -> Option<u8> {
+	let deadline = timer::uptime() + timer::Duration::from_millis(ms as u64);
+	let _guard = interrupts::InterruptGuard::new();
+	let mut typed = None;
+	loop {
+		decode_scancodes(Decode::Characters, |_, c| {
+			if c != b'\0' && !modifiers().ctrl {
+				typed = Some(c);
+			}
+			typed.is_none()
+		});
+		if typed.is_some() || timer::uptime() >= deadline {
+			break;
+		}
+		interrupts::enable();
+		librs::hlt();
+		interrupts::disable();
+	}
+	typed
+}
+
+/// Next key for the console TTY, without waiting. Ctrl+letter comes as a
+/// control key rather than as a byte, since the layouts use the low control
+/// codes for accented letters.
+pub fn read_tty_input() -> Option<tty::Input> {
+	let mut input = None;
+	decode_scancodes(Decode::Characters, |scancode, c| {
+		input = match (scancode, c) {
+			(0x0e, _) => Some(tty::Input::Erase),
+			(_, b'a'..=b'z') if modifiers().ctrl => Some(tty::Input::Control(c)),
+			(_, b'\0') => None,
+			_ if modifiers().ctrl => None,
+			_ => Some(tty::Input::Char(c)),
+		};
+		input.is_none()
+	});
+	input
+}
+
+/// Keypad keys sharing their codes with the navigation block: they type a
+/// digit or '.' while NumLock is on and navigate when it is off.
+fn is_keypad_digit(scancode: u8) -> bool {
+	matches!(scancode, 0x47..=0x49 | 0x4b..=0x4d | 0x4f..=0x53)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Decode {
+	/// Bound keys run their action.
+	Shell,
+	/// Bound keys are dropped: their actions switch screens or redraw the
+	/// prompt under the command that is reading.
+	Characters,
+}
+
+/// Turns queued scancodes into characters, handing each key to `deliver`
+/// until it returns false. Keypad digits come with scancode 0 so the
+/// navigation handlers leave them alone.
+fn decode_scancodes(mode: Decode, mut deliver: impl FnMut(u8, u8) -> bool) {
+	while let Some(scancode) = pop_scancode() {
+		if scancode == KEY_ERROR || scancode == OVERRUN {
+			resynchronize(scancode);
+			continue;
+		}
+		RECENT.lock().push(scancode);
+		if PAUSE_REMAINING.load(Ordering::SeqCst) > 0 {
+			PAUSE_REMAINING.fetch_sub(1, Ordering::SeqCst);
+			continue;
+		}
+		match scancode {
+			EXTENDED_PREFIX => {
+				EXTENDED.store(true, Ordering::SeqCst);
+				continue;
+			}
+			PAUSE_PREFIX => {
+				PAUSE_REMAINING.store(PAUSE_SEQUENCE_LENGTH, Ordering::SeqCst);
+				continue;
+			}
+			_ => (),
+		}
+		let c = if EXTENDED.swap(false, Ordering::SeqCst) {
+			if handle_extended_key(scancode) {
+				continue;
+			}
+			// Extended keys share their codes with the keypad but never type digits
+			update_modifier_state(scancode, true);
+			match scancode {
+				0x1c => b'\n',
+				0x35 => b'/',
+				_ => b'\0',
+			}
+		} else if is_keypad_digit(scancode) && modifiers().num_lock {
+			if !deliver(0, scancode_to_char(scancode)) {
+				break;
+			}
+			continue;
+		} else if let Some(action) = bound_action(scancode) {
+			if mode == Decode::Shell {
+				action.run();
+			}
+			continue;
 		} else {
-			KEYBOARD_LAYOUT.store(QWERTY, Ordering::SeqCst);
+			update_modifier_state(scancode, false);
+			match scancode_to_char(scancode) {
+				b'\0' => b'\0',
+				c => match DEAD_KEY.swap(0, Ordering::SeqCst) {
+					0 => c,
+					dead => compose(dead, c),
+				},
+			}
+		};
+		if !deliver(scancode, c) {
+			break;
+		}
+	}
+
+	/// Drops any half-read E0/E1 sequence and releases the modifiers, whose
+	/// break codes may have been lost with the rest of the controller buffer.
+	fn resynchronize(scancode: u8) {
+		let count = ERRORS.fetch_add(1, Ordering::SeqCst) + 1;
+		let mut context = FixedString::<{ RECENT_SCANCODES * 3 }>::new();
+		for byte in RECENT.lock().iter() {
+			let _ = fmt::Write::write_fmt(&mut context, format_args!(" {:02x}", byte));
+		}
+		let kind = if scancode == OVERRUN { "overrun" } else { "key detection error" };
+		log!(Debug, "keyboard: {} ({} so far) after{}", kind, count, context.as_str());
+		release_keys();
+	}
+
+	/// Consumes the E0 codes that have no legacy equivalent (multimedia and
+	/// ACPI keys) and the fake shifts some keyboards wrap around the
+	/// navigation block, so they cannot reach update_modifier_state.
+	fn handle_extended_key(scancode: u8) -> bool {
+		let pressed = scancode & 0x80 == 0;
+		match scancode & 0x7f {
+			0x2a | 0x36 => true,
+			0x5e => {
+				if pressed {
+					power_key();
+				}
+				true
+			}
+			0x5f | 0x63 => {
+				if pressed {
+					log!(Info, "keyboard: {} key", if scancode == 0x5f { "sleep" } else { "wake" });
+				}
+				true
+			}
+			0x10 | 0x19 | 0x20 | 0x22 | 0x24 | 0x2e | 0x30 | 0x32 | 0x6d => {
+				if pressed {
+					log!(Debug, "keyboard: multimedia key {:#04x}", scancode);
+				}
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// First press asks for confirmation, a second one within
+	/// POWER_CONFIRM_TICKS shuts the machine down.
+	fn power_key() {
+		let now = TICKS.load(Ordering::SeqCst).max(1);
+		let pressed_at = POWER_PRESSED_AT.swap(now, Ordering::SeqCst);
+		if pressed_at != 0 && now.wrapping_sub(pressed_at) < POWER_CONFIRM_TICKS {
+			shell::shutdown();
+			return;
+		}
+		println_async!("power key pressed, press it again within 5 seconds to shut down");
+	}
+
+	fn update_modifier_state(scancode: u8, extended: bool) {
+		let before = modifiers();
+		match scancode {
+			0x2a | 0x36 => SHIFT_PRESSED.store(true, Ordering::SeqCst),
+			0xaa | 0xb6 => SHIFT_PRESSED.store(false, Ordering::SeqCst),
+			0x1d => CTRL_PRESSED.store(true, Ordering::SeqCst),
+			0x9d => CTRL_PRESSED.store(false, Ordering::SeqCst),
+			0x45 => {
+				let num_lock = NUM_LOCK_PRESSED.load(Ordering::SeqCst);
+				NUM_LOCK_PRESSED.store(!num_lock, Ordering::SeqCst);
+			}
+			0x3a => {
+				let caps_lock = CAPS_LOCK_PRESSED.load(Ordering::SeqCst);
+				CAPS_LOCK_PRESSED.store(!caps_lock, Ordering::SeqCst);
+			}
+			0x38 if extended => ALT_GR_PRESSED.store(true, Ordering::SeqCst),
+			0xb8 if extended => ALT_GR_PRESSED.store(false, Ordering::SeqCst),
+			0x38 => ALT_PRESSED.store(true, Ordering::SeqCst),
+			0xb8 => ALT_PRESSED.store(false, Ordering::SeqCst),
+			0x52 => video_graphics_array::toggle_overwrite(),
+			interrupts::SYSRQ_SCANCODE if ctrl_alt_pressed() => interrupts::dump_sysrq_snapshot(),
+			0x59..=0x7f if !extended => log!(Debug, "keyboard: unknown scancode {:#04x}", scancode),
+			_ => (),
+		}
+		if modifiers() != before {
+			video_graphics_array::refresh_status_bar();
 		}
 	}
 
 	fn scancode_to_char(scancode: u8) -> u8 {
-		let shift = SHIFT_PRESSED.load(Ordering::SeqCst);
-		//let ctrl = CTRL_PRESSED.load(Ordering::SeqCst);
-		let num_lock = NUM_LOCK_PRESSED.load(Ordering::SeqCst);
-		let caps_lock = CAPS_LOCK_PRESSED.load(Ordering::SeqCst);
-		let alt_gr = ALT_GR_PRESSED.load(Ordering::SeqCst);
+		let Modifiers { shift, num_lock, caps_lock, alt, alt_gr, .. } = modifiers();
+		// Left Alt types the third symbol too when no binding took the key
+		let alt_gr = alt || alt_gr;
 
 		if KEYBOARD_LAYOUT.load(Ordering::SeqCst) == QWERTY {
 			match scancode {
@@ -199,7 +721,10 @@ pub fn process_keyboard_input() {
 				0x17 => if shift ^ caps_lock { b'I' } else { b'i' }
 				0x18 => if shift ^ caps_lock { b'O' } else { b'o' }
 				0x19 => if shift ^ caps_lock { b'P' } else { b'p' }
-				0x1a => if shift ^ caps_lock { b'^' } else { b'\0' }
+				0x1a => {
+					DEAD_KEY.store(if shift { DIAERESIS } else { CIRCUMFLEX }, Ordering::SeqCst);
+					b'\0'
+				}
 				0x1b => if shift { 0x16 } else { b'$' }
 				0x1c => b'\n',
 				0x1e => if shift ^ caps_lock { b'Q' } else { b'q' }
@@ -3,16 +3,57 @@
 #![feature(naked_functions)]
 
 #[macro_use] mod librs;
+#[macro_use] mod log;
+#[macro_use] mod trace;
 #[macro_use] mod interrupts;
+mod acpi;
+mod api;
+mod applet;
+mod args;
+mod assets;
+mod bell;
+mod bits;
+mod blank;
+mod block;
+mod calc;
+mod checksum;
+mod collections;
+mod config;
+mod coredump;
 mod debug;
+mod debugger;
+mod devfs;
+mod driver;
+mod error;
+mod frame;
 mod gdt;
+mod hpet;
+mod idle;
 mod idt;
+mod initramfs;
 mod io;
 mod keyboard;
+mod lz4;
 mod memory;
+mod msr;
+mod multiboot;
 mod pic8259;
+mod pipe;
+mod power;
+mod procfs;
 mod prompt;
+mod ramdisk;
+mod rtc;
+mod selfcheck;
 mod shell;
+mod splash;
+mod sync;
+mod task;
+mod timer;
+mod tsc;
+mod tty;
+mod version;
+mod vfs;
 mod video_graphics_array;
 
 use core::arch::asm;
@@ -98,71 +139,6 @@ static MULTIBOOT_HEADER: MultibootHeader = MultibootHeader {
 	end_tag_size: 8,
 };
 
-#[repr(C, align(8))]
-struct MultibootInfo {
-	total_size: u32,
-	reserved: u32,
-	tags: [MultibootTag; 1],
-}
-
-#[repr(C, align(8))]
-struct MultibootTag {
-	typ: u32,
-	size: u32,
-}
-
-#[repr(C)]
-struct MultibootTagString {
-	typ: u32,
-	size: u32,
-	string: u8,
-}
-
-#[repr(C)]
-struct MultibootTagModule {
-	typ: u32,
-	size: u32,
-	mod_start: u32,
-	mod_end: u32,
-	string: u8,
-}
-
-#[repr(C)]
-struct MultibootTagBasicMemInfo {
-	typ: u32,
-	size: u32,
-	mem_lower: u32,
-	mem_upper: u32,
-}
-
-#[repr(C)]
-struct MultibootTagBootDev {
-	typ: u32,
-	size: u32,
-	biosdev: u32,
-	partition: u32,
-	sub_partition: u32,
-}
-
-#[repr(C)]
-struct MultibootMemoryMap {
-	typ: u32,
-	size: u32,
-	entry_size: u32,
-	entry_version: u32,
-	entries: [MultibootMemoryMapTag; 1],
-}
-
-#[repr(C)]
-struct MultibootMemoryMapTag {
-	size: u32,
-	base_addr: u64,
-	length: u64,
-	typ: u32,
-}
-
-const  MULTIBOOT_MEMORY_AVAILABLE: u8 = 1;
-
 #[no_mangle]
 pub extern "C" fn _start(multiboot_magic: u32, multiboot_addr: u32) -> ! {
 	if multiboot_magic != 0x36d76289 {
@@ -171,71 +147,131 @@ pub extern "C" fn _start(multiboot_magic: u32, multiboot_addr: u32) -> ! {
 	if multiboot_addr & 0x7 != 0 {
 		panic!("Unaligned multiboot address: 0x{:x}", multiboot_addr);
 	}
+	let cmdline = multiboot_cmdline(multiboot_addr);
+	if args::cmdline_flag(cmdline, "quiet") {
+		log::LOG_LEVEL.store(log::Level::Warning as u8, core::sync::atomic::Ordering::SeqCst);
+	}
+	splash::init(cmdline);
 	init();
 
-	let mb_info = unsafe { &*(multiboot_addr as *const MultibootInfo) };
-	let mut current_addr = multiboot_addr + 8;
-
-	while current_addr < multiboot_addr + (mb_info.total_size as u32) {
-		let tag = unsafe { &*(current_addr as *const MultibootTag) };
-
+	let mut ramdisk_module = None;
+	for tag in multiboot::tags(multiboot_addr) {
+		let tag = match tag {
+			Ok(tag) => tag,
+			Err(error) => {
+				log!(Error, "multiboot: {}, ignoring the tags after it", error);
+				break;
+			}
+		};
 		match tag.typ {
-			0 => break,  // End tag
-			1 => {  // Boot command line
-				let cmdline_tag = unsafe { &*(current_addr as *const MultibootTagString) };
-				let cmdline = unsafe { core::slice::from_raw_parts((&cmdline_tag.string) as *const u8, cmdline_tag.size as usize - 8) };
-				println!("Command line: {}", core::str::from_utf8(cmdline).unwrap());
-			},
-			2 => {  // Boot loader name
-				let loader_tag = unsafe { &*(current_addr as *const MultibootTagString) };
-				let loader = unsafe { core::slice::from_raw_parts((&loader_tag.string) as *const u8, loader_tag.size as usize - 8) };
-				println!("Boot loader: {}", core::str::from_utf8(loader).unwrap());
-			},
-			3 => {  // Module
-				let module_tag = unsafe { &*(current_addr as *const MultibootTagModule) };
-				let module = unsafe { core::slice::from_raw_parts((&module_tag.string) as *const u8, module_tag.size as usize - 8) };
-				println!("Module: {}", core::str::from_utf8(module).unwrap());
-			},
-			4 => {  // Basic memory information
-				let mem_tag = unsafe { &*(current_addr as *const MultibootTagBasicMemInfo) };
-				println!("Memory: {} KB", mem_tag.mem_lower + mem_tag.mem_upper);
-			},
-			5 => {  // BIOS boot device
-				let bootdev_tag = unsafe { &*(current_addr as *const MultibootTagBootDev) };
-				println!("Boot device: 0x{:x}", bootdev_tag.biosdev);
-			},
-			6 => { // Memory map tag type
-				let mmap = unsafe { &*(current_addr as *const MultibootMemoryMap) };
-				let entries = (mmap.size as usize - 16) / mmap.entry_size as usize;
-	
-				let mut entry_addr = current_addr + 16; // Start of the memory map entries
-				for _ in 0..entries {
-					let entry = unsafe { &*(entry_addr as *const MultibootMemoryMapTag) };
-	
-					if entry.typ == 1 {
-						println!("Available memory region: start = {:x}, length = {:x}", entry.base_addr, entry.length);
+			multiboot::TAG_CMDLINE => log!(Info, "Command line: {}", tag.string(8)),
+			multiboot::TAG_BOOT_LOADER => log!(Info, "Boot loader: {}", tag.string(8)),
+			multiboot::TAG_MODULE => {
+				let module = tag.string(16);
+				log!(Info, "Module: {}", module);
+				match (tag.u32_at(8), tag.u32_at(12)) {
+					(Some(start), Some(end)) if start <= end => {
+						if ramdisk_module.is_none() {
+							ramdisk_module = Some((start as usize, end as usize, module));
+						}
+					}
+					_ => log!(Warning, "multiboot: module tag at {:#x} has bad bounds", tag.address),
+				}
+			}
+			multiboot::TAG_BASIC_MEMINFO => {
+				if let (Some(lower), Some(upper)) = (tag.u32_at(8), tag.u32_at(12)) {
+					log!(Info, "Memory: {} KB", lower + upper);
+					procfs::set_boot_memory(lower, upper);
+				}
+			}
+			multiboot::TAG_BOOTDEV => {
+				if let Some(biosdev) = tag.u32_at(8) {
+					log!(Info, "Boot device: 0x{:x}", biosdev);
+				}
+			}
+			multiboot::TAG_MEMORY_MAP => {
+				for (base, length, typ) in tag.memory_map() {
+					if typ == multiboot::MEMORY_AVAILABLE {
+						log!(Info, "Available memory region: start = {:x}, length = {:x}", base, length);
 					} else {
-						println!("Reserved memory region: start = {:x}, length = {:x}", entry.base_addr, entry.length);
+						log!(Info, "Reserved memory region: start = {:x}, length = {:x}", base, length);
 					}
-	
-					entry_addr += mmap.entry_size as u32;
-				}}
-			// Add other cases for different tag types
-			_ => (),
+				}
+			}
+			_ => multiboot::keep_unknown(tag),
+		}
+	}
+
+	procfs::set_bad_ram(cmdline);
+	acpi::init();
+	hpet::init();
+	timer::select();
+	splash::stage("multiboot");
+
+	// A module is only used if it matches the crc32= given on its own line
+	// or module_crc32= on the kernel's
+	let ramdisk_module = ramdisk_module.filter(|&(start, end, options)| {
+		let expected = args::cmdline_option(options, "crc32")
+			.or_else(|| args::cmdline_option(cmdline, "module_crc32"))
+			.and_then(args::parse_int);
+		let data = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+		checksum::verify(options.split_whitespace().next().unwrap_or("module"), data, expected)
+	});
+	match (ramdisk_module, args::cmdline_option(cmdline, "ramdisk_size").and_then(args::parse_int)) {
+		(Some((start, end, _)), _) => {
+			if let Some((start, end)) = ramdisk::init_from_module(start, end) {
+				initramfs::init_from_module(start, end);
+			}
 		}
+		(None, Some(kilobytes)) => ramdisk::init_empty(kilobytes),
+		(None, None) => (),
+	}
+	splash::stage("modules");
+	if let Err(error) = vfs::mount("/dev", &devfs::DEVFS) {
+		log!(Error, "devfs: mount failed: {}", error.as_str());
+	} else if let Err(error) = vfs::FILES.lock().open_console() {
+		log!(Error, "vfs: no console for stdin/stdout: {}", error.as_str());
+	}
+	if let Err(error) = vfs::mount("/proc", &procfs::PROCFS) {
+		log!(Error, "procfs: mount failed: {}", error.as_str());
+	}
+	splash::stage("filesystems");
+	splash::finish();
+	shell::print_welcome_message();
+	idle::init(cmdline);
+	keyboard::init(cmdline);
+	tsc::frequency_khz();
+	if let Err(error) = task::spawn("shell", run_shell) {
+		panic!("cannot start the shell task: {}", error.as_str());
+	}
 
-		current_addr = ((current_addr + (tag.size as u32) + 7) & !7) as u32;
+	// The kernel task is what runs when every other task has yielded
+	loop {
+		task::yield_now();
+		idle::enter();
 	}
+}
 
+fn run_shell() {
 	loop {
 		keyboard::process_keyboard_input();
-		librs::hlt();
+		prompt::run_command();
+		task::yield_now();
 	}
 }
 
+const PANIC_LOG_RECORDS: usize = 5;
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+	interrupts::disable();
 	println!("{}", info);
+	let _ = interrupts::write_cpu_state(&mut librs::Console);
+	log::print_recent(PANIC_LOG_RECORDS);
+	debug::flush();
+	if config::get(config::Key::PanicCoredump) != 0 {
+		println!("core dump: {} bytes sent on COM2", coredump::stream());
+	}
 	loop {
 		librs::hlt();
 	}
@@ -244,7 +280,24 @@ fn panic(info: &PanicInfo) -> ! {
 fn init() {
 	gdt::init();
 	idt::init();
+	gdt::validate();
+	idt::validate();
+	splash::stage("cpu tables");
+	timer::init();
 	interrupts::init();
+	rtc::init();
+	splash::stage("interrupts");
 	debug::init_serial_port();
-	shell::print_welcome_message();
+	debug::enable_transmit_interrupt();
+	splash::stage("serial");
+	bell::init();
+}
+
+/// The command line is needed before anything else is set up (`quiet`,
+/// `splash`), ahead of the full walk over the tags.
+fn multiboot_cmdline(multiboot_addr: u32) -> &'static str {
+	multiboot::tags(multiboot_addr)
+		.map_while(Result::ok)
+		.find(|tag| tag.typ == multiboot::TAG_CMDLINE)
+		.map_or("", |tag| tag.string(8))
 }
@@ -1,7 +1,10 @@
 use core::fmt;
+use core::sync::atomic::{ AtomicUsize, Ordering };
 use crate::debug::DEBUG;
 use crate::interrupts;
-use crate::video_graphics_array::WRITER;
+use crate::prompt::PROMPT;
+use crate::vfs::FILES;
+use crate::video_graphics_array::{ self, ScreenOwner, WRITER, VGA_LAST_LINE };
 
 #[macro_export]
 macro_rules! print {
@@ -14,6 +17,13 @@ macro_rules! println {
 	($($arg:tt)*) => (print!("{}\n", format_args!($($arg)*)));
 }
 
+/// For messages raised asynchronously (interrupt and exception handlers):
+/// they are printed above the line being edited instead of inside it.
+#[macro_export]
+macro_rules! println_async {
+	($($arg:tt)*) => ($crate::librs::print_async(format_args!("{}\n", format_args!($($arg)*))));
+}
+
 #[macro_export]
 macro_rules! printk {
 	/*($level:expr, $($arg:tt)*) => {
@@ -31,31 +41,87 @@ macro_rules! print_serial {
 	};
 }
 
+const NO_REDIRECT: usize = usize::MAX;
+
+/// Descriptor `print` writes to instead of the screen while a shell
+/// redirection is active.
+static REDIRECT: AtomicUsize = AtomicUsize::new(NO_REDIRECT);
+
+pub fn redirect(descriptor: Option<usize>) {
+	REDIRECT.store(descriptor.unwrap_or(NO_REDIRECT), Ordering::SeqCst);
+}
+
+/// `print` as a fmt::Write, for formatters shared with other outputs.
+pub struct Console;
+
+impl fmt::Write for Console {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		print(format_args!("{}", s));
+		Ok(())
+	}
+}
+
+struct FileWriter(usize);
+
+impl fmt::Write for FileWriter {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		FILES.lock().write(self.0, s.as_bytes()).map(|_| ()).map_err(|_| fmt::Error)
+	}
+}
+
 pub fn print(args: fmt::Arguments) {
 	use core::fmt::Write;
-	interrupts::disable();
+	// Taken while writing so a device printing to the console cannot loop
+	let descriptor = REDIRECT.swap(NO_REDIRECT, Ordering::SeqCst);
+	if descriptor != NO_REDIRECT {
+		let _ = FileWriter(descriptor).write_fmt(args);
+		REDIRECT.store(descriptor, Ordering::SeqCst);
+		return;
+	}
+	let _guard = interrupts::InterruptGuard::new();
 	WRITER.lock().write_fmt(args).unwrap();
-	interrupts::enable();
+}
+
+/// If the prompt is idle its line is cleared, the message written from
+/// column 0 and the prompt redrawn below it. If the prompt is busy running a
+/// command the message simply follows that command's output. Screens that
+/// are not shells get the message through the log instead.
+pub fn print_async(args: fmt::Arguments) {
+	use core::fmt::Write;
+	if video_graphics_array::current_owner() != ScreenOwner::Shell {
+		crate::log::log(crate::log::Level::Error, args);
+		return;
+	}
+
+	let prompt = if crate::prompt::command_running() { None } else { PROMPT.try_lock() };
+	{
+		let _guard = interrupts::InterruptGuard::new();
+		let mut writer = WRITER.lock();
+		if prompt.is_some() {
+			writer.clear_row(VGA_LAST_LINE);
+		}
+		writer.write_fmt(args).unwrap();
+	}
+	if let Some(mut prompt) = prompt {
+		prompt.update_line();
+	}
 }
 
 pub fn print_serial(args: fmt::Arguments) {
 	use core::fmt::Write;
-	interrupts::disable();
+	let _guard = interrupts::InterruptGuard::new();
 	DEBUG.lock().write_fmt(args).unwrap();
-	interrupts::enable();
 }
 
 //je vais l'ecraser
 pub fn printraw(string: &str) {
-	interrupts::disable();
+	let _guard = interrupts::InterruptGuard::new();
 	WRITER.lock().write_string_raw(string);
-	interrupts::enable();
 }
 
 pub fn clear() {
-	interrupts::disable();
+	let _guard = interrupts::InterruptGuard::new();
 	WRITER.lock().clear_screen();
-	interrupts::enable();
 }
 
 #[inline]
@@ -64,6 +130,24 @@ pub fn hlt() {
 		asm!("hlt", options(nomem, nostack, preserves_flags));
 	}
 }
+
+/// Returns eax, ecx and edx; ebx belongs to LLVM so it is saved around cpuid.
+pub fn cpuid(leaf: u32) -> (u32, u32, u32) {
+	let (eax, ecx, edx): (u32, u32, u32);
+	unsafe {
+		asm!(
+			"mov {saved:e}, ebx",
+			"cpuid",
+			"mov ebx, {saved:e}",
+			saved = out(reg) _,
+			inout("eax") leaf => eax,
+			inout("ecx") 0 => ecx,
+			out("edx") edx,
+			options(nomem, nostack, preserves_flags),
+		);
+	}
+	(eax, ecx, edx)
+}
 /*/
 pub const KERN_EMERG: &str = "KERN_EMERG: ";
 pub const KERN_ALERT: &str = "KERN_ALERT: ";
@@ -80,9 +164,8 @@ pub fn printk(/*level: &str, */ args: fmt::Arguments) {
 	/*let mut writer = WRITER.lock();
 	writer.write_str(level).unwrap();
 	writer.write_fmt(args).unwrap();*/
-	interrupts::disable();
+	let _guard = interrupts::InterruptGuard::new();
 	WRITER.lock().write_fmt(args).unwrap();
-	interrupts::enable();
 }
 
 ///
@@ -90,6 +173,16 @@ pub fn printk(/*level: &str, */ args: fmt::Arguments) {
 ///
 use core::arch::asm;
 
+extern "C" {
+	static __kernel_start: u8;
+	static __kernel_end: u8;
+}
+
+/// Where the linker put the kernel image, .bss and boot stack included.
+pub fn kernel_image() -> (usize, usize) {
+	unsafe { (&__kernel_start as *const u8 as usize, &__kernel_end as *const u8 as usize) }
+}
+
 pub fn print_stack() {
 	let stack_pointer: usize;
 	unsafe {
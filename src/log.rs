@@ -0,0 +1,323 @@
+use core::fmt;
+use core::sync::atomic::{ AtomicBool, AtomicU8, AtomicUsize, Ordering };
+use crate::sync::Mutex;
+use crate::collections::{ FixedString, RingBuffer };
+use crate::debug::DEBUG;
+use crate::interrupts::{ self, TICKS, TICKS_PER_SECOND };
+use crate::video_graphics_array::{ self, ColorCode, WRITER, VGA_COLUMNS, VGA_FIRST_LINE, VGA_LAST_LINE };
+
+pub const LOG_SCREEN: usize = 3;
+
+const LOG_CAPACITY: usize = 128;
+const LOG_LINE_LENGTH: usize = VGA_COLUMNS;
+const LOG_ROWS: usize = VGA_LAST_LINE - VGA_FIRST_LINE;
+
+static LOG_UPDATED: AtomicBool = AtomicBool::new(false);
+
+/// Maximum number of records accepted per second, 0 meaning unlimited.
+pub static RATE_LIMIT: AtomicUsize = AtomicUsize::new(50);
+/// Records less severe than this level are discarded before formatting.
+pub static LOG_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+#[macro_export]
+macro_rules! log {
+	($level:ident, $($arg:tt)*) => {
+		$crate::log::log($crate::log::Level::$level, format_args!($($arg)*))
+	};
+}
+
+/// Debug output of the memory code, compiled out unless the
+/// `verbose-memory` feature is enabled.
+#[macro_export]
+macro_rules! log_memory {
+	($($arg:tt)*) => {
+		if cfg!(feature = "verbose-memory") {
+			$crate::log!(Debug, $($arg)*);
+		}
+	};
+}
+
+/// Interrupt tracing, compiled out unless the `verbose-irq` feature is
+/// enabled and then toggled at runtime with `sysctl verbose_irq=<0|1>`.
+#[macro_export]
+macro_rules! log_irq {
+	($($arg:tt)*) => {
+		if cfg!(feature = "verbose-irq") && $crate::config::get($crate::config::Key::VerboseIrq) != 0 {
+			$crate::log!(Debug, $($arg)*);
+		}
+	};
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+	Emergency = 0,
+	Alert,
+	Critical,
+	Error,
+	Warning,
+	Notice,
+	Info,
+	Debug,
+}
+
+impl Level {
+	pub fn tag(self) -> &'static str {
+		match self {
+			Level::Emergency => "EMERG",
+			Level::Alert => "ALERT",
+			Level::Critical => "CRIT",
+			Level::Error => "ERR",
+			Level::Warning => "WARN",
+			Level::Notice => "NOTICE",
+			Level::Info => "INFO",
+			Level::Debug => "DEBUG",
+		}
+	}
+
+	/// Foreground of the level's records on screen.
+	pub fn color(self) -> ColorCode {
+		match self {
+			Level::Emergency | Level::Alert | Level::Critical | Level::Error => ColorCode::LightRed,
+			Level::Warning => ColorCode::Yellow,
+			Level::Notice => ColorCode::White,
+			Level::Info => ColorCode::LightGray,
+			Level::Debug => ColorCode::DarkGray,
+		}
+	}
+
+	pub fn from_u8(level: u8) -> Option<Level> {
+		match level {
+			0 => Some(Level::Emergency),
+			1 => Some(Level::Alert),
+			2 => Some(Level::Critical),
+			3 => Some(Level::Error),
+			4 => Some(Level::Warning),
+			5 => Some(Level::Notice),
+			6 => Some(Level::Info),
+			7 => Some(Level::Debug),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+	level: Level,
+	message: FixedString<LOG_LINE_LENGTH>,
+}
+
+impl Record {
+	fn from_args(level: Level, args: fmt::Arguments) -> Record {
+		let mut record = Record { level, message: FixedString::new() };
+		fmt::Write::write_fmt(&mut record, args).unwrap();
+		record
+	}
+
+	fn as_str(&self) -> &str {
+		self.message.as_str()
+	}
+
+	fn same_as(&self, other: &Record) -> bool {
+		self.level == other.level && self.message == other.message
+	}
+}
+
+impl fmt::Write for Record {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		for c in s.chars() {
+			if !self.message.push(if c == '\n' { ' ' } else { c }) {
+				break;
+			}
+		}
+		Ok(())
+	}
+}
+
+pub struct Log {
+	records: RingBuffer<Record, LOG_CAPACITY>,
+	repeated: usize,
+	window_start: u32,
+	window_count: usize,
+	suppressed: usize,
+}
+
+impl Log {
+	fn emit(&mut self, record: Record) {
+		use core::fmt::Write;
+		self.records.push(record);
+		DEBUG.lock().write_fmt(format_args!("[{}] {}\n", record.level.tag(), record.as_str())).unwrap();
+	}
+
+	fn submit(&mut self, record: Record) {
+		if self.get_recent(0).is_some_and(|last| last.same_as(&record)) {
+			self.repeated += 1;
+			return;
+		}
+		if self.repeated > 0 {
+			let last_level = self.get_recent(0).map_or(record.level, |last| last.level);
+			let repeated = self.repeated;
+			self.repeated = 0;
+			self.emit(Record::from_args(last_level, format_args!("last message repeated {} times", repeated)));
+		}
+		if self.rate_limited() {
+			self.suppressed += 1;
+			return;
+		}
+		self.emit(record);
+	}
+
+	fn rate_limited(&mut self) -> bool {
+		let now = TICKS.load(Ordering::SeqCst);
+		if now.wrapping_sub(self.window_start) >= TICKS_PER_SECOND {
+			self.window_start = now;
+			self.window_count = 0;
+			if self.suppressed > 0 {
+				let suppressed = self.suppressed;
+				self.suppressed = 0;
+				self.window_count += 1;
+				self.emit(Record::from_args(Level::Warning, format_args!("{} messages suppressed by rate limit", suppressed)));
+			}
+		}
+
+		let limit = RATE_LIMIT.load(Ordering::SeqCst);
+		if limit != 0 && self.window_count >= limit {
+			return true;
+		}
+		self.window_count += 1;
+		false
+	}
+
+	/// Returns the `index`-th most recent record, 0 being the newest.
+	fn get_recent(&self, index: usize) -> Option<&Record> {
+		self.records.recent(index)
+	}
+}
+
+/// Prints the last `count` records on the console, oldest first, each in
+/// its level's color. Gives up rather than wait if the log is locked, since
+/// the panic handler calls it.
+pub fn print_recent(count: usize) {
+	let log = match LOG.try_lock() {
+		Some(log) => log,
+		None => return println!("(log locked)"),
+	};
+	for record in log.records.iter().skip(log.records.len().saturating_sub(count)) {
+		video_graphics_array::with_foreground(record.level.color(), || {
+			println!("[{}] {}", record.level.tag(), record.as_str());
+		});
+	}
+}
+
+struct LogViewer {
+	scroll: usize,
+	filter: Level,
+}
+
+pub static LOG: Mutex<Log> = Mutex::new(Log {
+	records: RingBuffer::new(),
+	repeated: 0,
+	window_start: 0,
+	window_count: 0,
+	suppressed: 0,
+});
+
+static VIEWER: Mutex<LogViewer> = Mutex::new(LogViewer {
+	scroll: 0,
+	filter: Level::Debug,
+});
+
+pub fn log(level: Level, args: fmt::Arguments) {
+	if level as u8 > LOG_LEVEL.load(Ordering::SeqCst) {
+		return;
+	}
+	let record = Record::from_args(level, args);
+
+	{
+		let _guard = interrupts::InterruptGuard::new();
+		LOG.lock().submit(record);
+	}
+	LOG_UPDATED.store(true, Ordering::SeqCst);
+}
+
+/// Called from the main loop: refreshes the log screen if records arrived
+/// since the last call, or flags it as dirty when it is not displayed.
+pub fn process_pending() {
+	crate::interrupts::report_over_budget();
+	if !LOG_UPDATED.swap(false, Ordering::SeqCst) {
+		return;
+	}
+	if WRITER.lock().current_display == LOG_SCREEN {
+		render();
+	} else {
+		video_graphics_array::mark_dirty(LOG_SCREEN);
+	}
+}
+
+pub fn render() {
+	let _guard = interrupts::InterruptGuard::new();
+	let log = LOG.lock();
+	let viewer = VIEWER.lock();
+	let mut writer = WRITER.lock();
+
+	let mut row = VGA_LAST_LINE;
+	let mut skipped = 0;
+	let mut index = 0;
+	while row > VGA_FIRST_LINE {
+		let record = match log.get_recent(index) {
+			Some(record) => record,
+			None => break,
+		};
+		index += 1;
+		if record.level > viewer.filter {
+			continue;
+		}
+		if skipped < viewer.scroll {
+			skipped += 1;
+			continue;
+		}
+		row -= 1;
+		let mut line = FixedString::<VGA_COLUMNS>::new();
+		let _ = fmt::Write::write_fmt(&mut line, format_args!("{:>6}  {}", record.level.tag(), record.as_str()));
+		writer.write_row(row, line.as_bytes(), record.level.color());
+	}
+	while row > VGA_FIRST_LINE {
+		row -= 1;
+		writer.write_row(row, b"", ColorCode::LightGray);
+	}
+
+	let mut footer = FixedString::<VGA_COLUMNS>::new();
+	let _ = fmt::Write::write_fmt(&mut footer, format_args!(
+		" level <= {}  scroll {}  [PgUp/PgDn] scroll  [0-7] filter", viewer.filter.tag(), viewer.scroll
+	));
+	writer.write_row(VGA_LAST_LINE, footer.as_bytes(), ColorCode::White);
+	writer.update_cursor(VGA_LAST_LINE, VGA_COLUMNS - 1);
+}
+
+pub fn handle_key(scancode: u8, c: u8) {
+	match scancode {
+		0x49 => scroll(LOG_ROWS as isize),
+		0x51 => scroll(-(LOG_ROWS as isize)),
+		0x48 => scroll(1),
+		0x50 => scroll(-1),
+		_ => match c {
+			b'0'..=b'7' => {
+				if let Some(level) = Level::from_u8(c - b'0') {
+					let mut viewer = VIEWER.lock();
+					viewer.filter = level;
+					viewer.scroll = 0;
+				}
+			}
+			_ => return,
+		},
+	}
+	render();
+}
+
+fn scroll(lines: isize) {
+	let count = LOG.lock().records.len();
+	let mut viewer = VIEWER.lock();
+	let scroll = viewer.scroll as isize + lines;
+	viewer.scroll = scroll.clamp(0, count.saturating_sub(1) as isize) as usize;
+}
@@ -0,0 +1,134 @@
+const FRAME_MAGIC: u32 = 0x184d2204;
+const FLAG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLAG_CONTENT_SIZE: u8 = 1 << 3;
+const FLAG_DICTIONARY_ID: u8 = 1 << 0;
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+const MIN_MATCH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4Error {
+	Truncated,
+	OutputTooSmall,
+	BadOffset,
+	BadFrame,
+}
+
+struct Reader<'a> {
+	input: &'a [u8],
+	position: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn byte(&mut self) -> Result<u8, Lz4Error> {
+		let byte = *self.input.get(self.position).ok_or(Lz4Error::Truncated)?;
+		self.position += 1;
+		Ok(byte)
+	}
+
+	fn bytes(&mut self, count: usize) -> Result<&'a [u8], Lz4Error> {
+		let bytes = self.input.get(self.position..self.position + count).ok_or(Lz4Error::Truncated)?;
+		self.position += count;
+		Ok(bytes)
+	}
+
+	fn u32(&mut self) -> Result<u32, Lz4Error> {
+		let bytes = self.bytes(4)?;
+		Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+
+	fn is_empty(&self) -> bool {
+		self.position >= self.input.len()
+	}
+
+	/// Lengths of 15 go on in the following bytes, each 255 meaning more.
+	fn length(&mut self, base: usize) -> Result<usize, Lz4Error> {
+		let mut length = base;
+		if base == 15 {
+			loop {
+				let byte = self.byte()?;
+				length += byte as usize;
+				if byte != 255 {
+					break;
+				}
+			}
+		}
+		Ok(length)
+	}
+}
+
+/// Decodes one raw LZ4 block into `output`, returning the bytes written.
+pub fn decompress_block(input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+	let mut reader = Reader { input, position: 0 };
+	let mut written = 0;
+	while !reader.is_empty() {
+		let token = reader.byte()?;
+
+		let literals = reader.length((token >> 4) as usize)?;
+		let source = reader.bytes(literals)?;
+		output.get_mut(written..written + literals).ok_or(Lz4Error::OutputTooSmall)?.copy_from_slice(source);
+		written += literals;
+		// The last sequence has literals only
+		if reader.is_empty() {
+			break;
+		}
+
+		let offset = u16::from_le_bytes([reader.byte()?, reader.byte()?]) as usize;
+		if offset == 0 || offset > written {
+			return Err(Lz4Error::BadOffset);
+		}
+		let length = reader.length((token & 0xf) as usize)? + MIN_MATCH;
+		if written + length > output.len() {
+			return Err(Lz4Error::OutputTooSmall);
+		}
+		// Byte by byte: the match may overlap what it is producing
+		for _ in 0..length {
+			output[written] = output[written - offset];
+			written += 1;
+		}
+	}
+	Ok(written)
+}
+
+pub fn is_frame(input: &[u8]) -> bool {
+	input.len() >= 4 && u32::from_le_bytes([input[0], input[1], input[2], input[3]]) == FRAME_MAGIC
+}
+
+/// Decodes an LZ4 frame (the `lz4` tool's format) into `output`. Header,
+/// block and content checksums are skipped, not verified.
+pub fn decompress_frame(input: &[u8], output: &mut [u8]) -> Result<usize, Lz4Error> {
+	let mut reader = Reader { input, position: 0 };
+	if reader.u32()? != FRAME_MAGIC {
+		return Err(Lz4Error::BadFrame);
+	}
+	let flags = reader.byte()?;
+	if flags >> 6 != 0b01 {
+		return Err(Lz4Error::BadFrame);
+	}
+	reader.byte()?;
+	if flags & FLAG_CONTENT_SIZE != 0 {
+		reader.bytes(8)?;
+	}
+	if flags & FLAG_DICTIONARY_ID != 0 {
+		reader.bytes(4)?;
+	}
+	reader.byte()?;
+
+	let mut written = 0;
+	loop {
+		let size = reader.u32()?;
+		if size == 0 {
+			break;
+		}
+		let block = reader.bytes((size & !BLOCK_UNCOMPRESSED) as usize)?;
+		if size & BLOCK_UNCOMPRESSED != 0 {
+			output.get_mut(written..written + block.len()).ok_or(Lz4Error::OutputTooSmall)?.copy_from_slice(block);
+			written += block.len();
+		} else {
+			written += decompress_block(block, &mut output[written..])?;
+		}
+		if flags & FLAG_BLOCK_CHECKSUM != 0 {
+			reader.bytes(4)?;
+		}
+	}
+	Ok(written)
+}
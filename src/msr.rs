@@ -0,0 +1,85 @@
+use core::arch::asm;
+use crate::librs::cpuid;
+
+const CPUID_MSR: u32 = 1 << 5;
+
+/// Feature a register depends on: cpuid leaf, output register (0 eax,
+/// 1 ecx, 2 edx) and bit.
+type Feature = (u32, usize, u32);
+
+pub struct Msr {
+	pub index: u32,
+	pub name: &'static str,
+	requires: Option<Feature>,
+}
+
+/// Only these are read: rdmsr of a register the CPU does not implement
+/// raises a #GP the handler would return into forever, and nothing here
+/// writes MSRs at all.
+pub static ALLOWED: [Msr; 12] = [
+	Msr { index: 0x10, name: "IA32_TIME_STAMP_COUNTER", requires: Some((1, 2, 1 << 4)) },
+	Msr { index: 0x1b, name: "IA32_APIC_BASE", requires: Some((1, 2, 1 << 9)) },
+	Msr { index: 0xfe, name: "IA32_MTRRCAP", requires: Some((1, 2, 1 << 12)) },
+	Msr { index: 0x174, name: "IA32_SYSENTER_CS", requires: Some((1, 2, 1 << 11)) },
+	Msr { index: 0x175, name: "IA32_SYSENTER_ESP", requires: Some((1, 2, 1 << 11)) },
+	Msr { index: 0x176, name: "IA32_SYSENTER_EIP", requires: Some((1, 2, 1 << 11)) },
+	Msr { index: 0x19c, name: "IA32_THERM_STATUS", requires: Some((6, 0, 1 << 0)) },
+	Msr { index: 0x1a0, name: "IA32_MISC_ENABLE", requires: None },
+	Msr { index: 0x1a2, name: "IA32_TEMPERATURE_TARGET", requires: Some((6, 0, 1 << 0)) },
+	Msr { index: 0x1b1, name: "IA32_PACKAGE_THERM_STATUS", requires: Some((6, 0, 1 << 6)) },
+	Msr { index: 0x277, name: "IA32_PAT", requires: Some((1, 2, 1 << 16)) },
+	Msr { index: 0xc000_0080, name: "IA32_EFER", requires: Some((0x8000_0001, 2, 1 << 20)) },
+];
+
+const THERM_STATUS: u32 = 0x19c;
+const TEMPERATURE_TARGET: u32 = 0x1a2;
+
+pub fn supported() -> bool {
+	cpuid(1).2 & CPUID_MSR != 0
+}
+
+fn has_feature((leaf, register, bit): Feature) -> bool {
+	let highest = cpuid(leaf & 0x8000_0000).0;
+	if leaf > highest {
+		return false;
+	}
+	let (eax, ecx, edx) = cpuid(leaf);
+	[eax, ecx, edx][register] & bit != 0
+}
+
+pub fn find(index: u32) -> Option<&'static Msr> {
+	ALLOWED.iter().find(|msr| msr.index == index)
+}
+
+impl Msr {
+	pub fn available(&self) -> bool {
+		supported() && self.requires.is_none_or(has_feature)
+	}
+
+	/// None when the CPU does not have the register.
+	pub fn read(&self) -> Option<u64> {
+		if !self.available() {
+			return None;
+		}
+		Some(unsafe { rdmsr(self.index) })
+	}
+}
+
+unsafe fn rdmsr(index: u32) -> u64 {
+	let (low, high): (u32, u32);
+	asm!("rdmsr", in("ecx") index, out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+	(high as u64) << 32 | low as u64
+}
+
+/// Core temperature in degrees Celsius from the digital thermal sensor,
+/// read as an offset below the TjMax of IA32_TEMPERATURE_TARGET.
+pub fn temperature() -> Option<u32> {
+	let status = find(THERM_STATUS)?.read()?;
+	if status & (1 << 31) == 0 {
+		return None;
+	}
+	let target = find(TEMPERATURE_TARGET)?.read()?;
+	let tj_max = (target >> 16) as u32 & 0xff;
+	let readout = (status >> 16) as u32 & 0x7f;
+	tj_max.checked_sub(readout)
+}
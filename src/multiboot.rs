@@ -0,0 +1,176 @@
+use core::fmt;
+use core::ptr::read_unaligned;
+use crate::sync::Mutex;
+use crate::collections::FixedVec;
+
+pub const TAG_CMDLINE: u32 = 1;
+pub const TAG_BOOT_LOADER: u32 = 2;
+pub const TAG_MODULE: u32 = 3;
+pub const TAG_BASIC_MEMINFO: u32 = 4;
+pub const TAG_BOOTDEV: u32 = 5;
+pub const TAG_MEMORY_MAP: u32 = 6;
+const TAG_END: u32 = 0;
+
+pub const MEMORY_AVAILABLE: u32 = 1;
+
+/// Type and size, in front of the info structure and of every tag.
+const HEADER_SIZE: u32 = 8;
+const MEMORY_MAP_ENTRY_SIZE: u32 = 24;
+/// A walk that has not met the end tag by then is going through garbage.
+const MAX_TAGS: usize = 256;
+const MAX_UNKNOWN_TAGS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultibootError {
+	Misaligned(u32),
+	BadTotalSize(u32),
+	/// A tag whose size is below its header or runs past the structure.
+	BadTag { address: u32, size: u32 },
+	TooManyTags,
+	MissingEnd,
+}
+
+impl fmt::Display for MultibootError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			MultibootError::Misaligned(address) => write!(f, "info at {:#x} is not 8-byte aligned", address),
+			MultibootError::BadTotalSize(size) => write!(f, "bad total size {}", size),
+			MultibootError::BadTag { address, size } => write!(f, "tag at {:#x} has bad size {}", address, size),
+			MultibootError::TooManyTags => write!(f, "more than {} tags", MAX_TAGS),
+			MultibootError::MissingEnd => write!(f, "no end tag"),
+		}
+	}
+}
+
+/// A tag checked to lie inside the info structure.
+#[derive(Debug, Clone, Copy)]
+pub struct Tag {
+	pub typ: u32,
+	pub address: u32,
+	pub size: u32,
+}
+
+impl Tag {
+	/// The u32 `offset` bytes into the tag, None past its end.
+	pub fn u32_at(&self, offset: u32) -> Option<u32> {
+		if offset + 4 > self.size {
+			return None;
+		}
+		Some(unsafe { read_unaligned((self.address + offset) as *const u32) })
+	}
+
+	/// Zero-terminated string from `offset` to the end of the tag, "" when
+	/// it is not UTF-8.
+	pub fn string(&self, offset: u32) -> &'static str {
+		if offset >= self.size {
+			return "";
+		}
+		let bytes = unsafe { core::slice::from_raw_parts((self.address + offset) as *const u8, (self.size - offset) as usize) };
+		core::str::from_utf8(bytes).unwrap_or("").trim_end_matches('\0')
+	}
+
+	/// Base, length and type of each entry of a memory map tag. Nothing when
+	/// the entry size is too small or unaligned to be trusted.
+	pub fn memory_map(&self) -> impl Iterator<Item = (u64, u64, u32)> {
+		let entry_size = self.u32_at(8).filter(|&size| size >= MEMORY_MAP_ENTRY_SIZE && size % 8 == 0);
+		let count = entry_size.map_or(0, |entry_size| self.size.saturating_sub(16) / entry_size);
+		let first = self.address + 16;
+		(0..count).map(move |index| {
+			let entry = first + index * entry_size.unwrap_or(0);
+			unsafe {
+				(
+					read_unaligned(entry as *const u64),
+					read_unaligned((entry + 8) as *const u64),
+					read_unaligned((entry + 16) as *const u32),
+				)
+			}
+		})
+	}
+}
+
+/// Walks the tags of the info structure at `info`, checking each one lies
+/// inside it. An error ends the walk.
+pub struct Tags {
+	next: u32,
+	end: u32,
+	count: usize,
+	error: Option<MultibootError>,
+	done: bool,
+}
+
+pub fn tags(info: u32) -> Tags {
+	let mut tags = Tags { next: info.saturating_add(HEADER_SIZE), end: info, count: 0, error: None, done: false };
+	if info % 8 != 0 {
+		tags.error = Some(MultibootError::Misaligned(info));
+		return tags;
+	}
+	let total_size = unsafe { read_unaligned(info as *const u32) };
+	match info.checked_add(total_size) {
+		Some(end) if total_size >= 2 * HEADER_SIZE => tags.end = end,
+		_ => tags.error = Some(MultibootError::BadTotalSize(total_size)),
+	}
+	tags
+}
+
+impl Tags {
+	fn fail(&mut self, error: MultibootError) -> Option<Result<Tag, MultibootError>> {
+		self.done = true;
+		Some(Err(error))
+	}
+}
+
+impl Iterator for Tags {
+	type Item = Result<Tag, MultibootError>;
+
+	fn next(&mut self) -> Option<Result<Tag, MultibootError>> {
+		if self.done {
+			return None;
+		}
+		if let Some(error) = self.error {
+			return self.fail(error);
+		}
+		if self.end.saturating_sub(self.next) < HEADER_SIZE {
+			return self.fail(MultibootError::MissingEnd);
+		}
+		if self.count == MAX_TAGS {
+			return self.fail(MultibootError::TooManyTags);
+		}
+		let address = self.next;
+		let (typ, size) = unsafe { (read_unaligned(address as *const u32), read_unaligned((address + 4) as *const u32)) };
+		if size < HEADER_SIZE || size > self.end - address {
+			return self.fail(MultibootError::BadTag { address, size });
+		}
+		if typ == TAG_END {
+			self.done = true;
+			return None;
+		}
+		self.count += 1;
+		// Tags start on 8-byte boundaries; the end may round past `end`, which
+		// the next call reports as a missing end tag
+		self.next = address.saturating_add((size + 7) & !7);
+		Some(Ok(Tag { typ, address, size }))
+	}
+}
+
+/// Tags the kernel has no use for yet, kept for code that will (framebuffer,
+/// ACPI tables).
+static UNKNOWN_TAGS: Mutex<FixedVec<Tag, MAX_UNKNOWN_TAGS>> = Mutex::new(FixedVec::new());
+
+pub fn keep_unknown(tag: Tag) {
+	if UNKNOWN_TAGS.lock().push(tag).is_err() {
+		log!(Warning, "multiboot: no room left for tag {} at {:#x}", tag.typ, tag.address);
+	}
+}
+
+pub fn kept_tag(typ: u32) -> Option<Tag> {
+	UNKNOWN_TAGS.lock().iter().find(|tag| tag.typ == typ).copied()
+}
+
+/// /proc/multiboot: the tags kept by `keep_unknown`.
+pub fn write_unknown_tags(writer: &mut dyn fmt::Write) -> fmt::Result {
+	writeln!(writer, "TYPE  ADDRESS     SIZE")?;
+	for tag in UNKNOWN_TAGS.lock().iter() {
+		writeln!(writer, "{:4}  {:#010x}  {}", tag.typ, tag.address, tag.size)?;
+	}
+	Ok(())
+}
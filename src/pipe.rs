@@ -0,0 +1,123 @@
+use crate::sync::Mutex;
+use crate::vfs::{ DirEntry, FileSystem, FsError, Node, NodeKind };
+
+const MAX_PIPES: usize = 4;
+const PIPE_CAPACITY: usize = 4096;
+
+/// Bytes in flight between the two ends. There is nothing to wait on until
+/// the scheduler lands, so a read of an empty pipe or a write to a full one
+/// fails with WouldBlock instead of sleeping.
+#[derive(Clone, Copy)]
+struct Pipe {
+	buffer: [u8; PIPE_CAPACITY],
+	head: usize,
+	length: usize,
+	readers: usize,
+	writers: usize,
+	used: bool,
+}
+
+impl Pipe {
+	const fn new() -> Pipe {
+		Pipe { buffer: [0; PIPE_CAPACITY], head: 0, length: 0, readers: 0, writers: 0, used: false }
+	}
+
+	fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError> {
+		if self.length == 0 {
+			return if self.writers == 0 { Ok(0) } else { Err(FsError::WouldBlock) };
+		}
+		let count = buffer.len().min(self.length);
+		for byte in buffer[..count].iter_mut() {
+			*byte = self.buffer[self.head];
+			self.head = (self.head + 1) % PIPE_CAPACITY;
+		}
+		self.length -= count;
+		Ok(count)
+	}
+
+	fn write(&mut self, buffer: &[u8]) -> Result<usize, FsError> {
+		if self.readers == 0 {
+			return Err(FsError::BrokenPipe);
+		}
+		let count = buffer.len().min(PIPE_CAPACITY - self.length);
+		if count == 0 && !buffer.is_empty() {
+			return Err(FsError::WouldBlock);
+		}
+		for &byte in &buffer[..count] {
+			self.buffer[(self.head + self.length) % PIPE_CAPACITY] = byte;
+			self.length += 1;
+		}
+		Ok(count)
+	}
+}
+
+static PIPES: Mutex<[Pipe; MAX_PIPES]> = Mutex::new([Pipe::new(); MAX_PIPES]);
+
+/// Inode 2n is the read end of pipe n and 2n + 1 its write end.
+fn end_node(pipe: usize, write: bool) -> Node {
+	Node { inode: pipe * 2 + write as usize, kind: NodeKind::Device, size: 0 }
+}
+
+/// Nodes of a fresh pipe's read and write ends. The pipe is freed once
+/// every descriptor to both ends has been closed.
+pub fn create() -> Result<(Node, Node), FsError> {
+	let mut pipes = PIPES.lock();
+	let index = pipes.iter().position(|pipe| !pipe.used).ok_or(FsError::TooManyOpenFiles)?;
+	let pipe = &mut pipes[index];
+	(pipe.head, pipe.length, pipe.used) = (0, 0, true);
+	Ok((end_node(index, false), end_node(index, true)))
+}
+
+/// Only reachable through descriptors handed out by `create`.
+pub struct PipeFs;
+
+pub static PIPEFS: PipeFs = PipeFs;
+
+impl FileSystem for PipeFs {
+	fn name(&self) -> &'static str {
+		"pipefs"
+	}
+
+	fn lookup(&self, _path: &str) -> Result<Node, FsError> {
+		Err(FsError::NotFound)
+	}
+
+	fn read(&self, node: Node, _offset: usize, buffer: &mut [u8]) -> Result<usize, FsError> {
+		if node.inode % 2 == 1 {
+			return Err(FsError::BadDescriptor);
+		}
+		PIPES.lock()[node.inode / 2].read(buffer)
+	}
+
+	fn write(&self, node: Node, _offset: usize, buffer: &[u8]) -> Result<usize, FsError> {
+		if node.inode % 2 == 0 {
+			return Err(FsError::BadDescriptor);
+		}
+		PIPES.lock()[node.inode / 2].write(buffer)
+	}
+
+	fn read_dir(&self, _node: Node, _index: usize) -> Result<Option<DirEntry>, FsError> {
+		Err(FsError::NotADirectory)
+	}
+
+	fn opened(&self, node: Node) {
+		let pipe = &mut PIPES.lock()[node.inode / 2];
+		if node.inode % 2 == 0 {
+			pipe.readers += 1;
+		} else {
+			pipe.writers += 1;
+		}
+	}
+
+	fn closed(&self, node: Node) {
+		let pipe = &mut PIPES.lock()[node.inode / 2];
+		if node.inode % 2 == 0 {
+			pipe.readers -= 1;
+		} else {
+			pipe.writers -= 1;
+		}
+		if pipe.readers == 0 && pipe.writers == 0 {
+			pipe.used = false;
+		}
+	}
+}
@@ -0,0 +1,39 @@
+use core::arch::asm;
+use core::sync::atomic::Ordering;
+use crate::interrupts::{ self, InterruptIndex, PIC_1_OFFSET, TICKS, TICKS_PER_SECOND };
+use crate::{ debug, driver, keyboard, tsc };
+
+/// Lines that end a suspend. The RTC's periodic interrupt is left masked,
+/// it would wake the machine twice a second.
+const WAKE_LINES: [InterruptIndex; 1] = [InterruptIndex::Keyboard];
+
+/// Halts with every line but the wake sources masked, so the timer tick,
+/// and with it the scheduler and the bell, stop until a key is pressed.
+/// Drivers are suspended first and resumed after. The key only wakes the
+/// machine and is not typed. Returns the time spent suspended in
+/// milliseconds, which is added back to TICKS.
+pub fn suspend() -> u64 {
+	let wake = WAKE_LINES.map(|index| index.as_usize() - PIC_1_OFFSET as usize);
+	let mut discarded = [0; 16];
+	log!(Notice, "power: suspending, press a key to resume");
+	driver::suspend_all();
+	debug::flush();
+	let _guard = interrupts::InterruptGuard::new();
+	interrupts::mask_all_but(&wake);
+	let start = tsc::read();
+	while keyboard::read_scancodes(&mut discarded) == 0 {
+		// sti takes effect after the next instruction, so no interrupt can
+		// slip in between and leave hlt waiting for a key already pressed
+		unsafe {
+			asm!("sti", "hlt", "cli", options(nomem, nostack));
+		}
+	}
+	let cycles = tsc::read() - start;
+	interrupts::restore_irq_masks();
+	driver::resume_all();
+	let khz = tsc::cached_frequency_khz().max(1) as u64;
+	let milliseconds = cycles / khz;
+	TICKS.fetch_add((milliseconds * TICKS_PER_SECOND as u64 / 1000) as u32, Ordering::SeqCst);
+	log!(Notice, "power: resumed after {} ms", milliseconds);
+	milliseconds
+}
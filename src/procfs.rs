@@ -0,0 +1,148 @@
+use core::fmt::{ self, Write };
+use core::sync::atomic::{ AtomicU32, Ordering };
+use crate::sync::Mutex;
+use crate::args;
+use crate::collections::{ FixedString, FixedVec };
+use crate::vfs::{ DirEntry, FileSystem, FsError, Node, NodeKind };
+
+const PROC_BUFFER_SIZE: usize = 2048;
+const ROOT_INODE: usize = usize::MAX;
+
+/// Memory the boot loader reported, in KB.
+static MEMORY_LOWER: AtomicU32 = AtomicU32::new(0);
+static MEMORY_UPPER: AtomicU32 = AtomicU32::new(0);
+
+const MAX_BAD_RAM_RANGES: usize = 8;
+/// Start and length in bytes of the ranges given by `badram=`.
+static BAD_RAM: Mutex<FixedVec<(usize, usize), MAX_BAD_RAM_RANGES>> = Mutex::new(FixedVec::new());
+
+struct ProcFile {
+	name: &'static str,
+	generate: fn(&mut dyn Write) -> fmt::Result,
+}
+
+static FILES: [ProcFile; 10] = [
+	ProcFile { name: "meminfo", generate: write_meminfo },
+	ProcFile { name: "interrupts", generate: crate::interrupts::write_irq_table },
+	ProcFile { name: "uptime", generate: crate::interrupts::write_uptime },
+	ProcFile { name: "clock", generate: crate::timer::write_clock },
+	ProcFile { name: "tasks", generate: crate::task::write_tasks },
+	ProcFile { name: "drivers", generate: crate::driver::write_drivers },
+	ProcFile { name: "idle", generate: crate::idle::write_stats },
+	ProcFile { name: "multiboot", generate: crate::multiboot::write_unknown_tags },
+	ProcFile { name: "acpi", generate: crate::acpi::write_tables },
+	ProcFile { name: "version", generate: |writer| crate::shell::write_uname(writer, [true; 5]) },
+];
+
+pub fn set_boot_memory(lower: u32, upper: u32) {
+	MEMORY_LOWER.store(lower, Ordering::Relaxed);
+	MEMORY_UPPER.store(upper, Ordering::Relaxed);
+}
+
+/// Takes the `badram=start:length[,start:length...]` ranges of the command
+/// line out of the free memory. A range over the kernel image is reported,
+/// there is nothing to do about it.
+pub fn set_bad_ram(cmdline: &str) {
+	let list = match args::cmdline_option(cmdline, "badram") {
+		Some(list) => list,
+		None => return,
+	};
+	let (kernel_start, kernel_end) = crate::librs::kernel_image();
+	let mut ranges = BAD_RAM.lock();
+	for range in list.split(',') {
+		let parsed = range.split_once(':').and_then(|(start, length)| Some((args::parse_int(start)?, args::parse_int(length)?)));
+		let (start, length) = match parsed {
+			Some((start, length)) if length > 0 => (start, length),
+			_ => {
+				log!(Warning, "badram: cannot parse {}, expected start:length", range);
+				continue;
+			}
+		};
+		let end = start.saturating_add(length);
+		if start < kernel_end && kernel_start < end {
+			log!(Error, "badram: {:#x}-{:#x} overlaps the kernel image", start, end - 1);
+		} else {
+			log!(Notice, "badram: reserving {:#x}-{:#x}", start, end - 1);
+		}
+		if ranges.push((start, length)).is_err() {
+			log!(Warning, "badram: more than {} ranges, ignoring {}", MAX_BAD_RAM_RANGES, range);
+		}
+	}
+}
+
+fn bad_ram_kb() -> u32 {
+	BAD_RAM.lock().iter().map(|&(_, length)| length.div_ceil(1024) as u32).sum()
+}
+
+/// Total memory and what is left of it once the kernel image is loaded and
+/// bad RAM is set aside, in KB. Nothing else is allocated until the heap
+/// comes back.
+pub fn memory_kb() -> (u32, u32) {
+	let total = MEMORY_LOWER.load(Ordering::Relaxed) + MEMORY_UPPER.load(Ordering::Relaxed);
+	(total, total.saturating_sub(kernel_kb()).saturating_sub(bad_ram_kb()))
+}
+
+fn kernel_kb() -> u32 {
+	let (start, end) = crate::librs::kernel_image();
+	((end - start) / 1024) as u32
+}
+
+fn write_meminfo(writer: &mut dyn Write) -> fmt::Result {
+	let lower = MEMORY_LOWER.load(Ordering::Relaxed);
+	let upper = MEMORY_UPPER.load(Ordering::Relaxed);
+	let (total, free) = memory_kb();
+	writeln!(writer, "MemTotal:  {} kB", total)?;
+	writeln!(writer, "MemFree:   {} kB", free)?;
+	writeln!(writer, "MemLower:  {} kB", lower)?;
+	writeln!(writer, "MemUpper:  {} kB", upper)?;
+	writeln!(writer, "Kernel:    {} kB", kernel_kb())?;
+	writeln!(writer, "BadRAM:    {} kB", bad_ram_kb())
+}
+
+/// Files are generated again on every read, so their size is unknown (0)
+/// and a reader just goes on until it gets nothing back.
+pub struct ProcFs;
+
+pub static PROCFS: ProcFs = ProcFs;
+
+fn file_node(inode: usize) -> Node {
+	Node { inode, kind: NodeKind::File, size: 0 }
+}
+
+impl FileSystem for ProcFs {
+	fn name(&self) -> &'static str {
+		"procfs"
+	}
+
+	fn lookup(&self, path: &str) -> Result<Node, FsError> {
+		if path.is_empty() {
+			return Ok(Node { inode: ROOT_INODE, kind: NodeKind::Directory, size: 0 });
+		}
+		FILES.iter().position(|file| file.name == path).map(file_node).ok_or(FsError::NotFound)
+	}
+
+	fn read(&self, node: Node, offset: usize, buffer: &mut [u8]) -> Result<usize, FsError> {
+		let file = FILES.get(node.inode).ok_or(FsError::NotFound)?;
+		// Output of a generator is cut at PROC_BUFFER_SIZE
+		let mut contents = FixedString::<PROC_BUFFER_SIZE>::new();
+		let _ = (file.generate)(&mut contents);
+		let data = contents.as_bytes().get(offset..).unwrap_or(&[]);
+		let count = data.len().min(buffer.len());
+		buffer[..count].copy_from_slice(&data[..count]);
+		Ok(count)
+	}
+
+	fn write(&self, _node: Node, _offset: usize, _buffer: &[u8]) -> Result<usize, FsError> {
+		Err(FsError::ReadOnly)
+	}
+
+	fn read_dir(&self, node: Node, index: usize) -> Result<Option<DirEntry>, FsError> {
+		if node.inode != ROOT_INODE {
+			return Err(FsError::NotADirectory);
+		}
+		match FILES.get(index) {
+			Some(file) => Ok(Some(DirEntry::new(file.name, file_node(index))?)),
+			None => Ok(None),
+		}
+	}
+}
@@ -1,21 +1,47 @@
+use core::sync::atomic::{ AtomicBool, Ordering };
 use lazy_static::lazy_static;
-use spin::Mutex;
+use crate::sync::Mutex;
 use crate::video_graphics_array::{ WRITER, VGA_COLUMNS, VGA_LAST_LINE };
 use crate::shell::readline;
+use crate::bell;
 
 pub static PROMPT_STRING: &str = "$> ";
+pub static CONTINUATION_STRING: &str = " > ";
 pub static PROMPT_LENGTH: usize = PROMPT_STRING.len();
 
+pub const MAX_LINE_LENGTH: usize = 256;
+pub const MAX_COMMAND_LENGTH: usize = 256;
+
+const VISIBLE_COLUMNS: usize = VGA_COLUMNS - 1;
+const SCROLL_MARGIN: usize = 8;
+
 lazy_static! {
 	pub static ref PROMPT: Mutex<Prompt> = Mutex::new(Prompt {
-		buffer: [0; VGA_COLUMNS],
+		buffer: [0; MAX_LINE_LENGTH],
 		length: 0,
+		cursor: 0,
+		scroll: 0,
+		command: [0; MAX_COMMAND_LENGTH],
+		command_length: 0,
+		command_ready: false,
 	});
 }
 
+/// Set while `run_command` has a command running, PROMPT unlocked.
+static COMMAND_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The line being edited, prompt string included. Only a window of
+/// VISIBLE_COLUMNS starting at `scroll` is drawn, with '<' and '>' marking
+/// text hidden on either side.
 pub struct Prompt {
-	buffer: [u8; VGA_COLUMNS],
+	buffer: [u8; MAX_LINE_LENGTH],
 	pub length: usize,
+	cursor: usize,
+	scroll: usize,
+	command: [u8; MAX_COMMAND_LENGTH],
+	command_length: usize,
+	/// Enter finished `command`, for `run_command` to pick up.
+	command_ready: bool,
 }
 
 impl Prompt {
@@ -28,40 +54,73 @@ impl Prompt {
 	pub fn insert_char(&mut self, c: u8, insert: bool) {
 		if c == b'\n' {
 			println!();
-			readline(core::str::from_utf8(&self.buffer[PROMPT_LENGTH..self.length]).unwrap());
-			self.init();
-			return;
-		}
-
-		if self.length == VGA_COLUMNS - 1 {
+			let line = self.buffer;
+			let mut line = &line[PROMPT_LENGTH..self.length];
+			let continued = line.last() == Some(&b'\\');
+			if continued {
+				line = &line[..line.len() - 1];
+			}
+			self.append_command(line);
+			if continued {
+				self.start_line(CONTINUATION_STRING);
+				return;
+			}
+			self.command_ready = true;
 			return;
 		}
 
-		let column_position = WRITER.lock().column_position;
-		if !insert {
-			for i in (column_position..self.length).rev() {
-				self.buffer[i + 1] = self.buffer[i];
+		if !insert || self.cursor == self.length {
+			if self.length == MAX_LINE_LENGTH {
+				bell::prompt_alert();
+				return;
 			}
-		}
-
-		if !insert || column_position == self.length {
+			self.buffer.copy_within(self.cursor..self.length, self.cursor + 1);
 			self.length += 1;
 		}
 
-		self.buffer[column_position] = c;
+		self.buffer[self.cursor] = c;
+		self.cursor += 1;
 		self.update_line();
-		WRITER.lock().move_cursor(1);
 	}
 
 	pub fn remove_char(&mut self) {
-		let column_position = WRITER.lock().column_position - 1;
-		for i in column_position..self.length {
-			self.buffer[i] = self.buffer[i + 1];
-		}
-
+		self.buffer.copy_within(self.cursor..self.length, self.cursor - 1);
+		self.cursor -= 1;
 		self.length -= 1;
 		self.update_line();
-		WRITER.lock().move_cursor(-1);
+	}
+
+	/// Removes `start..self.cursor`, leaving the cursor at `start`.
+	fn remove_before_cursor(&mut self, start: usize) {
+		let removed = self.cursor - start;
+		self.buffer.copy_within(self.cursor..self.length, start);
+		self.cursor = start;
+		self.length -= removed;
+		self.update_line();
+	}
+
+	/// Start of the word before the cursor, skipping the spaces in between.
+	fn previous_word(&self) -> usize {
+		let mut position = self.cursor;
+		while position > PROMPT_LENGTH && self.buffer[position - 1] == b' ' {
+			position -= 1;
+		}
+		while position > PROMPT_LENGTH && self.buffer[position - 1] != b' ' {
+			position -= 1;
+		}
+		position
+	}
+
+	/// End of the word after the cursor, skipping the spaces in between.
+	fn next_word(&self) -> usize {
+		let mut position = self.cursor;
+		while position < self.length && self.buffer[position] == b' ' {
+			position += 1;
+		}
+		while position < self.length && self.buffer[position] != b' ' {
+			position += 1;
+		}
+		position
 	}
 
 	pub fn clear(&mut self) {
@@ -69,55 +128,154 @@ impl Prompt {
 			self.buffer[i] = 0;
 		}
 		self.length = 0;
+		self.cursor = 0;
+		self.scroll = 0;
+	}
+
+	fn set_cursor(&mut self, cursor: usize) {
+		self.cursor = cursor.clamp(PROMPT_LENGTH, self.length);
+		self.update_line();
 	}
 
 	pub fn update_line(&mut self) {
-		let buffer_as_str = core::str::from_utf8(&self.buffer[..self.length]).unwrap();
-		WRITER.lock().update_line(buffer_as_str);
+		if self.cursor < self.scroll + SCROLL_MARGIN {
+			self.scroll = self.cursor.saturating_sub(SCROLL_MARGIN);
+		}
+		let right = (self.cursor + SCROLL_MARGIN).min(self.length + 1);
+		if right > self.scroll + VISIBLE_COLUMNS {
+			self.scroll = right - VISIBLE_COLUMNS;
+		}
+
+		let end = self.length.min(self.scroll + VISIBLE_COLUMNS);
+		let mut window = [0; VISIBLE_COLUMNS];
+		let width = end - self.scroll;
+		window[..width].copy_from_slice(&self.buffer[self.scroll..end]);
+		if self.scroll > 0 {
+			window[0] = b'<';
+		}
+		if end < self.length {
+			window[width - 1] = b'>';
+		}
+
+		let mut writer = WRITER.lock();
+		writer.update_line(core::str::from_utf8(&window[..width]).unwrap());
+		writer.column_position = self.cursor - self.scroll;
+		writer.update_cursor(VGA_LAST_LINE, self.cursor - self.scroll);
 	}
 
-	pub fn init(&mut self) {
+	fn append_command(&mut self, line: &[u8]) {
+		let length = line.len().min(MAX_COMMAND_LENGTH - self.command_length);
+		self.command[self.command_length..self.command_length + length].copy_from_slice(&line[..length]);
+		self.command_length += length;
+	}
+
+	fn start_line(&mut self, prompt: &str) {
 		self.clear();
 		WRITER.lock().column_position = 0;
-		self.insert_string(PROMPT_STRING);
+		self.insert_string(prompt);
+	}
+
+	pub fn init(&mut self) {
+		self.command_length = 0;
+		self.command_ready = false;
+		self.start_line(PROMPT_STRING);
 	}
 }
 
+/// Called from the shell loop: runs the command Enter finished, if any.
+/// PROMPT is not held meanwhile: a task holding a lock is never preempted,
+/// and commands may take long.
+pub fn run_command() {
+	let (command, length) = {
+		let mut prompt = PROMPT.lock();
+		if !core::mem::take(&mut prompt.command_ready) {
+			return;
+		}
+		let command = (prompt.command, prompt.command_length);
+		prompt.command_length = 0;
+		command
+	};
+	COMMAND_RUNNING.store(true, Ordering::SeqCst);
+	readline(core::str::from_utf8(&command[..length]).unwrap());
+	COMMAND_RUNNING.store(false, Ordering::SeqCst);
+	PROMPT.lock().init();
+}
+
+pub fn command_running() -> bool {
+	COMMAND_RUNNING.load(Ordering::SeqCst)
+}
+
 pub fn right_arrow() {
-	if WRITER.lock().column_position < PROMPT.lock().length {
-		WRITER.lock().move_cursor(1);
-	}
+	let mut prompt = PROMPT.lock();
+	let cursor = prompt.cursor;
+	prompt.set_cursor(cursor + 1);
 }
 
 pub fn left_arrow() {
-	if WRITER.lock().column_position > PROMPT_LENGTH {
-		WRITER.lock().move_cursor(-1);
-	}
+	let mut prompt = PROMPT.lock();
+	let cursor = prompt.cursor;
+	prompt.set_cursor(cursor.saturating_sub(1));
 }
 
 pub fn backspace() {
-	if WRITER.lock().column_position > PROMPT_LENGTH {
-		PROMPT.lock().remove_char();
+	let mut prompt = PROMPT.lock();
+	if prompt.cursor > PROMPT_LENGTH {
+		prompt.remove_char();
+	} else {
+		bell::prompt_alert();
 	}
 }
 
 pub fn tab() {
-	if WRITER.lock().column_position < VGA_COLUMNS - 4 {
-		PROMPT.lock().insert_string("    ");
+	let mut prompt = PROMPT.lock();
+	if prompt.length + 4 <= MAX_LINE_LENGTH {
+		prompt.insert_string("    ");
+	} else {
+		bell::prompt_alert();
 	}
 }
 
 pub fn end() {
-	WRITER.lock().update_cursor(VGA_LAST_LINE, PROMPT.lock().length);
+	let mut prompt = PROMPT.lock();
+	let length = prompt.length;
+	prompt.set_cursor(length);
 }
 
 pub fn home() {
-	WRITER.lock().update_cursor(VGA_LAST_LINE, PROMPT_LENGTH);
+	PROMPT.lock().set_cursor(PROMPT_LENGTH);
 }
 
 pub fn delete() {
-	if WRITER.lock().column_position < PROMPT.lock().length {
-		WRITER.lock().move_cursor(1);
-		PROMPT.lock().remove_char();
+	let mut prompt = PROMPT.lock();
+	if prompt.cursor < prompt.length {
+		prompt.cursor += 1;
+		prompt.remove_char();
+	} else {
+		bell::prompt_alert();
 	}
 }
+
+pub fn word_left() {
+	let mut prompt = PROMPT.lock();
+	let position = prompt.previous_word();
+	prompt.set_cursor(position);
+}
+
+pub fn word_right() {
+	let mut prompt = PROMPT.lock();
+	let position = prompt.next_word();
+	prompt.set_cursor(position);
+}
+
+pub fn delete_word_left() {
+	let mut prompt = PROMPT.lock();
+	let start = prompt.previous_word();
+	prompt.remove_before_cursor(start);
+}
+
+pub fn delete_word_right() {
+	let mut prompt = PROMPT.lock();
+	let start = prompt.cursor;
+	prompt.cursor = prompt.next_word();
+	prompt.remove_before_cursor(start);
+}
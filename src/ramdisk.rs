@@ -0,0 +1,111 @@
+use core::sync::atomic::{ AtomicBool, Ordering };
+use crate::sync::Mutex;
+use crate::block::{ BlockDevice, BlockError, BLOCK_SIZE };
+use crate::lz4;
+
+/// Backing store for a ramdisk created empty from `ramdisk_size=` or
+/// expanded from a compressed module, until there is an allocator to take
+/// it from.
+const RAMDISK_POOL_SIZE: usize = 1024 * 1024;
+
+static mut RAMDISK_POOL: [u8; RAMDISK_POOL_SIZE] = [0; RAMDISK_POOL_SIZE];
+static POOL_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The pool, handed out once so there is never a second reference to it.
+fn take_pool() -> Option<&'static mut [u8; RAMDISK_POOL_SIZE]> {
+	if POOL_TAKEN.swap(true, Ordering::SeqCst) {
+		log!(Error, "ramdisk: pool already in use");
+		return None;
+	}
+	Some(unsafe { &mut *core::ptr::addr_of_mut!(RAMDISK_POOL) })
+}
+
+pub static RAMDISK: Mutex<Option<RamDisk>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+	Module,
+	CompressedModule,
+	Empty,
+}
+
+pub struct RamDisk {
+	base: *mut u8,
+	size: usize,
+	pub source: Source,
+}
+
+// The memory behind a ramdisk is only reached through the RAMDISK lock.
+unsafe impl Send for RamDisk {}
+
+impl RamDisk {
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	fn block(&self, index: usize) -> Result<*mut u8, BlockError> {
+		if index >= self.block_count() {
+			return Err(BlockError::OutOfRange(index));
+		}
+		Ok(unsafe { self.base.add(index * BLOCK_SIZE) })
+	}
+}
+
+impl BlockDevice for RamDisk {
+	fn block_count(&self) -> usize {
+		self.size / BLOCK_SIZE
+	}
+
+	fn read_block(&self, index: usize, buffer: &mut [u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+		let block = self.block(index)?;
+		unsafe { core::ptr::copy_nonoverlapping(block, buffer.as_mut_ptr(), BLOCK_SIZE) };
+		Ok(())
+	}
+
+	fn write_block(&mut self, index: usize, buffer: &[u8; BLOCK_SIZE]) -> Result<(), BlockError> {
+		let block = self.block(index)?;
+		unsafe { core::ptr::copy_nonoverlapping(buffer.as_ptr(), block, BLOCK_SIZE) };
+		Ok(())
+	}
+}
+
+/// The module's memory becomes the disk in place, its contents being the
+/// initial image; an LZ4 frame is expanded into the pool first. Returns the
+/// range holding the image.
+pub fn init_from_module(start: usize, end: usize) -> Option<(usize, usize)> {
+	let module = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+	let (base, size, source) = if lz4::is_frame(module) {
+		let pool = take_pool()?;
+		match lz4::decompress_frame(module, pool) {
+			Ok(size) => (pool.as_mut_ptr(), size, Source::CompressedModule),
+			Err(error) => {
+				log!(Error, "ramdisk: cannot expand module: {:?}", error);
+				return None;
+			}
+		}
+	} else {
+		(start as *mut u8, end - start, Source::Module)
+	};
+	*RAMDISK.lock() = Some(RamDisk { base, size, source });
+	log!(Info, "ramdisk: {} KB from module at {:#x} ({:?})", size / 1024, start, source);
+	Some((base as usize, base as usize + size))
+}
+
+/// `size` is in KB like the Linux option, capped to RAMDISK_POOL_SIZE.
+pub fn init_empty(kilobytes: usize) {
+	let mut size = kilobytes.saturating_mul(1024);
+	if size > RAMDISK_POOL_SIZE {
+		log!(Warning, "ramdisk: size capped to {} KB", RAMDISK_POOL_SIZE / 1024);
+		size = RAMDISK_POOL_SIZE;
+	}
+	let pool = match take_pool() {
+		Some(pool) => pool,
+		None => return,
+	};
+	*RAMDISK.lock() = Some(RamDisk {
+		base: pool.as_mut_ptr(),
+		size,
+		source: Source::Empty,
+	});
+	log!(Info, "ramdisk: {} KB empty", size / 1024);
+}
@@ -0,0 +1,221 @@
+use core::fmt;
+use core::sync::atomic::{ AtomicU32, Ordering };
+use crate::sync::Mutex;
+use crate::collections::FixedVec;
+use crate::driver;
+use crate::error::{ KernelError, KernelResult };
+use crate::interrupts::{ self, InterruptGuard, InterruptIndex, PIC_1_OFFSET, TICKS_PER_SECOND };
+use crate::io::{ inb, outb };
+use crate::timer::{ self, Source };
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_A: u8 = 0x0a;
+const REGISTER_B: u8 = 0x0b;
+const REGISTER_C: u8 = 0x0c;
+
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+const PERIODIC_ENABLE: u8 = 0x40;
+const BINARY_MODE: u8 = 0x04;
+const HOURS_24: u8 = 0x02;
+const PM: u8 = 0x80;
+/// Register C bit telling the interrupt came from the periodic timer.
+const PERIODIC_FLAG: u8 = 0x40;
+
+/// Rate 15 divides the 32768 Hz base down to 2 Hz.
+const RATE: u8 = 15;
+const PERIODIC_HZ: u32 = 2;
+
+const MAX_ALARMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DateTime {
+	pub year: u16,
+	pub month: u8,
+	pub day: u8,
+	pub hours: u8,
+	pub minutes: u8,
+	pub seconds: u8,
+}
+
+impl fmt::Display for DateTime {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{:02}/{:02}/{:04} {:02}:{:02}:{:02}",
+			self.day, self.month, self.year, self.hours, self.minutes, self.seconds
+		)
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Alarm {
+	/// timer::uptime_ms() it fires at.
+	due: u64,
+	callback: fn(),
+}
+
+/// Last time read from CMOS.
+static TIME: Mutex<Option<DateTime>> = Mutex::new(None);
+static PERIODIC_COUNT: AtomicU32 = AtomicU32::new(0);
+static ALARMS: Mutex<FixedVec<Alarm, MAX_ALARMS>> = Mutex::new(FixedVec::new());
+
+fn read_register(register: u8) -> u8 {
+	unsafe {
+		outb(CMOS_ADDRESS, register);
+		inb(CMOS_DATA)
+	}
+}
+
+fn write_register(register: u8, value: u8) {
+	unsafe {
+		outb(CMOS_ADDRESS, register);
+		outb(CMOS_DATA, value);
+	}
+}
+
+fn bcd_to_binary(bcd: u8) -> u8 {
+	((bcd & 0xf0) >> 4) * 10 + (bcd & 0x0f)
+}
+
+/// None while the chip is updating its registers, which can then hold half
+/// of the old time and half of the new.
+fn read_cmos_time() -> Option<DateTime> {
+	if read_register(REGISTER_A) & UPDATE_IN_PROGRESS != 0 {
+		return None;
+	}
+	let format = read_register(REGISTER_B);
+	let decode = |value: u8| if format & BINARY_MODE != 0 { value } else { bcd_to_binary(value) };
+	let raw_hours = read_register(REGISTER_HOURS);
+	let mut hours = decode(raw_hours & !PM);
+	if format & HOURS_24 == 0 {
+		hours %= 12;
+		if raw_hours & PM != 0 {
+			hours += 12;
+		}
+	}
+	Some(DateTime {
+		year: 2000 + decode(read_register(REGISTER_YEAR)) as u16,
+		month: decode(read_register(REGISTER_MONTH)),
+		day: decode(read_register(REGISTER_DAY)),
+		hours,
+		minutes: decode(read_register(REGISTER_MINUTES)),
+		seconds: decode(read_register(REGISTER_SECONDS)),
+	})
+}
+
+/// Starts the periodic interrupt and takes the first reading. Needs the PIC
+/// set up.
+pub fn init() {
+	{
+		let _guard = InterruptGuard::new();
+		let rate = read_register(REGISTER_A);
+		write_register(REGISTER_A, (rate & 0xf0) | RATE);
+		let format = read_register(REGISTER_B);
+		write_register(REGISTER_B, format | PERIODIC_ENABLE);
+		// An interrupt already flagged in C would hold IRQ8 until C is read
+		read_register(REGISTER_C);
+	}
+	now();
+	interrupts::enable_irq(InterruptIndex::Rtc.as_usize() - PIC_1_OFFSET as usize);
+	let driver = driver::Driver { name: "rtc", depends_on: &[], suspend: None, resume: None, shutdown: Some(stop_periodic) };
+	if let Err(error) = driver::register(driver) {
+		log!(Warning, "rtc: no power hooks: {}", error.as_str());
+	}
+}
+
+/// Firmware and the next kernel do not expect the periodic interrupt.
+fn stop_periodic() {
+	let _guard = InterruptGuard::new();
+	let format = read_register(REGISTER_B);
+	write_register(REGISTER_B, format & !PERIODIC_ENABLE);
+}
+
+/// Reads CMOS into the cache, unless the chip is updating or the cache is
+/// in use; the next second tries again.
+fn refresh() {
+	if let (Some(time), Some(mut cache)) = (read_cmos_time(), TIME.try_lock()) {
+		*cache = Some(time);
+	}
+}
+
+/// Called from IRQ8. Reading register C acknowledges the interrupt; without
+/// it the RTC raises no other. The time is refreshed once a second.
+pub fn interrupt() {
+	if read_register(REGISTER_C) & PERIODIC_FLAG == 0 {
+		return;
+	}
+	let count = PERIODIC_COUNT.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+	if count % PERIODIC_HZ == 0 {
+		refresh();
+	}
+}
+
+/// Called by the timer interrupt with the new TICKS. The HPET's legacy
+/// route takes IRQ8 along with IRQ0, so while it drives the timer the time
+/// is refreshed from here instead.
+pub fn tick(now: u32) {
+	if now % TICKS_PER_SECOND == 0 && timer::source() == Source::Hpet {
+		refresh();
+	}
+}
+
+/// The wall-clock time as of the last refresh. Only the first call, before
+/// any refresh, waits for CMOS.
+pub fn now() -> DateTime {
+	let _guard = InterruptGuard::new();
+	let mut cache = TIME.lock();
+	if let Some(time) = *cache {
+		return time;
+	}
+	let time = loop {
+		if let Some(time) = read_cmos_time() {
+			break time;
+		}
+		core::hint::spin_loop();
+	};
+	*cache = Some(time);
+	time
+}
+
+/// Runs `callback` `seconds` from now, from the next task to yield after
+/// that: callbacks may print, which must not happen inside an interrupt.
+pub fn alarm_in(seconds: u32, callback: fn()) -> KernelResult<()> {
+	let due = timer::uptime_ms() + seconds.max(1) as u64 * 1000;
+	let _guard = InterruptGuard::new();
+	ALARMS.lock().push(Alarm { due, callback }).map_err(|_| KernelError::Busy)
+}
+
+/// Called from task::yield_now: runs the alarms that are due, with no lock
+/// held.
+pub fn process_alarms() {
+	let uptime = timer::uptime_ms();
+	let mut due: FixedVec<fn(), MAX_ALARMS> = FixedVec::new();
+	{
+		let _guard = InterruptGuard::new();
+		let mut alarms = ALARMS.lock();
+		let mut index = 0;
+		while index < alarms.as_slice().len() {
+			if alarms.as_slice()[index].due <= uptime {
+				let _ = due.push(alarms.remove(index).callback);
+			} else {
+				index += 1;
+			}
+		}
+	}
+	for callback in due.iter() {
+		callback();
+	}
+}
+
+pub fn pending_alarms() -> usize {
+	let _guard = InterruptGuard::new();
+	ALARMS.lock().as_slice().len()
+}
@@ -0,0 +1,83 @@
+use core::arch::asm;
+use core::fmt::{ self, Write };
+
+const CR0_PAGING: usize = 1 << 31;
+const PAGE_SIZE: usize = 0x1000;
+const LARGE_PAGE_SIZE: usize = 0x400000;
+const ENTRIES: usize = 1024;
+const RECURSIVE_SLOT: usize = 1023;
+
+const PRESENT: u32 = 1 << 0;
+const WRITABLE: u32 = 1 << 1;
+const USER: u32 = 1 << 2;
+const LARGE: u32 = 1 << 7;
+const FRAME_MASK: u32 = !0xfff;
+
+const VGA_TEXT: usize = 0xb8000;
+
+/// Flags of the page mapping `address`, None when it is not mapped. Page
+/// tables are read at their physical address, which holds as long as the
+/// kernel identity-maps the memory they live in.
+unsafe fn translate(directory: *const u32, address: usize) -> Option<(usize, u32)> {
+	let entry = *directory.add(address / LARGE_PAGE_SIZE);
+	if entry & PRESENT == 0 {
+		return None;
+	}
+	if entry & LARGE != 0 {
+		return Some(((entry & !0x3fffff) as usize + address % LARGE_PAGE_SIZE, entry));
+	}
+	let table = (entry & FRAME_MASK) as *const u32;
+	let page = *table.add(address / PAGE_SIZE % ENTRIES);
+	if page & PRESENT == 0 {
+		return None;
+	}
+	// A page is only as permissive as both levels allow
+	Some(((page & FRAME_MASK) as usize + address % PAGE_SIZE, page & (entry | !(USER | WRITABLE))))
+}
+
+/// Walks the page directory in CR3 and reports every broken invariant:
+/// the kernel image and text screen mapped, no kernel page reachable from
+/// ring 3, and the recursive slot, when used, pointing back at the
+/// directory. Returns how many problems were found.
+pub fn paging(writer: &mut dyn Write) -> Result<usize, fmt::Error> {
+	let (cr0, cr3): (usize, usize);
+	unsafe {
+		asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack, preserves_flags));
+		asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+	}
+	if cr0 & CR0_PAGING == 0 {
+		writeln!(writer, "paging: disabled (cr0.pg clear), nothing to check")?;
+		return Ok(0);
+	}
+	let directory = (cr3 & FRAME_MASK as usize) as *const u32;
+	let mut problems = 0;
+
+	let (kernel_start, kernel_end) = crate::librs::kernel_image();
+	for address in (kernel_start & !(PAGE_SIZE - 1)..kernel_end).step_by(PAGE_SIZE) {
+		match unsafe { translate(directory, address) } {
+			None => {
+				writeln!(writer, "paging: kernel page {:#010x} not mapped", address)?;
+				problems += 1;
+			}
+			Some((_, flags)) if flags & USER != 0 => {
+				let access = if flags & WRITABLE != 0 { "writable" } else { "readable" };
+				writeln!(writer, "paging: kernel page {:#010x} {} from ring 3", address, access)?;
+				problems += 1;
+			}
+			Some(_) => (),
+		}
+	}
+	if unsafe { translate(directory, VGA_TEXT) }.is_none() {
+		writeln!(writer, "paging: text screen {:#010x} not mapped", VGA_TEXT)?;
+		problems += 1;
+	}
+
+	let recursive = unsafe { *directory.add(RECURSIVE_SLOT) };
+	if recursive & PRESENT != 0 && recursive & LARGE == 0 && (recursive & FRAME_MASK) as usize != cr3 & FRAME_MASK as usize {
+		writeln!(writer, "paging: slot {} maps {:#010x}, not the directory {:#010x}", RECURSIVE_SLOT, recursive & FRAME_MASK, cr3 & FRAME_MASK as usize)?;
+		problems += 1;
+	}
+
+	writeln!(writer, "paging: directory {:#010x}, {} problem(s)", cr3 & FRAME_MASK as usize, problems)?;
+	Ok(problems)
+}
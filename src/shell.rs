@@ -1,295 +1,1219 @@
-use lazy_static::lazy_static;
-use spin::Mutex;
+use core::fmt;
+use crate::sync::Mutex;
+use crate::args::{ ArgError, Args };
+use crate::collections::{ FixedString, RingBuffer };
 use crate::generate_interrupt;
-use crate::librs::{self, printraw};
-use crate::prompt::PROMPT;
-use crate::video_graphics_array::WRITER;
+use crate::librs::{self, printraw, Console};
+use crate::prompt::{ PROMPT, MAX_LINE_LENGTH };
+use crate::vfs::FILES;
+use crate::video_graphics_array::VGA_COLUMNS;
 
-const CMOS_ADDRESS: u16 = 0x70;
-const CMOS_DATA: u16 = 0x71;
-
-const MAX_LINE_LENGTH: usize = 76;
-const MAX_HISTORY_LINES: usize = 16;
+const MAX_HISTORY_LINES: usize = 64;
 
+/// Ring of the last MAX_HISTORY_LINES commands. Entries are numbered from 1
+/// in the order they were typed; `browse` is how far back the arrow keys went.
 pub struct History {
-    buffer: [[u8; MAX_LINE_LENGTH]; MAX_HISTORY_LINES],
-    index: usize,
+    lines: RingBuffer<FixedString<MAX_LINE_LENGTH>, MAX_HISTORY_LINES>,
+    total: usize,
+    browse: usize,
 }
 
 impl History {
-    fn new() -> History {
+    const fn new() -> History {
         History {
-            buffer: [[0; MAX_LINE_LENGTH]; MAX_HISTORY_LINES],
-            index: 0,
+            lines: RingBuffer::new(),
+            total: 0,
+            browse: 0,
         }
     }
 
     fn add(&mut self, line: &str) {
-        self.buffer[self.index] = str_to_array(line);
-        self.index = (self.index + 1) % MAX_HISTORY_LINES;
+        self.browse = 0;
+        if self.get_recent(0) == Some(line) {
+            return;
+        }
+        self.lines.push(FixedString::from(line));
+        self.total += 1;
     }
 
-    fn get(&self, index: usize) -> &[u8; MAX_LINE_LENGTH] {
-        &self.buffer[index]
+    /// Returns the `index`-th most recent entry, 0 being the last command.
+    fn get_recent(&self, index: usize) -> Option<&str> {
+        self.lines.recent(index).map(|line| line.as_str())
+    }
+
+    /// Expands a leading `!!`, `!n` or `!prefix` event, keeping the rest of
+    /// the line. None when no entry matches.
+    fn expand(&self, line: &str) -> Option<FixedString<MAX_LINE_LENGTH>> {
+        let (event, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let event = &event[1..];
+        let found = if event == "!" {
+            self.get_recent(0)
+        } else if let Some(number) = event.parse::<usize>().ok().filter(|&number| number > 0) {
+            self.total.checked_sub(number).and_then(|index| self.get_recent(index))
+        } else if !event.is_empty() {
+            self.lines.iter().rev().map(|entry| entry.as_str()).find(|entry| entry.starts_with(event))
+        } else {
+            None
+        }?;
+        let mut expanded = FixedString::from(found);
+        if !rest.is_empty() {
+            expanded.push(' ');
+            expanded.push_str(rest);
+        }
+        Some(expanded)
     }
 
     fn print(&self) {
-        for i in 0..MAX_HISTORY_LINES {
-            let line = self.get(i);
-            if line[0] != 0 {
-                for &c in line.iter().take_while(|&&c| c != 0) {
-                    print!("{}", c as char);
-                }
-                println!();
-            }
+        let first = self.total - self.lines.len() + 1;
+        for (number, line) in (first..).zip(self.lines.iter()) {
+            println!("{:5}  {}", number, line.as_str());
         }
     }
 
-    fn print_prompt(&self, index: usize) {
-        for c in self.get(index).iter().take_while(|&&c| c != 0) {
-            PROMPT.lock().insert_char(*c, false);
+    fn print_prompt(&self) {
+        let mut prompt = PROMPT.lock();
+        prompt.init();
+        if self.browse > 0 {
+            prompt.insert_string(self.get_recent(self.browse - 1).unwrap_or(""));
         }
     }
 
     pub fn scroll_up(&mut self) {
-        if self.index == 0 {
+        if self.browse == self.lines.len() {
             return;
         }
-        PROMPT.lock().init();
-        self.index = (self.index - 1) % MAX_HISTORY_LINES;
-        self.print_prompt(self.index);
+        self.browse += 1;
+        self.print_prompt();
     }
 
     pub fn scroll_down(&mut self) {
-        if self.index == MAX_HISTORY_LINES - 1 {
+        if self.browse == 0 {
             return;
         }
-
-        PROMPT.lock().init();
-        self.index = (self.index + 1) % MAX_HISTORY_LINES;
-        self.print_prompt(self.index);
+        self.browse -= 1;
+        self.print_prompt();
     }
 }
 
-lazy_static! {
-    pub static ref HISTORY: Mutex<History> = Mutex::new(History::new());
+pub static HISTORY: Mutex<History> = Mutex::new(History::new());
+
+const HELP_SPLIT_COLUMN: usize = 39;
+
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub usage: &'static str,
+    pub example: &'static str,
+    max_args: usize,
+    handler: fn(&Args) -> Result<(), ArgError>,
 }
 
-fn str_to_array(s: &str) -> [u8; MAX_LINE_LENGTH] {
-    let mut array = [0; MAX_LINE_LENGTH];
-    for (i, c) in s.bytes().enumerate() {
-        array[i] = c;
-    }
-    array
+static COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        description: "list commands or show one",
+        usage: "help [command]",
+        example: "help uname",
+        max_args: 1,
+        handler: help,
+    },
+    Command {
+        name: "echo",
+        description: "display a line of text",
+        usage: "echo <text>",
+        example: "echo hello world",
+        max_args: usize::MAX,
+        handler: echo,
+    },
+    Command {
+        name: "clear",
+        description: "clear the screen",
+        usage: "clear",
+        example: "clear",
+        max_args: 0,
+        handler: |_| {
+            clear();
+            Ok(())
+        },
+    },
+    Command {
+        name: "history",
+        description: "show command history, rerun with !!, !n or !prefix",
+        usage: "history",
+        example: "history",
+        max_args: 0,
+        handler: |_| {
+            HISTORY.lock().print();
+            Ok(())
+        },
+    },
+    Command {
+        name: "printstack",
+        description: "print the kernel stack",
+        usage: "printstack",
+        example: "printstack",
+        max_args: 0,
+        handler: |_| {
+            librs::print_stack();
+            Ok(())
+        },
+    },
+    Command {
+        name: "coredump",
+        description: "send an ELF core of kernel memory over COM2",
+        usage: "coredump serial",
+        example: "coredump serial",
+        max_args: 1,
+        handler: |args| {
+            if args.get(1)? != "serial" {
+                return Err(ArgError::Invalid(1));
+            }
+            println!("coredump: {} bytes sent on COM2", crate::coredump::stream());
+            Ok(())
+        },
+    },
+    Command {
+        name: "trace",
+        description: "record interrupts, syscalls and idle periods",
+        usage: "trace <start|stop|dump|send|clear>",
+        example: "trace start",
+        max_args: 1,
+        handler: trace,
+    },
+    Command {
+        name: "time",
+        description: "print the time, or the wall time taken by a command",
+        usage: "time [command]",
+        example: "time cksum /proc/version",
+        max_args: 16,
+        handler: time,
+    },
+    Command {
+        name: "sleep",
+        description: "wait for a number of milliseconds",
+        usage: "sleep <ms>",
+        example: "sleep 500",
+        max_args: 1,
+        handler: |args| {
+            crate::timer::sleep_ms(args.get_int(1)? as u64);
+            Ok(())
+        },
+    },
+    Command {
+        name: "date",
+        description: "print the date and time",
+        usage: "date",
+        example: "date",
+        max_args: 0,
+        handler: |_| {
+            date();
+            Ok(())
+        },
+    },
+    Command {
+        name: "alarm",
+        description: "ring the bell in a number of seconds",
+        usage: "alarm [seconds]",
+        example: "alarm 60",
+        max_args: 1,
+        handler: alarm,
+    },
+    Command {
+        name: "uname",
+        description: "print system information",
+        usage: "uname [-asrvmo]",
+        example: "uname -a",
+        max_args: 0,
+        handler: uname,
+    },
+    Command {
+        name: "uptime",
+        description: "time since boot",
+        usage: "uptime",
+        example: "uptime",
+        max_args: 0,
+        handler: |_| {
+            let _ = crate::interrupts::write_uptime(&mut Console);
+            Ok(())
+        },
+    },
+    Command {
+        name: "rdmsr",
+        description: "read an allowlisted model specific register",
+        usage: "rdmsr [index]",
+        example: "rdmsr 0x1b",
+        max_args: 1,
+        handler: rdmsr,
+    },
+    Command {
+        name: "powertop",
+        description: "time spent idle and sleep states used",
+        usage: "powertop",
+        example: "powertop",
+        max_args: 0,
+        handler: |_| {
+            let _ = crate::idle::write_stats(&mut Console);
+            Ok(())
+        },
+    },
+    Command {
+        name: "cycles",
+        description: "time stamp counter, or cycles taken by a command",
+        usage: "cycles [command]",
+        example: "cycles cksum /dev/zero",
+        max_args: 16,
+        handler: cycles,
+    },
+    Command {
+        name: "calc",
+        description: "evaluate an integer expression",
+        usage: "calc <expression>",
+        example: "calc (0xb8000 + 160) & ~0xfff",
+        max_args: usize::MAX,
+        handler: calc,
+    },
+    Command {
+        name: "bits",
+        description: "decode the bits of a value",
+        usage: "bits <value> <cr0|cr4|eflags|pf|bit:NAME,low-high:NAME,...>",
+        example: "bits 0x80000011 0:PE,4:ET,31:PG",
+        max_args: 2,
+        handler: bits,
+    },
+    Command {
+        name: "miao",
+        description: "print a cat",
+        usage: "miao",
+        example: "miao",
+        max_args: 0,
+        handler: |_| {
+            miao();
+            Ok(())
+        },
+    },
+    Command {
+        name: "exept",
+        description: "throw an exception",
+        usage: "exept <0-255>",
+        example: "exept 3",
+        max_args: 1,
+        handler: exept,
+    },
+    Command {
+        name: "crash",
+        description: "deliberately crash the kernel",
+        usage: "crash [-y] <nullderef|stackoverflow|doublefault|assert|oom>",
+        example: "crash assert",
+        max_args: 1,
+        handler: crash,
+    },
+    Command {
+        name: "applet",
+        description: "run a flat binary in kernel mode",
+        usage: "applet [-y] <file> <crc32>",
+        example: "applet /bin/hello.bin 0x1c291ca3",
+        max_args: 2,
+        handler: applet,
+    },
+    Command {
+        name: "watch",
+        description: "set a hardware watchpoint",
+        usage: "watch [address] [rw|w|x]",
+        example: "watch 0xb8000 w",
+        max_args: 2,
+        handler: watch,
+    },
+    Command {
+        name: "unwatch",
+        description: "remove a hardware watchpoint",
+        usage: "unwatch <0-3>",
+        example: "unwatch 0",
+        max_args: 1,
+        handler: unwatch,
+    },
+    Command {
+        name: "irqmask",
+        description: "show or toggle IRQ lines",
+        usage: "irqmask [line on|off]",
+        example: "irqmask 1 off",
+        max_args: 2,
+        handler: irqmask,
+    },
+    Command {
+        name: "ls",
+        description: "list directory contents",
+        usage: "ls [directory]",
+        example: "ls /bin",
+        max_args: 1,
+        handler: ls,
+    },
+    Command {
+        name: "cat",
+        description: "print a file, or stdin",
+        usage: "cat [file]",
+        example: "cat /etc/motd",
+        max_args: 1,
+        handler: cat,
+    },
+    Command {
+        name: "cksum",
+        description: "print the CRC-32 of a file",
+        usage: "cksum <file>",
+        example: "cksum /etc/motd",
+        max_args: 1,
+        handler: cksum,
+    },
+    Command {
+        name: "check",
+        description: "verify kernel invariants",
+        usage: "check paging",
+        example: "check paging",
+        max_args: 1,
+        handler: check,
+    },
+    Command {
+        name: "mount",
+        description: "list mounted filesystems",
+        usage: "mount",
+        example: "mount",
+        max_args: 0,
+        handler: mount,
+    },
+    Command {
+        name: "ramdisk",
+        description: "show the ramdisk or a block",
+        usage: "ramdisk [block]",
+        example: "ramdisk 0",
+        max_args: 1,
+        handler: ramdisk,
+    },
+    Command {
+        name: "loglevel",
+        description: "show or set the log level",
+        usage: "loglevel [0-7]",
+        example: "loglevel 7",
+        max_args: 1,
+        handler: loglevel,
+    },
+    Command {
+        name: "lograte",
+        description: "show or set log messages/s",
+        usage: "lograte [messages per second]",
+        example: "lograte 0",
+        max_args: 1,
+        handler: lograte,
+    },
+    Command {
+        name: "sysctl",
+        description: "show or change settings",
+        usage: "sysctl [name=value]",
+        example: "sysctl verbose_irq=1",
+        max_args: 1,
+        handler: sysctl,
+    },
+    Command {
+        name: "bind",
+        description: "list or change key bindings",
+        usage: "bind [chord action|none]",
+        example: "bind alt+5 welcome",
+        max_args: 2,
+        handler: bind,
+    },
+    Command {
+        name: "halt",
+        description: "halt the system",
+        usage: "halt",
+        example: "halt",
+        max_args: 0,
+        handler: |_| {
+            librs::hlt();
+            Ok(())
+        },
+    },
+    Command {
+        name: "reboot",
+        description: "reboot the system",
+        usage: "reboot",
+        example: "reboot",
+        max_args: 0,
+        handler: |_| {
+            reboot();
+            Ok(())
+        },
+    },
+    Command {
+        name: "shutdown",
+        description: "shutdown the system",
+        usage: "shutdown",
+        example: "shutdown",
+        max_args: 0,
+        handler: |_| {
+            shutdown();
+            Ok(())
+        },
+    },
+    Command {
+        name: "suspend",
+        description: "halt with only the keyboard unmasked until a key is pressed",
+        usage: "suspend",
+        example: "suspend",
+        max_args: 0,
+        handler: |_| {
+            let slept = crate::power::suspend();
+            println!("suspended for {}.{:03} s", slept / 1000, slept % 1000);
+            Ok(())
+        },
+    },
+];
+
+static KEY_BINDINGS: &[(&str, &str)] = &[
+    ("F1-F3", "switch between shells"),
+    ("F4", "kernel log screen"),
+    ("Alt+1-4", "switch screens, see bind"),
+    ("F9", "display welcome message"),
+    ("F10", "change keyboard layout"),
+    ("F11 / F12", "text / background color"),
+    ("C+A+F12", "dump CPU state to log"),
+    ("Power", "press twice to shut down"),
+    ("C+Arrows", "move by word"),
+    ("C+Bksp/Del", "delete word"),
+];
+
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    let name = if name == "man" { "help" } else { name };
+    COMMANDS.iter().find(|command| command.name == name)
 }
 
-fn bcd_to_binary(bcd: u8) -> u8 {
-    ((bcd & 0xf0) >> 4) * 10 + (bcd & 0x0f)
+fn print_border(left: &str, split: &str, right: &str) {
+    printraw(left);
+    for column in 1..VGA_COLUMNS - 1 {
+        printraw(if column == HELP_SPLIT_COLUMN { split } else { "m" });
+    }
+    printraw(right);
 }
 
-fn print_help_line(command: &str, description: &str) {
-    print!("  {:13}", command);
+fn print_help_row(left: (&str, &str), right: Option<(&str, &str)>) {
+    printraw("Z");
+    print!(" {:10.10} {:26.26}", left.0, left.1);
     printraw("Z");
-    print!("  {:60}", description);
-    if command == "shutdown" {
-        printraw("Z");
-    } else if command != "F12" {
-        printraw("ZZ");
+    match right {
+        Some((name, description)) => print!(" {:10.10} {:27.27}", name, description),
+        None => print!("{:39}", ""),
     }
+    printraw("Z");
 }
 
-fn help() {
+fn print_help_table(entries: &[(&str, &str)]) {
+    for pair in entries.chunks(2) {
+        print_help_row(pair[0], pair.get(1).copied());
+    }
+}
+
+fn help(args: &Args) -> Result<(), ArgError> {
+    if let Some(name) = args.opt(1) {
+        match find_command(name) {
+            Some(command) => {
+                println!("{} - {}", command.name, command.description);
+                println!("usage:   {}", command.usage);
+                println!("example: {}", command.example);
+            }
+            None => println!("help: no such command '{}'", name),
+        }
+        return Ok(());
+    }
+
     clear();
-    printraw("immmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmm[Z");
-    print!(" Available commands                                                           ");
-    printraw("ZlmmmmmmmmmmmmmmmkmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmYZ");
-    print_help_line("echo", "display a line of text");
-    print_help_line("clear", "clear the screen");
-    print_help_line("printstack", "print the stack");
-    print_help_line("time", "print the time");
-    print_help_line("date", "display the current date and time");
-    print_help_line("miao", "print a cat");
-    print_help_line("uname", "print system information");
-    print_help_line("exept", "throw an exception");
-    print_help_line("halt", "halt the system");
-    print_help_line("reboot", "reboot the system");
-    print_help_line("shutdown", "shutdown the system");
-    printraw("lmmmmmmmmmmmmmmmnmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmYZ");
-    print_help_line("F1-F4", "change between screens");
-    print_help_line("F9", "display welcome message");
-    print_help_line("F10", "change keyboard layout");
-    print_help_line("F11", "switch text color");
-    print_help_line("F12", "switch background color");
-
-    printraw("ZlmmmmmmmmmmmmmmmjmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmYZ");
+    print_border("i", "m", "[");
+    printraw("Z");
+    print!(" {:77}", "Available commands (help <command> for details)");
+    printraw("Z");
+    print_border("l", "k", "Y");
+    for pair in COMMANDS.chunks(2) {
+        print_help_row(
+            (pair[0].name, pair[0].description),
+            pair.get(1).map(|command| (command.name, command.description)),
+        );
+    }
+    print_border("l", "n", "Y");
+    print_help_table(KEY_BINDINGS);
+    print_border("l", "j", "Y");
+    printraw("Z");
     print!(
         " Type 'history' to view command history           {} {} navigate history        ",
         0x1e as char, 0x1f as char
     );
-    printraw("Zhmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmmm\\");
-    println!("");
+    printraw("Z");
+    print_border("h", "m", "\\");
+    println!();
+    Ok(())
 }
 
 fn clear() {
-    WRITER.lock().clear_screen();
+    librs::clear();
+}
+
+fn echo(args: &Args) -> Result<(), ArgError> {
+    println!("{}", args.rest(1)?);
+    Ok(())
 }
 
-fn echo(line: &str) {
-    let message: &str = &line["echo".len()..];
-    if message.starts_with(" ") && message.len() > 1 {
-        println!("{}", message[1..].trim());
-    } else {
-        println!("echo: missing argument");
+fn date() {
+    println!("{}", crate::rtc::now());
+}
+
+fn alarm(args: &Args) -> Result<(), ArgError> {
+    use crate::rtc;
+    if args.len() == 0 {
+        println!("alarm: {} pending", rtc::pending_alarms());
+        return Ok(());
+    }
+    let seconds = u32::try_from(args.get_int(1)?).map_err(|_| ArgError::OutOfRange(1))?;
+    if let Err(error) = rtc::alarm_in(seconds, || println_async!("\x07alarm: time is up")) {
+        println!("alarm: {}", error.as_str());
+    }
+    Ok(())
+}
+
+fn miao() {
+    print!("{}", crate::assets::get("miao").text());
+}
+
+fn reboot() {
+    crate::driver::shutdown_all();
+    crate::debug::flush();
+    unsafe {
+        use crate::io::outb;
+        outb(0x64, 0xfe);
     }
 }
 
-fn read_cmos(register: u8) -> u8 {
+pub fn shutdown() {
+    crate::driver::shutdown_all();
+    crate::debug::flush();
     unsafe {
-        use crate::io::{inb, outb};
-        outb(CMOS_ADDRESS, register);
-        inb(CMOS_DATA)
+        use crate::io::outw;
+        outw(0x604, 0x2000);
     }
 }
 
-fn get_rtc_time() -> (u8, u8, u8) {
-    let seconds = bcd_to_binary(read_cmos(0x00));
-    let minutes = bcd_to_binary(read_cmos(0x02));
-    let hours = bcd_to_binary(read_cmos(0x04));
+fn uname(args: &Args) -> Result<(), ArgError> {
+    let mut flags = [false; 5];
+    for flag in args.flags() {
+        match flag {
+            'a' => flags = [true; 5],
+            's' => flags[0] = true,
+            'r' => flags[1] = true,
+            'v' => flags[2] = true,
+            'm' => flags[3] = true,
+            'o' => flags[4] = true,
+            _ => return Err(ArgError::UnknownFlag(flag)),
+        }
+    }
+    if !flags.iter().any(|&flag| flag) {
+        flags[0] = true;
+    }
 
-    (hours, minutes, seconds)
+    let _ = write_uname(&mut Console, flags);
+    Ok(())
 }
 
-fn get_rtc_date() -> (u8, u8, u8) {
-    let year = bcd_to_binary(read_cmos(0x09));
-    let month = bcd_to_binary(read_cmos(0x08));
-    let day = bcd_to_binary(read_cmos(0x07));
+/// Fields in -s -r -v -m -o order; shared by `uname` and /proc/version.
+pub fn write_uname(writer: &mut dyn fmt::Write, flags: [bool; 5]) -> fmt::Result {
+    use crate::version::*;
+    let fields: [&dyn fmt::Display; 5] = [
+        &SYSNAME,
+        &RELEASE,
+        &format_args!("{} #{} ({}) {}", CODENAME, GIT_HASH, BUILD_DATE, RUSTC_VERSION),
+        &MACHINE,
+        &format_args!("{} {}", OPERATING_SYSTEM, AUTHORS),
+    ];
+    let mut first = true;
+    for (field, _) in fields.iter().zip(flags).filter(|(_, enabled)| *enabled) {
+        if !first {
+            write!(writer, " ")?;
+        }
+        write!(writer, "{}", field)?;
+        first = false;
+    }
+    writeln!(writer)
+}
 
-    (year, month, day)
+fn cycles(args: &Args) -> Result<(), ArgError> {
+    use crate::tsc;
+    let khz = tsc::frequency_khz() as u64;
+    if args.len() == 0 {
+        let serializing = if tsc::has_rdtscp() { "rdtscp" } else { "cpuid+rdtsc" };
+        println!("cycles: {} ({}.{:03} MHz, {})", tsc::read_serialized(), khz / 1000, khz % 1000, serializing);
+        return Ok(());
+    }
+    let start = tsc::read_serialized();
+    run(args.rest(1)?);
+    let elapsed = tsc::read_serialized() - start;
+    println!("cycles: {} ({} us)", elapsed, elapsed * 1000 / khz.max(1));
+    Ok(())
 }
 
-fn time() {
-    let (hours, minutes, seconds) = get_rtc_time();
-    println!("{:02}:{:02}:{:02}", hours, minutes, seconds);
+fn calc(args: &Args) -> Result<(), ArgError> {
+    use crate::video_graphics_array::{ with_foreground, ColorCode };
+    let value = match crate::calc::evaluate(args.rest(1)?) {
+        Ok(value) => value,
+        Err(error) => {
+            match error.position() {
+                Some(position) => println!("calc: {} at column {}", error.as_str(), position + 1),
+                None => println!("calc: {}", error.as_str()),
+            }
+            return Ok(());
+        }
+    };
+    with_foreground(ColorCode::White, || print!("{}", value));
+    if (value as i32) < 0 {
+        with_foreground(ColorCode::LightGray, || print!(" ({})", value as i32));
+    }
+    with_foreground(ColorCode::LightCyan, || print!("  {:#010x}", value));
+    with_foreground(ColorCode::LightGreen, || print!("  {:#b}", value));
+    println!();
+    Ok(())
 }
 
-fn date() {
-    let (hours, minutes, seconds) = get_rtc_time();
-    let (year, month, day) = get_rtc_date();
+fn bits(args: &Args) -> Result<(), ArgError> {
+    use crate::bits;
+    let value = u32::try_from(args.get_int(1)?).map_err(|_| ArgError::OutOfRange(1))?;
+    let spec = args.get(2)?;
+    let parsed;
+    let fields = match bits::NAMED.iter().find(|(name, _)| *name == spec) {
+        Some((_, fields)) => *fields,
+        None => {
+            parsed = bits::parse_spec(spec).ok_or(ArgError::Invalid(2))?;
+            parsed.as_slice()
+        }
+    };
+    println!("{} {:#010x} {:#o} {:#034b}", value, value, value, value);
+    for field in fields {
+        if field.low == field.high {
+            println!("   {:>2}  {:10} {}", field.low, field.name, field.extract(value));
+        } else {
+            println!("{:>2}-{:>2}  {:10} {}", field.low, field.high, field.name, field.extract(value));
+        }
+    }
+    println!("set: {}", bits::decode(value, fields));
+    Ok(())
+}
 
-    let full_year = 2000 + year as u16;
+fn rdmsr(args: &Args) -> Result<(), ArgError> {
+    use crate::msr;
+    if !msr::supported() {
+        println!("rdmsr: this CPU has no MSRs");
+        return Ok(());
+    }
+    if args.len() == 0 {
+        for register in msr::ALLOWED.iter() {
+            match register.read() {
+                Some(value) => println!("{:#010x} {:26} {:#018x}", register.index, register.name, value),
+                None => println!("{:#010x} {:26} unavailable", register.index, register.name),
+            }
+        }
+        if let Some(celsius) = msr::temperature() {
+            println!("core temperature: {} C", celsius);
+        }
+        return Ok(());
+    }
+    let index = args.get_int(1)?;
+    let register = msr::find(index as u32).ok_or(ArgError::Invalid(1))?;
+    match register.read() {
+        Some(value) => println!("{}: {:#018x}", register.name, value),
+        None => println!("rdmsr: {} is not implemented by this CPU", register.name),
+    }
+    Ok(())
+}
 
-    println!(
-        "{:02}/{:02}/{:02} {:02}:{:02}:{:02}",
-        day, month, full_year, hours, minutes, seconds
-    );
+fn time(args: &Args) -> Result<(), ArgError> {
+    use crate::tsc;
+    if args.len() == 0 {
+        let now = crate::rtc::now();
+        println!("{:02}:{:02}:{:02}", now.hours, now.minutes, now.seconds);
+        return Ok(());
+    }
+    let command = args.rest(1)?;
+    let khz = (tsc::frequency_khz() as u64).max(1);
+    let start = tsc::read_serialized();
+    run(command);
+    let microseconds = (tsc::read_serialized() - start) * 1000 / khz;
+    println!("real {}.{:06}s", microseconds / 1_000_000, microseconds % 1_000_000);
+    Ok(())
 }
 
-fn miao() {
-    println!("  /\\_/\\");
-    println!("=( ^.^ )=");
-    println!("  )   (   //");
-    println!(" (__ __)//");
+fn trace(args: &Args) -> Result<(), ArgError> {
+    use crate::trace;
+    match args.get(1)? {
+        "start" => trace::start(),
+        "stop" => trace::stop(),
+        "clear" => trace::clear(),
+        "dump" => {
+            let _ = trace::write_records(&mut Console);
+        }
+        "send" => println!("trace: {} records sent on COM2", trace::send_records()),
+        _ => return Err(ArgError::Invalid(1)),
+    }
+    Ok(())
 }
 
-fn reboot() {
-    unsafe {
-        use crate::io::outb;
-        outb(0x64, 0xfe);
+fn exept(args: &Args) -> Result<(), ArgError> {
+    let num = args.get_int(1)?;
+    if num > 255 {
+        return Err(ArgError::OutOfRange(1));
     }
+    println!("exept: throwing exception {}", num);
+    generate_interrupt(num as u8);
+    Ok(())
 }
 
-fn shutdown() {
-    unsafe {
-        use crate::io::outw;
-        outw(0x604, 0x2000);
+const CRASH_CONFIRM_MS: u32 = 10_000;
+
+fn crash(args: &Args) -> Result<(), ArgError> {
+    let kind = args.get(1)?;
+    if !["nullderef", "stackoverflow", "doublefault", "assert", "oom"].contains(&kind) {
+        return Err(ArgError::Invalid(1));
+    }
+    let mut confirmed = false;
+    for flag in args.flags() {
+        match flag {
+            'y' => confirmed = true,
+            _ => return Err(ArgError::UnknownFlag(flag)),
+        }
+    }
+    if !confirmed {
+        println!("crash: really trigger {}? [y/N]", kind);
+        if crate::keyboard::read_char_timeout(CRASH_CONFIRM_MS) != Some(b'y') {
+            println!("crash: cancelled");
+            return Ok(());
+        }
+    }
+    match kind {
+        "nullderef" => {
+            // Through asm: a null read in Rust is UB, which debug builds
+            // catch with a panic of their own before anything faults
+            let value: u32;
+            unsafe { core::arch::asm!("mov {0:e}, [0]", out(reg) value, options(nostack, readonly)) };
+            println!("crash: read {:#010x} from NULL, page 0 is still mapped", value);
+        }
+        "stackoverflow" => match crate::task::stack_bounds() {
+            // With no guard page, running off the stack would silently
+            // overwrite whatever lies below it, other tasks' stacks included
+            Some((bottom, _)) => {
+                println!("crash: recursing down to the bottom of the stack");
+                overflow_stack(0, bottom);
+            }
+            None => println!("crash: stackoverflow: no guard page, and the boot stack has no known bounds"),
+        },
+        "doublefault" => unsafe {
+            // A #GP whose gate is not present raises #NP during its
+            // delivery, which the CPU escalates to a double fault
+            crate::idt::set_gate_present(13, false);
+            core::arch::asm!("mov ds, {0:x}", in(reg) 0xfff8u32, options(nostack));
+        },
+        "assert" => {
+            #[allow(clippy::assertions_on_constants)]
+            assert!(false, "crash: assertion requested from the shell");
+        }
+        _ => println!("crash: oom: there is no kernel heap to exhaust yet"),
     }
+    Ok(())
 }
 
-fn uname() {
-    println!(
-        "{} {} {} {} {} {}",
-        "KFC",
-        "0.0.1-kfc1-i386",
-        "DeepFryer 0.0.1-1kfc1 (2023-12-13)",
-        "i386",
-        "KFC/Deepnux",
-        "A|L"
-    );
+const APPLET_CONFIRM_MS: u32 = 10_000;
+
+fn applet(args: &Args) -> Result<(), ArgError> {
+    use crate::applet::{ self, APPLET_BASE };
+    let path = args.get(1)?;
+    let crc32 = args.get_int(2)?;
+    let mut confirmed = false;
+    for flag in args.flags() {
+        match flag {
+            'y' => confirmed = true,
+            _ => return Err(ArgError::UnknownFlag(flag)),
+        }
+    }
+    let size = match applet::load(path, crc32) {
+        Ok(size) => size,
+        Err(error) => {
+            println!("applet: {}: {}", path, error.as_str());
+            return Ok(());
+        }
+    };
+    if !confirmed {
+        println!(
+            "applet: run {} ({} bytes at {:#x}, kernel api {}.{}) in ring 0? [y/N]",
+            path, size, APPLET_BASE, crate::api::API_VERSION_MAJOR, crate::api::API_VERSION_MINOR
+        );
+        if crate::keyboard::read_char_timeout(APPLET_CONFIRM_MS) != Some(b'y') {
+            println!("applet: cancelled");
+            return Ok(());
+        }
+    }
+    match applet::run() {
+        Ok(status) => println!("applet: {} exited with {}", path, status),
+        Err(error) => println!("applet: {}", error.as_str()),
+    }
+    Ok(())
 }
 
-fn exept(line: &str) {
-    let message: &str = &line["exept".len()..];
-    if message.starts_with(" ") && message.len() > 1 {
-        let num: usize = message[1..].trim().parse::<usize>().unwrap_or(usize::MAX);
-        if num > 255 {
-            println!("exept: argument must be between 0 and 255");
-            return;
+/// Room left below the stack pointer when overflow_stack gives up: enough
+/// for the panic handler to run on the same stack.
+const STACK_OVERFLOW_MARGIN: usize = 4096;
+
+fn overflow_stack(depth: usize, bottom: usize) -> usize {
+    let frame = core::hint::black_box([depth; 64]);
+    let esp: usize;
+    unsafe { core::arch::asm!("mov {}, esp", out(reg) esp, options(nomem, nostack, preserves_flags)) };
+    if esp < bottom + STACK_OVERFLOW_MARGIN {
+        panic!("stack overflow: esp {:#010x} at depth {}, stack bottom {:#010x}", esp, depth, bottom);
+    }
+    overflow_stack(depth + 1, bottom) + frame[0]
+}
+
+fn watch(args: &Args) -> Result<(), ArgError> {
+    use crate::debugger::{ self, WatchKind };
+    if args.len() == 0 {
+        for (slot, watch) in debugger::watchpoints().iter().enumerate() {
+            if let Some((address, kind)) = watch {
+                println!("{}: {:#010x} {}", slot, address, kind.name());
+            }
+        }
+        return Ok(());
+    }
+    let address = args.get_int(1)?;
+    let kind = match args.opt(2) {
+        Some(name) => WatchKind::parse(name).ok_or(ArgError::Invalid(2))?,
+        None => WatchKind::Write,
+    };
+    match debugger::set_watchpoint(address, kind) {
+        Ok(slot) => println!("watch: slot {} on {:#010x} ({})", slot, address, kind.name()),
+        Err(_) => println!("watch: all 4 debug registers in use"),
+    }
+    Ok(())
+}
+
+fn unwatch(args: &Args) -> Result<(), ArgError> {
+    crate::debugger::clear_watchpoint(args.get_int(1)?).map_err(|_| ArgError::OutOfRange(1))
+}
+
+fn irqmask(args: &Args) -> Result<(), ArgError> {
+    use crate::interrupts::{ self, IRQ_LINES };
+    if args.len() == 0 {
+        let _ = interrupts::write_irq_table(&mut Console);
+        return Ok(());
+    }
+    let line = args.get_int(1)?;
+    if line >= IRQ_LINES {
+        return Err(ArgError::OutOfRange(1));
+    }
+    match args.get(2)? {
+        "on" => interrupts::enable_irq(line),
+        "off" => interrupts::disable_irq(line),
+        _ => return Err(ArgError::Invalid(2)),
+    }
+    Ok(())
+}
+
+fn ls(args: &Args) -> Result<(), ArgError> {
+    use crate::vfs::{ self, NodeKind };
+    let path = args.opt(1).unwrap_or("/");
+    let result = vfs::read_dir(path, |entry| match entry.node.kind {
+        NodeKind::Directory => println!("{:>8}  {}/", "", entry.name()),
+        _ => println!("{:>8}  {}", entry.node.size, entry.name()),
+    });
+    if let Err(error) = result {
+        println!("ls: {}: {}", path, error.as_str());
+    }
+    Ok(())
+}
+
+fn cat(args: &Args) -> Result<(), ArgError> {
+    let path = args.opt(1).unwrap_or("stdin");
+    let opened = match args.opt(1) {
+        Some(path) => FILES.lock().open(path),
+        None => FILES.lock().dup(0),
+    };
+    let descriptor = match opened {
+        Ok(descriptor) => descriptor,
+        Err(error) => {
+            println!("cat: {}: {}", path, error.as_str());
+            return Ok(());
+        }
+    };
+    let mut buffer = [0; 64];
+    loop {
+        // The table must not stay locked while printing: output may be redirected to a file
+        let read = crate::vfs::read(descriptor, &mut buffer);
+        match read {
+            Ok(0) => break,
+            Ok(count) => {
+                print!("{}", core::str::from_utf8(&buffer[..count]).unwrap_or("?"));
+                crate::task::yield_now();
+            }
+            Err(error) => {
+                println!("cat: {}: {}", path, error.as_str());
+                break;
+            }
         }
-        println!("exept: throwing exception {}", num);
-        generate_interrupt(num as u8);
-    } else {
-        println!("exept: missing argument");
     }
+    let _ = FILES.lock().close(descriptor);
+    Ok(())
+}
+
+fn cksum(args: &Args) -> Result<(), ArgError> {
+    use crate::checksum;
+    let path = args.get(1)?;
+    let opened = FILES.lock().open(path);
+    let descriptor = match opened {
+        Ok(descriptor) => descriptor,
+        Err(error) => {
+            println!("cksum: {}: {}", path, error.as_str());
+            return Ok(());
+        }
+    };
+    let (mut crc, mut size) = (0, 0);
+    let mut buffer = [0; 512];
+    loop {
+        let read = crate::vfs::read(descriptor, &mut buffer);
+        match read {
+            Ok(0) => break,
+            Ok(count) => {
+                crc = checksum::crc32_update(crc, &buffer[..count]);
+                size += count;
+                crate::task::yield_now();
+            }
+            Err(error) => {
+                println!("cksum: {}: {}", path, error.as_str());
+                break;
+            }
+        }
+    }
+    let _ = FILES.lock().close(descriptor);
+    println!("{:#010x} {} {}", crc, size, path);
+    Ok(())
+}
+
+fn check(args: &Args) -> Result<(), ArgError> {
+    match args.get(1)? {
+        "paging" => {
+            let _ = crate::selfcheck::paging(&mut Console);
+        }
+        _ => return Err(ArgError::Invalid(1)),
+    }
+    Ok(())
+}
+
+fn bind(args: &Args) -> Result<(), ArgError> {
+    use crate::keyboard::{ self, Action, Chord };
+    if args.len() == 0 {
+        let _ = keyboard::write_bindings(&mut Console);
+        return Ok(());
+    }
+    let chord = Chord::parse(args.get(1)?).ok_or(ArgError::Invalid(1))?;
+    let action = match args.get(2)? {
+        "none" => None,
+        name => Some(Action::parse(name).ok_or(ArgError::Invalid(2))?),
+    };
+    if keyboard::bind(chord, action).is_err() {
+        println!("bind: too many bindings");
+    }
+    Ok(())
+}
+
+fn mount(_: &Args) -> Result<(), ArgError> {
+    crate::vfs::for_each_mount(|path, name| println!("{} on {}", name, path));
+    Ok(())
+}
+
+fn ramdisk(args: &Args) -> Result<(), ArgError> {
+    use crate::block::{ BlockDevice, BLOCK_SIZE };
+    use crate::ramdisk::RAMDISK;
+    let ramdisk = RAMDISK.lock();
+    let disk = match ramdisk.as_ref() {
+        Some(disk) => disk,
+        None => {
+            println!("ramdisk: none (boot with a module or ramdisk_size=<KB>)");
+            return Ok(());
+        }
+    };
+    if args.len() == 0 {
+        println!("ramdisk: {} KB, {} blocks, {:?}", disk.size() / 1024, disk.block_count(), disk.source);
+        return Ok(());
+    }
+    let mut block = [0; BLOCK_SIZE];
+    disk.read_block(args.get_int(1)?, &mut block).map_err(|_| ArgError::OutOfRange(1))?;
+    for (line, bytes) in block.chunks(16).take(8).enumerate() {
+        print!("{:04x}:", line * 16);
+        for byte in bytes {
+            print!(" {:02x}", byte);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn lograte(args: &Args) -> Result<(), ArgError> {
+    use crate::log::RATE_LIMIT;
+    use core::sync::atomic::Ordering;
+    if args.len() == 0 {
+        println!("lograte: {} messages per second (0 = unlimited)", RATE_LIMIT.load(Ordering::SeqCst));
+        return Ok(());
+    }
+    RATE_LIMIT.store(args.get_int(1)?, Ordering::SeqCst);
+    Ok(())
+}
+
+fn loglevel(args: &Args) -> Result<(), ArgError> {
+    use crate::log::{ Level, LOG_LEVEL };
+    use core::sync::atomic::Ordering;
+    if args.len() == 0 {
+        let level = Level::from_u8(LOG_LEVEL.load(Ordering::SeqCst)).unwrap_or(Level::Debug);
+        println!("loglevel: {} ({})", level as u8, level.tag());
+        return Ok(());
+    }
+    let level = u8::try_from(args.get_int(1)?).ok().and_then(Level::from_u8).ok_or(ArgError::OutOfRange(1))?;
+    LOG_LEVEL.store(level as u8, Ordering::SeqCst);
+    Ok(())
+}
+
+fn sysctl(args: &Args) -> Result<(), ArgError> {
+    use crate::config;
+    if args.len() == 0 {
+        config::print();
+        return Ok(());
+    }
+    let (name, value) = args.get(1)?.split_once('=').ok_or(ArgError::Invalid(1))?;
+    let value = crate::args::parse_int(value).ok_or(ArgError::Invalid(1))?;
+    if let Err(error) = config::set(name, value) {
+        println!("sysctl: {}: {}", name, error.as_str());
+    }
+    Ok(())
 }
 
 pub fn readline(raw_line: &str) {
-    let line = raw_line.trim();
+    let mut line = raw_line.trim();
     if line.is_empty() {
         return;
     }
-    HISTORY.lock().add(raw_line);
-    match line {
-        "help" | "man" => help(),
-        "clear" => clear(),
-        "printstack" => librs::print_stack(),
-        "time" => time(),
-        "miao" => miao(),
-        "reboot" => reboot(),
-        "halt" => librs::hlt(),
-        "shutdown" => shutdown(),
-        "history" => HISTORY.lock().print(),
-        "date" => date(),
-        "uname" => uname(),
-        _ => {
-            if line.starts_with("echo") {
-                echo(line);
-            } else if line.starts_with("exept") {
-                exept(line);
-            } else {
+    let expanded: FixedString<MAX_LINE_LENGTH>;
+    if line.starts_with('!') {
+        let found = HISTORY.lock().expand(line);
+        match found {
+            Some(found) => {
+                expanded = found;
+                line = expanded.as_str();
+            }
+            None => {
+                println!("{}: event not found", line.split(' ').next().unwrap_or(line));
+                return;
+            }
+        }
+        println!("{}", line);
+    }
+    HISTORY.lock().add(line);
+    if Args::new(line).name() == "calc" {
+        // | and >> are operators to calc, not a pipe and a redirection
+        return run(line);
+    }
+    match line.split_once('|') {
+        Some((first, second)) => run_piped(first.trim(), second.trim()),
+        None => run_redirected(line),
+    }
+}
+
+/// The first command's output goes into a pipe the second gets as stdin.
+/// They run one after the other, so that output is cut at the pipe's
+/// capacity.
+fn run_piped(first: &str, second: &str) {
+    let pipe = FILES.lock().pipe();
+    let [read, write] = match pipe {
+        Ok(descriptors) => descriptors,
+        Err(error) => {
+            println!("pipe: {}", error.as_str());
+            return;
+        }
+    };
+    librs::redirect(Some(write));
+    run(first);
+    librs::redirect(None);
+    let saved = {
+        let mut files = FILES.lock();
+        let _ = files.close(write);
+        let saved = files.dup(0);
+        let _ = files.dup2(read, 0);
+        let _ = files.close(read);
+        saved
+    };
+    run_redirected(second);
+    let mut files = FILES.lock();
+    match saved {
+        Ok(saved) => {
+            let _ = files.dup2(saved, 0);
+            let _ = files.close(saved);
+        }
+        Err(_) => {
+            let _ = files.close(0);
+        }
+    }
+}
+
+/// Runs `line`, sending its output to the file after a trailing `>`.
+fn run_redirected(line: &str) {
+    let (line, target) = match line.rsplit_once('>') {
+        Some((command, path)) => (command.trim(), Some(path.trim())),
+        None => (line, None),
+    };
+    let opened = target.map(|path| FILES.lock().open(path));
+    let descriptor = match opened {
+        Some(Ok(descriptor)) => Some(descriptor),
+        Some(Err(error)) => {
+            println!("{}: {}", target.unwrap_or(""), error.as_str());
+            return;
+        }
+        None => None,
+    };
+    librs::redirect(descriptor);
+    run(line);
+    librs::redirect(None);
+    if let Some(descriptor) = descriptor {
+        let _ = FILES.lock().close(descriptor);
+    }
+}
+
+fn run(line: &str) {
+    let args = Args::new(line);
+    match find_command(args.name()) {
+        Some(command) => {
+            let result = args.expect_max(command.max_args).and_then(|_| (command.handler)(&args));
+            if let Err(error) = result {
+                args.report(error, command.usage);
+            }
+        }
+        None => match crate::api::find_command(args.name()) {
+            Some(handler) => run_registered(&args, handler),
+            None => {
                 let mut len = line.len();
                 if len > 50 {
                     len = 50;
                 }
                 println!("Unknown command: {}", line[0..len].trim());
             }
-        }
+        },
+    }
+}
+
+/// A command an applet registered through the kernel API.
+fn run_registered(args: &Args, handler: crate::api::CommandHandler) {
+    let arguments = args.rest(1).unwrap_or("");
+    let status = handler(arguments.as_ptr(), arguments.len());
+    if status != 0 {
+        println!("{}: exited with {}", args.name(), status);
     }
 }
 
 pub fn print_welcome_message() {
     librs::clear();
-    println!("                                     :---------:    .---------:---------- ");
-    println!("                                   :#@@@@@@@@%=     +@@@@@@@#::@@@@@@@@@@.");
-    println!("                                 :#@@@@@@@@%=       +@@@@@%:  :@@@@@@@@@@.");
-    println!("                               :#@@@@@@@@%=         +@@@%-    :@@@@@@@@@@.");
-    println!("                             :#@@@@@@@@@=           +@%-      :@@@@@@@@@@.");
-    println!("                           :#@@@@@@@@@=             =-        -@@@@@@@@@@ ");
-    println!("                         :#@@@@@@@@@=                        +@@@@@@@@@*. ");
-    println!("                       :#@@@@@@@@@=                        +@@@@@@@@@*.   ");
-    println!("                     :#@@@@@@@@@=                        +@@@@@@@@@*.     ");
-    println!("                   :#@@@@@@@@@=                        +@@@@@@@@@*.       ");
-    println!("                 :#@@@@@@@@@=                        +@@@@@@@@@+.         ");
-    println!("                 @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@    +@@@@@@@@@#        :#.");
-    println!("                 @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@    +@@@@@@@@@#      :#@@.");
-    println!("                 @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@    +@@@@@@@@@#    :#@@@@.");
-    println!("                 @@@@@@@@@@@@@@@@@@@@@@@@@@@@@@@    +@@@@@@@@@#  :#@@@@@@.");
-    println!("                 ....................=@@@@@@@@@@    +@@@@@@@@@#:#@@@@@@@@.");
-    println!("                                     -@@@@@@@@@@     .................... ");
-    println!("                                     -@@@@@@@@@@     by                   ");
-    println!("                                     -@@@@@@@@@@          Alix Muller     ");
-    println!("                                     -@@@@@@@@@@       Lorenzo Simanic    ");
-    println!("                                     .----------                          ");
-    println!("");
-    println!("                       Welcome to KFC! Type 'help' for a list of commands!");
+    print!("{}", crate::assets::get("welcome").text());
     PROMPT.lock().init();
 }
@@ -0,0 +1,86 @@
+use core::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use crate::interrupts::{ TICKS, TICKS_PER_SECOND };
+use crate::librs;
+use crate::video_graphics_array::{ ColorCode, WRITER, VGA_COLUMNS, VGA_ROWS };
+
+/// Boot stages in the order `_start` goes through them.
+pub const STAGES: [&str; 6] = ["cpu tables", "interrupts", "serial", "multiboot", "modules", "filesystems"];
+
+const LOGO_ROW: usize = 6;
+const BAR_ROW: usize = 14;
+const BAR_WIDTH: usize = 50;
+const HOLD_TICKS: u32 = TICKS_PER_SECOND;
+
+const FULL_BLOCK: u8 = 0xdb;
+const LIGHT_SHADE: u8 = 0xb0;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static COMPLETED: AtomicUsize = AtomicUsize::new(0);
+
+/// Shows the splash when `splash` is on the kernel command line.
+pub fn init(cmdline: &str) {
+	if !crate::args::cmdline_flag(cmdline, "splash") {
+		return;
+	}
+	ENABLED.store(true, Ordering::SeqCst);
+	let mut writer = WRITER.lock();
+	writer.clear_screen();
+	for (index, line) in crate::assets::get("splash").lines().enumerate() {
+		let mut row = [b' '; VGA_COLUMNS];
+		let start = (VGA_COLUMNS - line.len()) / 2;
+		for (column, byte) in line.bytes().enumerate() {
+			row[start + column] = if byte == b'#' { FULL_BLOCK } else { b' ' };
+		}
+		writer.write_row(LOGO_ROW + index, &row, ColorCode::LightRed);
+	}
+	drop(writer);
+	draw_progress("");
+}
+
+/// Marks `name` (one of STAGES) as done and advances the bar.
+pub fn stage(name: &str) {
+	if !ENABLED.load(Ordering::SeqCst) {
+		return;
+	}
+	let position = STAGES.iter().position(|stage| *stage == name).map_or(0, |position| position + 1);
+	COMPLETED.fetch_max(position, Ordering::SeqCst);
+	draw_progress(name);
+}
+
+fn draw_progress(name: &str) {
+	let filled = COMPLETED.load(Ordering::SeqCst) * BAR_WIDTH / STAGES.len();
+	let start = (VGA_COLUMNS - BAR_WIDTH - 2) / 2;
+	let mut row = [b' '; VGA_COLUMNS];
+	row[start] = b'[';
+	for column in 0..BAR_WIDTH {
+		row[start + 1 + column] = if column < filled { FULL_BLOCK } else { LIGHT_SHADE };
+	}
+	row[start + 1 + BAR_WIDTH] = b']';
+
+	let mut label = [b' '; VGA_COLUMNS];
+	let name = &name.as_bytes()[..name.len().min(VGA_COLUMNS)];
+	let label_start = (VGA_COLUMNS - name.len()) / 2;
+	label[label_start..label_start + name.len()].copy_from_slice(name);
+
+	let mut writer = WRITER.lock();
+	writer.write_row(BAR_ROW, &row, ColorCode::White);
+	writer.write_row(BAR_ROW + 1, &label, ColorCode::DarkGray);
+	writer.update_cursor(VGA_ROWS, 0);
+}
+
+/// Leaves the splash up for HOLD_TICKS, less if a key is pressed (the key
+/// is swallowed), then clears it for the shell.
+pub fn finish() {
+	if !ENABLED.swap(false, Ordering::SeqCst) {
+		return;
+	}
+	let start = TICKS.load(Ordering::SeqCst);
+	let mut key = [0];
+	while TICKS.load(Ordering::SeqCst).wrapping_sub(start) < HOLD_TICKS {
+		if crate::keyboard::read_scancodes(&mut key) > 0 {
+			break;
+		}
+		librs::hlt();
+	}
+	librs::clear();
+}
@@ -0,0 +1,79 @@
+use core::mem::ManuallyDrop;
+use core::ops::{ Deref, DerefMut };
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use crate::task::{ self, MAX_TASKS };
+
+/// Locks each task slot holds right now. The timer does not preempt a task
+/// holding one: the task switched in could spin on it with interrupts off
+/// and never let the holder run again. A task spinning on a lock held by
+/// another counts nothing and can be switched away from.
+static LOCKS_HELD: [AtomicUsize; MAX_TASKS] = [const { AtomicUsize::new(0) }; MAX_TASKS];
+
+/// Locks held by the running task.
+pub fn locks_held() -> usize {
+	LOCKS_HELD[task::current_slot()].load(Ordering::SeqCst)
+}
+
+/// A spin::Mutex counted in LOCKS_HELD for as long as it is held.
+pub struct Mutex<T> {
+	inner: spin::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T> {
+	inner: ManuallyDrop<spin::MutexGuard<'a, T>>,
+	/// Slot counted for the lock, which the scheduler's own guard sees
+	/// change while it is held.
+	slot: usize,
+}
+
+impl<T> Mutex<T> {
+	pub const fn new(value: T) -> Mutex<T> {
+		Mutex { inner: spin::Mutex::new(value) }
+	}
+
+	/// Spins with the lock uncounted, so the timer can switch away from the
+	/// spinning task to the one holding it.
+	pub fn lock(&self) -> MutexGuard<'_, T> {
+		loop {
+			if let Some(guard) = self.try_lock() {
+				return guard;
+			}
+			core::hint::spin_loop();
+		}
+	}
+
+	/// Counted before the attempt, so the lock is never held uncounted.
+	pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+		let slot = task::current_slot();
+		LOCKS_HELD[slot].fetch_add(1, Ordering::SeqCst);
+		match self.inner.try_lock() {
+			Some(inner) => Some(MutexGuard { inner: ManuallyDrop::new(inner), slot }),
+			None => {
+				LOCKS_HELD[slot].fetch_sub(1, Ordering::SeqCst);
+				None
+			}
+		}
+	}
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.inner
+	}
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut self.inner
+	}
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+	/// Uncounted only once released.
+	fn drop(&mut self) {
+		unsafe { ManuallyDrop::drop(&mut self.inner) };
+		LOCKS_HELD[self.slot].fetch_sub(1, Ordering::SeqCst);
+	}
+}
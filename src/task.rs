@@ -0,0 +1,254 @@
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{ AtomicUsize, Ordering };
+use crate::sync::Mutex;
+use crate::config::{ self, Key };
+use crate::error::{ KernelError, KernelResult };
+use crate::{ interrupts, log, rtc, sync };
+
+pub const MAX_TASKS: usize = 8;
+const STACK_SIZE: usize = 16 * 1024;
+/// Callee-saved registers `switch_context` pushes above the return address.
+const SAVED_REGISTERS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+	Running,
+	Ready,
+	Finished,
+}
+
+impl State {
+	fn name(self) -> &'static str {
+		match self {
+			State::Running => "running",
+			State::Ready => "ready",
+			State::Finished => "finished",
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+pub struct Task {
+	pub id: usize,
+	pub name: &'static str,
+	pub state: State,
+	entry: fn(),
+	/// Saved by `switch_context` while the task is not running.
+	stack_pointer: usize,
+	pub switches: u64,
+}
+
+/// Slot 0 is the kernel task, which runs on the boot stack; the others get
+/// the stack of the same index.
+struct Scheduler {
+	tasks: [Option<Task>; MAX_TASKS],
+	current: usize,
+	next_id: usize,
+	/// Timer ticks since the current task was switched in.
+	slice_ticks: usize,
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler {
+	tasks: {
+		let mut tasks = [None; MAX_TASKS];
+		tasks[0] = Some(Task { id: 0, name: "kernel", state: State::Running, entry: || (), stack_pointer: 0, switches: 0 });
+		tasks
+	},
+	current: 0,
+	next_id: 1,
+	slice_ticks: 0,
+});
+
+/// `Scheduler::current`, readable without locking the scheduler, which
+/// counting its own lock needs.
+static CURRENT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+#[repr(align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+static mut STACKS: [Stack; MAX_TASKS] = [const { Stack([0; STACK_SIZE]) }; MAX_TASKS];
+
+impl Scheduler {
+	/// The next ready task after the current one, round robin, with the
+	/// pointers `switch_context` needs. None keeps the current task running.
+	fn switch_to_next(&mut self) -> Option<(*mut usize, usize)> {
+		let current = self.current;
+		let next = (1..MAX_TASKS)
+			.map(|offset| (current + offset) % MAX_TASKS)
+			.find(|&slot| matches!(self.tasks[slot], Some(Task { state: State::Ready, .. })))?;
+		if let Some(task) = self.tasks[current].as_mut().filter(|task| task.state == State::Running) {
+			task.state = State::Ready;
+		}
+		let task = self.tasks[next].as_mut()?;
+		task.state = State::Running;
+		task.switches += 1;
+		let new = task.stack_pointer;
+		trace!(TaskSwitch, self.tasks[current].map_or(0, |task| task.id), self.tasks[next].map_or(0, |task| task.id));
+		self.current = next;
+		CURRENT_SLOT.store(next, Ordering::SeqCst);
+		self.slice_ticks = 0;
+		// The table lives in a static, so the slot outlives the lock
+		let old = self.tasks[current].as_mut().map(|task| &mut task.stack_pointer as *mut usize)?;
+		Some((old, new))
+	}
+}
+
+/// Slot of the running task in the scheduler's table.
+pub fn current_slot() -> usize {
+	CURRENT_SLOT.load(Ordering::SeqCst)
+}
+
+/// Lowest and one past the highest address of the running task's stack,
+/// None for the kernel task on the boot stack.
+pub fn stack_bounds() -> Option<(usize, usize)> {
+	let slot = current_slot();
+	if slot == 0 {
+		return None;
+	}
+	let bottom = unsafe { core::ptr::addr_of!(STACKS[slot]) as usize };
+	Some((bottom, bottom + STACK_SIZE))
+}
+
+/// Id of the running task, 0 for the kernel task.
+pub fn current_id() -> usize {
+	let _guard = interrupts::InterruptGuard::new();
+	let scheduler = SCHEDULER.lock();
+	scheduler.tasks[scheduler.current].map_or(0, |task| task.id)
+}
+
+/// Starts `entry` as a task of its own, run once the current one yields.
+/// The slot of a finished task is reused.
+pub fn spawn(name: &'static str, entry: fn()) -> KernelResult<usize> {
+	let _guard = interrupts::InterruptGuard::new();
+	let mut scheduler = SCHEDULER.lock();
+	let slot = (1..MAX_TASKS)
+		.find(|&slot| scheduler.tasks[slot].map_or(true, |task| task.state == State::Finished))
+		.ok_or(KernelError::Busy)?;
+	let top = unsafe { (*core::ptr::addr_of_mut!(STACKS[slot])).0.as_mut_ptr() as usize + STACK_SIZE };
+	let entry_point: extern "C" fn() -> ! = task_entry;
+	// What switch_context pops: the saved registers, then task_entry as the
+	// return address, above which a null return address ends backtraces
+	let frame = (top - (SAVED_REGISTERS + 2) * 4) as *mut u32;
+	unsafe {
+		for register in 0..SAVED_REGISTERS {
+			*frame.add(register) = 0;
+		}
+		*frame.add(SAVED_REGISTERS) = entry_point as usize as u32;
+		*frame.add(SAVED_REGISTERS + 1) = 0;
+	}
+	let id = scheduler.next_id;
+	scheduler.next_id += 1;
+	scheduler.tasks[slot] = Some(Task { id, name, state: State::Ready, entry, stack_pointer: frame as usize, switches: 0 });
+	log!(Debug, "task: {} started as {}", name, id);
+	Ok(id)
+}
+
+/// First code of every spawned task, reached with interrupts off from the
+/// `yield_now` or timer tick that switched to it.
+extern "C" fn task_entry() -> ! {
+	let entry = {
+		let scheduler = SCHEDULER.lock();
+		scheduler.tasks[scheduler.current].map(|task| task.entry)
+	};
+	interrupts::enable();
+	if let Some(entry) = entry {
+		entry();
+	}
+	exit();
+}
+
+/// Ends the current task; its stack is freed for the next spawn once
+/// another task runs.
+pub fn exit() -> ! {
+	{
+		let _guard = interrupts::InterruptGuard::new();
+		let mut scheduler = SCHEDULER.lock();
+		let current = scheduler.current;
+		if let Some(task) = scheduler.tasks[current].as_mut() {
+			log!(Debug, "task: {} ({}) finished", task.name, task.id);
+			task.state = State::Finished;
+		}
+	}
+	loop {
+		yield_now();
+	}
+}
+
+/// Saves the callee-saved registers and stack pointer of the running code
+/// in `*old` and resumes the code that saved `new`.
+#[naked]
+extern "C" fn switch_context(_old: *mut usize, _new: usize) {
+	unsafe {
+		asm!(
+			"push ebp",
+			"push ebx",
+			"push esi",
+			"push edi",
+			"mov eax, [esp + 20]", // old
+			"mov ecx, [esp + 24]", // new
+			"mov [eax], esp",
+			"mov esp, ecx",
+			"pop edi",
+			"pop esi",
+			"pop ebx",
+			"pop ebp",
+			"ret",
+			options(noreturn)
+		);
+	}
+}
+
+/// Lets the rest of the kernel run in the middle of a long operation: does
+/// the main loop's background work, opens a window for pending interrupts
+/// even when called with them disabled, then switches to the next ready
+/// task.
+pub fn yield_now() {
+	log::process_pending();
+	rtc::process_alarms();
+	let enabled = interrupts::disable();
+	interrupts::enable();
+	core::hint::spin_loop();
+	interrupts::disable();
+	let switch = SCHEDULER.lock().switch_to_next();
+	if let Some((old, new)) = switch {
+		switch_context(old, new);
+	}
+	interrupts::restore(enabled);
+}
+
+/// Called from the timer interrupt after its end of interrupt, so the PIC
+/// goes on delivering ticks to the task switched in. Once the current task
+/// has had `sysctl time_slice` ticks, the next ready one takes over; the
+/// interrupted one keeps its frame on its own stack and returns from the
+/// interrupt when it is switched back in. A task holding a lock is left
+/// running until a later tick.
+pub fn tick() {
+	let slice = config::get(Key::TimeSlice);
+	if slice == 0 || sync::locks_held() != 0 {
+		return;
+	}
+	let switch = match SCHEDULER.try_lock() {
+		Some(mut scheduler) => {
+			scheduler.slice_ticks += 1;
+			if scheduler.slice_ticks < slice {
+				return;
+			}
+			scheduler.switch_to_next()
+		}
+		None => return,
+	};
+	if let Some((old, new)) = switch {
+		switch_context(old, new);
+	}
+}
+
+/// /proc/tasks.
+pub fn write_tasks(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let tasks = SCHEDULER.lock().tasks;
+	writeln!(writer, "PID  STATE     SWITCHES  NAME")?;
+	for task in tasks.iter().flatten() {
+		writeln!(writer, "{:3}  {:8}  {:8}  {}", task.id, task.state.name(), task.switches, task.name)?;
+	}
+	Ok(())
+}
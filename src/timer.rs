@@ -0,0 +1,241 @@
+use core::fmt;
+use core::ops::{ Add, Sub };
+use core::sync::atomic::{ AtomicU32, AtomicU8, Ordering };
+use crate::sync::Mutex;
+use crate::config::{ self, Key };
+use crate::hpet;
+use crate::interrupts::{ self, TICKS };
+use crate::io::outb;
+
+/// Value of `sysctl clocksource` asking for the HPET, 0 keeping the PIT.
+pub const CLOCK_HPET: usize = 1;
+
+/// Timer interrupts per second, whichever source drives IRQ0.
+pub const HZ: u32 = 1000;
+
+const PIT_INPUT_HZ: u64 = 1_193_182;
+const PIT_CHANNEL_0: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 0, low then high byte of the divisor, mode 2 (rate generator).
+const PIT_RATE_GENERATOR: u8 = 0x34;
+const PIT_DIVISOR: u64 = (PIT_INPUT_HZ + HZ as u64 / 2) / HZ as u64;
+
+/// One tick of the PIT at PIT_DIVISOR, a little off 1 / HZ since the
+/// divisor is whole. The HPET is given the same period.
+pub const TICK_NS: u64 = PIT_DIVISOR * 1_000_000_000 / PIT_INPUT_HZ;
+
+/// Times TICKS went around, so the clock outlasts its 49 days at 1000 Hz.
+static TICK_WRAPS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+	Pit,
+	Hpet,
+}
+
+impl Source {
+	pub fn name(self) -> &'static str {
+		match self {
+			Source::Pit => "pit",
+			Source::Hpet => "hpet",
+		}
+	}
+}
+
+static ACTIVE: AtomicU8 = AtomicU8::new(Source::Pit as u8);
+
+/// Keeps the time continuous across a change of source: the HPET counter
+/// starts from zero well after the PIT does.
+struct Timebase {
+	/// Added to what the active source reads.
+	offset: i64,
+	/// Last time returned, which no later reading goes below.
+	last: u64,
+}
+
+static TIMEBASE: Mutex<Timebase> = Mutex::new(Timebase { offset: 0, last: 0 });
+
+impl Timebase {
+	fn read(&mut self, source: Source) -> u64 {
+		let now = (raw_nanoseconds(source) as i64).saturating_add(self.offset).max(0) as u64;
+		self.last = self.last.max(now);
+		self.last
+	}
+}
+
+/// Programs PIT channel 0 for HZ interrupts a second in place of its
+/// power-on 18.2, before IRQ0 is unmasked.
+pub fn init() {
+	let _guard = interrupts::InterruptGuard::new();
+	unsafe {
+		outb(PIT_COMMAND, PIT_RATE_GENERATOR);
+		outb(PIT_CHANNEL_0, PIT_DIVISOR as u8);
+		outb(PIT_CHANNEL_0, (PIT_DIVISOR >> 8) as u8);
+	}
+}
+
+/// Called by the timer interrupt with the new TICKS.
+pub fn tick(now: u32) {
+	if now == 0 {
+		TICK_WRAPS.fetch_add(1, Ordering::SeqCst);
+	}
+}
+
+/// Ticks since boot, not wrapping.
+pub fn ticks() -> u64 {
+	loop {
+		let wraps = TICK_WRAPS.load(Ordering::SeqCst);
+		let ticks = TICKS.load(Ordering::SeqCst);
+		if TICK_WRAPS.load(Ordering::SeqCst) == wraps {
+			return (wraps as u64) << 32 | ticks as u64;
+		}
+	}
+}
+
+/// What drives IRQ0 and the clock.
+pub fn source() -> Source {
+	if ACTIVE.load(Ordering::SeqCst) == Source::Hpet as u8 { Source::Hpet } else { Source::Pit }
+}
+
+/// Switches to the source `sysctl clocksource` asks for, staying on the
+/// PIT when there is no HPET able to drive IRQ0.
+pub fn select() {
+	let wanted = config::get(Key::ClockSource) == CLOCK_HPET;
+	match source() {
+		Source::Pit if wanted && hpet::start_periodic(TICK_NS) => {
+			switch_to(Source::Hpet);
+			log!(Info, "timer: switched to the hpet");
+		}
+		Source::Hpet if !wanted => {
+			hpet::stop_periodic();
+			switch_to(Source::Pit);
+			log!(Info, "timer: switched to the pit");
+		}
+		_ => (),
+	}
+}
+
+/// Makes `source` active, going on from the time the old one reached.
+fn switch_to(source: Source) {
+	let _guard = interrupts::InterruptGuard::new();
+	let mut timebase = TIMEBASE.lock();
+	let now = timebase.read(self::source());
+	ACTIVE.store(source as u8, Ordering::SeqCst);
+	timebase.offset = now as i64 - raw_nanoseconds(source) as i64;
+}
+
+/// What `source` itself reads: the HPET main counter, or whole ticks.
+fn raw_nanoseconds(source: Source) -> u64 {
+	match source {
+		Source::Hpet => hpet::nanoseconds().unwrap_or(0),
+		Source::Pit => ticks() * TICK_NS,
+	}
+}
+
+/// Time since boot from the active source, never going backwards.
+pub fn nanoseconds() -> u64 {
+	let _guard = interrupts::InterruptGuard::new();
+	TIMEBASE.lock().read(source())
+}
+
+/// A span of time with nanosecond resolution, as returned by `uptime`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Duration {
+	nanoseconds: u64,
+}
+
+#[allow(dead_code)]
+impl Duration {
+	pub const fn from_nanos(nanoseconds: u64) -> Duration {
+		Duration { nanoseconds }
+	}
+
+	pub const fn from_millis(milliseconds: u64) -> Duration {
+		Duration { nanoseconds: milliseconds * 1_000_000 }
+	}
+
+	pub const fn from_secs(seconds: u64) -> Duration {
+		Duration { nanoseconds: seconds * 1_000_000_000 }
+	}
+
+	pub const fn as_nanos(self) -> u64 {
+		self.nanoseconds
+	}
+
+	pub const fn as_millis(self) -> u64 {
+		self.nanoseconds / 1_000_000
+	}
+
+	pub const fn as_secs(self) -> u64 {
+		self.nanoseconds / 1_000_000_000
+	}
+
+	pub const fn subsec_millis(self) -> u32 {
+		(self.nanoseconds / 1_000_000 % 1000) as u32
+	}
+
+	/// Whole timer ticks in this span, rounded up so a wait is never short.
+	pub const fn as_ticks(self) -> u64 {
+		self.nanoseconds.div_ceil(TICK_NS)
+	}
+}
+
+impl Add for Duration {
+	type Output = Duration;
+
+	fn add(self, other: Duration) -> Duration {
+		Duration::from_nanos(self.nanoseconds.saturating_add(other.nanoseconds))
+	}
+}
+
+impl Sub for Duration {
+	type Output = Duration;
+
+	fn sub(self, other: Duration) -> Duration {
+		Duration::from_nanos(self.nanoseconds.saturating_sub(other.nanoseconds))
+	}
+}
+
+impl fmt::Display for Duration {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}.{:03} s", self.as_secs(), self.subsec_millis())
+	}
+}
+
+/// Monotonic time since boot.
+pub fn uptime() -> Duration {
+	Duration::from_nanos(nanoseconds())
+}
+
+pub fn uptime_ms() -> u64 {
+	uptime().as_millis()
+}
+
+/// Halts until `duration` has passed, letting other tasks run meanwhile.
+/// Interrupts are opened for the wait, even when called with them off.
+pub fn sleep(duration: Duration) {
+	let deadline = uptime() + duration;
+	let _guard = interrupts::InterruptGuard::new();
+	while uptime() < deadline {
+		crate::task::yield_now();
+		interrupts::enable();
+		crate::librs::hlt();
+		interrupts::disable();
+	}
+}
+
+pub fn sleep_ms(milliseconds: u64) {
+	sleep(Duration::from_millis(milliseconds));
+}
+
+/// /proc/clock: the active source and what it reads.
+pub fn write_clock(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let now = nanoseconds();
+	writeln!(writer, "source {} at {} Hz", source().name(), HZ)?;
+	writeln!(writer, "time   {}.{:09} s", now / 1_000_000_000, now % 1_000_000_000)?;
+	match hpet::hpet() {
+		Some(hpet) => writeln!(writer, "{}{}", hpet, if hpet.can_tick { "" } else { ", counter only" }),
+		None => writeln!(writer, "no hpet"),
+	}
+}
@@ -0,0 +1,130 @@
+use core::fmt;
+use core::sync::atomic::{ AtomicBool, Ordering };
+use crate::sync::Mutex;
+use crate::collections::RingBuffer;
+use crate::frame::{ Channel, Frame };
+use crate::{ interrupts, tsc };
+
+const TRACE_CAPACITY: usize = 256;
+const RECORD_WIRE_SIZE: usize = 17;
+
+/// Records an event with two arguments when tracing is on; costs a flag
+/// test when it is off, so it can sit in interrupt handlers.
+#[macro_export]
+macro_rules! trace {
+	($event:ident, $first:expr, $second:expr) => {
+		if $crate::trace::enabled() {
+			$crate::trace::record($crate::trace::Event::$event, $first as u32, $second as u32);
+		}
+	};
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Event {
+	IrqEntry,
+	IrqExit,
+	SyscallEntry,
+	SyscallExit,
+	IdleEnter,
+	IdleExit,
+	TaskSwitch,
+}
+
+impl Event {
+	/// Decoder format: the event name, then what its two arguments mean.
+	fn describe(self) -> (&'static str, &'static str, &'static str) {
+		match self {
+			Event::IrqEntry => ("irq_entry", "line", "eip"),
+			Event::IrqExit => ("irq_exit", "line", "-"),
+			Event::SyscallEntry => ("syscall", "nr", "arg0"),
+			Event::SyscallExit => ("syscall_exit", "nr", "ret"),
+			Event::IdleEnter => ("idle", "mwait", "-"),
+			Event::IdleExit => ("idle_exit", "cycles", "-"),
+			Event::TaskSwitch => ("switch", "from", "to"),
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+	timestamp: u64,
+	event: Event,
+	arguments: [u32; 2],
+}
+
+type TraceBuffer = RingBuffer<Record, TRACE_CAPACITY>;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUFFER: Mutex<TraceBuffer> = Mutex::new(RingBuffer::new());
+
+#[inline]
+pub fn enabled() -> bool {
+	ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn record(event: Event, first: u32, second: u32) {
+	let timestamp = tsc::read();
+	let _guard = interrupts::InterruptGuard::new();
+	if let Some(mut buffer) = BUFFER.try_lock() {
+		buffer.push(Record { timestamp, event, arguments: [first, second] });
+	}
+}
+
+pub fn start() {
+	ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn stop() {
+	ENABLED.store(false, Ordering::SeqCst);
+}
+
+pub fn clear() {
+	let _guard = interrupts::InterruptGuard::new();
+	BUFFER.lock().clear();
+}
+
+/// Copy of the buffer. Tracing is paused while the copy is taken.
+fn snapshot() -> TraceBuffer {
+	let was_enabled = ENABLED.swap(false, Ordering::SeqCst);
+	let records = {
+		let _guard = interrupts::InterruptGuard::new();
+		*BUFFER.lock()
+	};
+	ENABLED.store(was_enabled, Ordering::SeqCst);
+	records
+}
+
+/// Decodes the buffer oldest first, timestamps in microseconds from the
+/// first record.
+pub fn write_records(writer: &mut dyn fmt::Write) -> fmt::Result {
+	let records = snapshot();
+	let was_enabled = enabled();
+	let khz = (tsc::frequency_khz() as u64).max(1);
+	let origin = records.iter().next().map_or(0, |record| record.timestamp);
+	for &record in records.iter() {
+		let microseconds = (record.timestamp - origin) * 1000 / khz;
+		let (name, first_name, second_name) = record.event.describe();
+		write!(writer, "{:>10}us {:12} {}={:#x}", microseconds, name, first_name, record.arguments[0])?;
+		if second_name != "-" {
+			write!(writer, " {}={:#x}", second_name, record.arguments[1])?;
+		}
+		writeln!(writer)?;
+	}
+	writeln!(writer, "{} records{}", records.len(), if was_enabled { ", tracing" } else { "" })
+}
+
+/// Sends the raw records as one Trace frame on COM2: per record the TSC
+/// (u64 LE), the event number (u8) and both arguments (u32 LE).
+pub fn send_records() -> usize {
+	let records = snapshot();
+	let mut frame = Frame::begin(Channel::Trace, records.len() * RECORD_WIRE_SIZE);
+	for record in records.iter() {
+		frame.write(&record.timestamp.to_le_bytes());
+		frame.write(&[record.event as u8]);
+		frame.write(&record.arguments[0].to_le_bytes());
+		frame.write(&record.arguments[1].to_le_bytes());
+	}
+	frame.end();
+	records.len()
+}
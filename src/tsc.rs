@@ -0,0 +1,86 @@
+use core::arch::asm;
+use core::sync::atomic::{ AtomicU32, Ordering };
+use crate::interrupts::{ self, TICKS, TICKS_PER_SECOND };
+use crate::librs::cpuid;
+use crate::timer::TICK_NS;
+
+const CALIBRATION_TICKS: u32 = TICKS_PER_SECOND / 4;
+
+/// Measured frequency in kHz, 0 until the first `frequency_khz` call.
+static FREQUENCY_KHZ: AtomicU32 = AtomicU32::new(0);
+
+const UNKNOWN: u32 = u32::MAX;
+static RDTSCP: AtomicU32 = AtomicU32::new(UNKNOWN);
+
+pub fn read() -> u64 {
+	let (low, high): (u32, u32);
+	unsafe {
+		asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags));
+	}
+	(high as u64) << 32 | low as u64
+}
+
+/// Cached since cpuid traps to the hypervisor under virtualization.
+pub fn has_rdtscp() -> bool {
+	match RDTSCP.load(Ordering::Relaxed) {
+		UNKNOWN => {
+			let present = cpuid(0x8000_0000).0 >= 0x8000_0001 && cpuid(0x8000_0001).2 & (1 << 27) != 0;
+			RDTSCP.store(present as u32, Ordering::Relaxed);
+			present
+		}
+		present => present != 0,
+	}
+}
+
+/// Waits for the instructions before it to retire, so they are not
+/// counted after the read: rdtscp when available, cpuid then rdtsc otherwise.
+pub fn read_serialized() -> u64 {
+	if !has_rdtscp() {
+		cpuid(0);
+		return read();
+	}
+	let (low, high): (u32, u32);
+	unsafe {
+		asm!("rdtscp", out("eax") low, out("edx") high, out("ecx") _, options(nomem, nostack, preserves_flags));
+	}
+	(high as u64) << 32 | low as u64
+}
+
+/// Counts cycles over CALIBRATION_TICKS timer ticks the first time it is
+/// called, which takes about a quarter of a second.
+pub fn frequency_khz() -> u32 {
+	let cached = FREQUENCY_KHZ.load(Ordering::SeqCst);
+	if cached != 0 {
+		return cached;
+	}
+	let enabled = interrupts::disable();
+	interrupts::enable();
+	let start_tick = wait_for_tick(TICKS.load(Ordering::SeqCst));
+	let start = read_serialized();
+	while TICKS.load(Ordering::SeqCst).wrapping_sub(start_tick) < CALIBRATION_TICKS {
+		crate::librs::hlt();
+	}
+	let cycles = read_serialized() - start;
+	if !enabled {
+		interrupts::disable();
+	}
+	let khz = (cycles * 1_000_000 / (CALIBRATION_TICKS as u64 * TICK_NS)) as u32;
+	FREQUENCY_KHZ.store(khz, Ordering::SeqCst);
+	khz
+}
+
+/// Zero until `frequency_khz` has calibrated, for callers that cannot wait.
+pub fn cached_frequency_khz() -> u32 {
+	FREQUENCY_KHZ.load(Ordering::SeqCst)
+}
+
+/// Halts until TICKS moves past `tick`, returning the new value.
+fn wait_for_tick(tick: u32) -> u32 {
+	loop {
+		let now = TICKS.load(Ordering::SeqCst);
+		if now != tick {
+			return now;
+		}
+		crate::librs::hlt();
+	}
+}
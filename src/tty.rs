@@ -0,0 +1,202 @@
+use crate::sync::Mutex;
+use crate::debug::{ LineDiscipline, DEBUG };
+use crate::interrupts::{ self, InterruptGuard };
+use crate::vfs::FsError;
+
+const LINE_CAPACITY: usize = 256;
+
+/// What a terminal's input turns into before the line discipline sees it.
+#[derive(Debug, Clone, Copy)]
+pub enum Input {
+	Char(u8),
+	Erase,
+	/// Ctrl held with a lowercase letter.
+	Control(u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Device {
+	/// Keyboard in, whichever screen is displayed out.
+	Console,
+	/// COM1.
+	Serial,
+}
+
+/// A terminal: where its input comes from, where its output and echo go,
+/// and the line being typed. With the default discipline a reader only
+/// gets whole lines; Ctrl+C drops the line and fails the read with
+/// Interrupted, Ctrl+D ends it early or reads as end of file on an empty
+/// line, Ctrl+U erases it. A raw discipline hands every key over as typed.
+pub struct Tty {
+	pub discipline: LineDiscipline,
+	device: Device,
+	line: [u8; LINE_CAPACITY],
+	length: usize,
+	/// Bytes at the start of `line` a reader may take.
+	ready: usize,
+	end_of_file: bool,
+	interrupted: bool,
+}
+
+impl Tty {
+	const fn new(device: Device) -> Tty {
+		Tty {
+			discipline: LineDiscipline { echo: true, crlf: true, raw: false },
+			device,
+			line: [0; LINE_CAPACITY],
+			length: 0,
+			ready: 0,
+			end_of_file: false,
+			interrupted: false,
+		}
+	}
+
+	fn next_input(&self) -> Option<Input> {
+		match self.device {
+			Device::Console => crate::keyboard::read_tty_input(),
+			Device::Serial => read_serial().map(|byte| match byte {
+				0x08 | 0x7f => Input::Erase,
+				b'\r' if self.discipline.crlf => Input::Char(b'\n'),
+				b'\n' | b'\t' => Input::Char(byte),
+				0x01..=0x1a => Input::Control(byte | 0x60),
+				_ => Input::Char(byte),
+			}),
+		}
+	}
+
+	pub fn write(&self, buffer: &[u8]) {
+		let text = core::str::from_utf8(buffer).unwrap_or("?");
+		match self.device {
+			Device::Console => crate::librs::print_async(format_args!("{}", text)),
+			Device::Serial => write_serial(text),
+		}
+	}
+
+	fn echo(&self, buffer: &[u8]) {
+		if self.discipline.echo {
+			self.write(buffer);
+		}
+	}
+
+	fn erase(&mut self) {
+		if self.length == self.ready {
+			return;
+		}
+		self.length -= 1;
+		if self.discipline.echo {
+			match self.device {
+				Device::Console => crate::video_graphics_array::backspace(),
+				Device::Serial => write_serial("\x08 \x08"),
+			}
+		}
+	}
+
+	fn push(&mut self, byte: u8) {
+		if self.length < LINE_CAPACITY {
+			self.line[self.length] = byte;
+			self.length += 1;
+		}
+	}
+
+	fn receive(&mut self, input: Input) {
+		if self.discipline.raw {
+			let byte = match input {
+				Input::Char(byte) => byte,
+				Input::Erase => 0x7f,
+				Input::Control(letter) => letter & 0x1f,
+			};
+			self.push(byte);
+			self.ready = self.length;
+			return;
+		}
+		match input {
+			Input::Char(b'\n') => {
+				self.push(b'\n');
+				self.ready = self.length;
+				self.echo(b"\n");
+			}
+			// The last byte is kept for the newline ending the line
+			Input::Char(byte) => {
+				if self.length < LINE_CAPACITY - 1 {
+					self.push(byte);
+					self.echo(&[byte]);
+				}
+			}
+			Input::Erase => self.erase(),
+			Input::Control(b'c') => {
+				self.length = self.ready;
+				self.interrupted = true;
+				self.echo(b"^C\n");
+			}
+			Input::Control(b'd') => {
+				if self.length == self.ready {
+					self.end_of_file = true;
+				}
+				self.ready = self.length;
+			}
+			Input::Control(b'u') => {
+				while self.length > self.ready {
+					self.erase();
+				}
+			}
+			Input::Control(_) => (),
+		}
+	}
+
+	/// Takes in pending input and hands over what fits of a finished line
+	/// (any byte in raw mode), None when there is none yet.
+	fn try_read(&mut self, buffer: &mut [u8]) -> Option<Result<usize, FsError>> {
+		while let Some(input) = self.next_input() {
+			self.receive(input);
+		}
+		if core::mem::take(&mut self.interrupted) {
+			return Some(Err(FsError::Interrupted));
+		}
+		if self.ready > 0 {
+			let count = self.ready.min(buffer.len());
+			buffer[..count].copy_from_slice(&self.line[..count]);
+			self.line.copy_within(count..self.length, 0);
+			self.length -= count;
+			self.ready -= count;
+			return Some(Ok(count));
+		}
+		if core::mem::take(&mut self.end_of_file) {
+			return Some(Ok(0));
+		}
+		None
+	}
+}
+
+/// Waits for a line on `tty`, which is not kept locked while waiting.
+pub fn read(tty: &Mutex<Tty>, buffer: &mut [u8]) -> Result<usize, FsError> {
+	loop {
+		if let Some(result) = tty.lock().try_read(buffer) {
+			return result;
+		}
+		wait_for_interrupt();
+	}
+}
+
+/// Interrupt handlers may log, which takes DEBUG, so it is only held here
+/// with interrupts off.
+fn read_serial() -> Option<u8> {
+	let _guard = InterruptGuard::new();
+	DEBUG.lock().try_read_byte_serial()
+}
+
+fn write_serial(text: &str) {
+	let _guard = InterruptGuard::new();
+	DEBUG.lock().write_string_serial(text);
+}
+
+/// The serial port has no receive interrupt enabled, so waiting there is
+/// bounded by the next timer tick.
+fn wait_for_interrupt() {
+	let _guard = InterruptGuard::new();
+	interrupts::enable();
+	crate::librs::hlt();
+	interrupts::disable();
+}
+
+pub static CONSOLE: Mutex<Tty> = Mutex::new(Tty::new(Device::Console));
+pub static SERIAL: Mutex<Tty> = Mutex::new(Tty::new(Device::Serial));
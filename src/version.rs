@@ -0,0 +1,10 @@
+pub const SYSNAME: &str = "KFC";
+pub const RELEASE: &str = concat!(env!("CARGO_PKG_VERSION"), "-kfc1-i386");
+pub const CODENAME: &str = "DeepFryer";
+pub const MACHINE: &str = "i386";
+pub const OPERATING_SYSTEM: &str = "KFC/Deepnux";
+pub const AUTHORS: &str = "A|L";
+
+pub const GIT_HASH: &str = env!("KFS_GIT_HASH");
+pub const BUILD_DATE: &str = env!("KFS_BUILD_DATE");
+pub const RUSTC_VERSION: &str = env!("KFS_RUSTC_VERSION");
@@ -0,0 +1,358 @@
+use crate::sync::Mutex;
+use crate::collections::{ FixedString, FixedVec };
+
+pub const MAX_PATH: usize = 256;
+pub const NAME_MAX: usize = 64;
+const MAX_MOUNTS: usize = 8;
+const MAX_OPEN_FILES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+	NotFound,
+	NotADirectory,
+	IsADirectory,
+	ReadOnly,
+	NameTooLong,
+	TooManyOpenFiles,
+	BadDescriptor,
+	Busy,
+	WouldBlock,
+	BrokenPipe,
+	Interrupted,
+}
+
+impl FsError {
+	pub fn errno(self) -> i32 {
+		match self {
+			FsError::NotFound => 2,
+			FsError::Interrupted => 4,
+			FsError::BadDescriptor => 9,
+			FsError::WouldBlock => 11,
+			FsError::Busy => 16,
+			FsError::NotADirectory => 20,
+			FsError::IsADirectory => 21,
+			FsError::TooManyOpenFiles => 24,
+			FsError::ReadOnly => 30,
+			FsError::BrokenPipe => 32,
+			FsError::NameTooLong => 36,
+		}
+	}
+
+	pub fn as_str(self) -> &'static str {
+		match self {
+			FsError::NotFound => "no such file or directory",
+			FsError::BadDescriptor => "bad file descriptor",
+			FsError::Busy => "device or resource busy",
+			FsError::NotADirectory => "not a directory",
+			FsError::IsADirectory => "is a directory",
+			FsError::TooManyOpenFiles => "too many open files",
+			FsError::ReadOnly => "read-only file system",
+			FsError::NameTooLong => "file name too long",
+			FsError::WouldBlock => "resource temporarily unavailable",
+			FsError::BrokenPipe => "broken pipe",
+			FsError::Interrupted => "interrupted",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+	File,
+	Directory,
+	Device,
+}
+
+/// A file as its filesystem identifies it; `inode` only means something to
+/// the filesystem that returned it.
+#[derive(Debug, Clone, Copy)]
+pub struct Node {
+	pub inode: usize,
+	pub kind: NodeKind,
+	pub size: usize,
+}
+
+pub struct DirEntry {
+	name: FixedString<NAME_MAX>,
+	pub node: Node,
+}
+
+impl DirEntry {
+	pub fn new(name: &str, node: Node) -> Result<DirEntry, FsError> {
+		if name.len() > NAME_MAX {
+			return Err(FsError::NameTooLong);
+		}
+		Ok(DirEntry { name: FixedString::from(name), node })
+	}
+
+	pub fn name(&self) -> &str {
+		self.name.as_str()
+	}
+}
+
+/// Paths handed to a filesystem are relative to its mount point, without
+/// leading slash, "" being the root of the filesystem.
+pub trait FileSystem: Sync {
+	fn name(&self) -> &'static str;
+	fn lookup(&self, path: &str) -> Result<Node, FsError>;
+	fn read(&self, node: Node, offset: usize, buffer: &mut [u8]) -> Result<usize, FsError>;
+	fn write(&self, node: Node, offset: usize, buffer: &[u8]) -> Result<usize, FsError>;
+	/// The `index`-th entry of a directory, None past the last one.
+	fn read_dir(&self, node: Node, index: usize) -> Result<Option<DirEntry>, FsError>;
+	/// A descriptor to `node` was opened or duplicated.
+	fn opened(&self, _node: Node) {}
+	/// A descriptor to `node` was closed.
+	fn closed(&self, _node: Node) {}
+}
+
+/// Absolute path with `.` and `..` resolved and no trailing slash.
+pub struct PathBuf {
+	bytes: [u8; MAX_PATH],
+	length: usize,
+}
+
+impl PathBuf {
+	pub fn as_str(&self) -> &str {
+		core::str::from_utf8(&self.bytes[..self.length]).unwrap_or("/")
+	}
+
+	fn push(&mut self, component: &str) -> Result<(), FsError> {
+		let separator = if self.length == 1 { 0 } else { 1 };
+		if self.length + separator + component.len() > MAX_PATH {
+			return Err(FsError::NameTooLong);
+		}
+		if separator == 1 {
+			self.bytes[self.length] = b'/';
+			self.length += 1;
+		}
+		self.bytes[self.length..self.length + component.len()].copy_from_slice(component.as_bytes());
+		self.length += component.len();
+		Ok(())
+	}
+
+	fn pop(&mut self) {
+		let parent = self.as_str().rfind('/').unwrap_or(0);
+		self.length = parent.max(1);
+	}
+}
+
+/// Relative paths are taken from `cwd`.
+pub fn normalize(path: &str, cwd: &str) -> Result<PathBuf, FsError> {
+	let mut result = PathBuf { bytes: [0; MAX_PATH], length: 1 };
+	result.bytes[0] = b'/';
+	let start = if path.starts_with('/') { "" } else { cwd };
+	for component in start.split('/').chain(path.split('/')) {
+		match component {
+			"" | "." => (),
+			".." => result.pop(),
+			name => result.push(name)?,
+		}
+	}
+	Ok(result)
+}
+
+#[derive(Clone, Copy)]
+struct Mount {
+	path: &'static str,
+	fs: &'static dyn FileSystem,
+}
+
+static MOUNTS: Mutex<FixedVec<Mount, MAX_MOUNTS>> = Mutex::new(FixedVec::new());
+
+/// `path` must be absolute and normalized.
+pub fn mount(path: &'static str, fs: &'static dyn FileSystem) -> Result<(), FsError> {
+	let mut mounts = MOUNTS.lock();
+	if mounts.iter().any(|mount| mount.path == path) {
+		return Err(FsError::Busy);
+	}
+	mounts.push(Mount { path, fs }).map_err(|_| FsError::Busy)?;
+	log!(Info, "vfs: {} mounted on {}", fs.name(), path);
+	Ok(())
+}
+
+/// Calls `f` with every mount point and the name of its filesystem.
+pub fn for_each_mount(mut f: impl FnMut(&str, &str)) {
+	for mount in MOUNTS.lock().iter() {
+		f(mount.path, mount.fs.name());
+	}
+}
+
+/// The mount with the longest prefix of `path` and the rest of the path.
+fn resolve_mount(path: &str) -> Result<(Mount, &str), FsError> {
+	let mounts = MOUNTS.lock();
+	let mut best: Option<(Mount, &str)> = None;
+	for mount in mounts.iter() {
+		let rest = match path.strip_prefix(mount.path) {
+			Some(rest) if mount.path == "/" => rest,
+			Some("") => "",
+			Some(rest) if rest.starts_with('/') => &rest[1..],
+			_ => continue,
+		};
+		if best.map_or(true, |(current, _)| mount.path.len() > current.path.len()) {
+			best = Some((*mount, rest));
+		}
+	}
+	best.ok_or(FsError::NotFound)
+}
+
+fn lookup(path: &str) -> Result<(Mount, Node), FsError> {
+	let path = normalize(path, "/")?;
+	let (mount, rest) = resolve_mount(path.as_str())?;
+	Ok((mount, mount.fs.lookup(rest)?))
+}
+
+/// Calls `f` with every entry of the directory at `path`.
+pub fn read_dir(path: &str, mut f: impl FnMut(&DirEntry)) -> Result<(), FsError> {
+	let (mount, node) = lookup(path)?;
+	if node.kind != NodeKind::Directory {
+		return Err(FsError::NotADirectory);
+	}
+	let mut index = 0;
+	while let Some(entry) = mount.fs.read_dir(node, index)? {
+		f(&entry);
+		index += 1;
+	}
+	Ok(())
+}
+
+/// Pipes are not reachable by path, their descriptors point here.
+const PIPE_MOUNT: Mount = Mount { path: "pipe:", fs: &crate::pipe::PIPEFS };
+
+#[derive(Clone, Copy)]
+struct OpenFile {
+	mount: Mount,
+	node: Node,
+	offset: usize,
+}
+
+/// Open files by descriptor, 0 to 2 being stdin, stdout and stderr. A
+/// forked process starts with a copy of its parent's table.
+pub struct FileTable {
+	files: [Option<OpenFile>; MAX_OPEN_FILES],
+	/// Bumped each time a descriptor is closed or reused, so a read done
+	/// with the table unlocked can tell it still has the same file.
+	generations: [u32; MAX_OPEN_FILES],
+}
+
+impl Clone for FileTable {
+	fn clone(&self) -> FileTable {
+		for file in self.files.iter().flatten() {
+			file.mount.fs.opened(file.node);
+		}
+		FileTable { files: self.files, generations: self.generations }
+	}
+}
+
+impl FileTable {
+	pub const fn new() -> FileTable {
+		FileTable { files: [None; MAX_OPEN_FILES], generations: [0; MAX_OPEN_FILES] }
+	}
+
+	/// Wires stdin, stdout and stderr to /dev/console, which must be mounted.
+	pub fn open_console(&mut self) -> Result<(), FsError> {
+		for descriptor in 0..3 {
+			let (mount, node) = lookup("/dev/console")?;
+			self.install(descriptor, OpenFile { mount, node, offset: 0 });
+		}
+		Ok(())
+	}
+
+	/// Puts `file` at `descriptor`, closing what was there.
+	fn install(&mut self, descriptor: usize, file: OpenFile) {
+		if let Some(previous) = self.files[descriptor].take() {
+			previous.mount.fs.closed(previous.node);
+		}
+		file.mount.fs.opened(file.node);
+		self.files[descriptor] = Some(file);
+		self.generations[descriptor] = self.generations[descriptor].wrapping_add(1);
+	}
+
+	/// Lowest free descriptor, as POSIX wants.
+	fn free_descriptor(&self) -> Result<usize, FsError> {
+		self.files.iter().position(|file| file.is_none()).ok_or(FsError::TooManyOpenFiles)
+	}
+
+	pub fn open(&mut self, path: &str) -> Result<usize, FsError> {
+		let (mount, node) = lookup(path)?;
+		let descriptor = self.free_descriptor()?;
+		self.install(descriptor, OpenFile { mount, node, offset: 0 });
+		Ok(descriptor)
+	}
+
+	/// Read and write ends of a new pipe.
+	pub fn pipe(&mut self) -> Result<[usize; 2], FsError> {
+		let mut free = (0..MAX_OPEN_FILES).filter(|&descriptor| self.files[descriptor].is_none());
+		let (read, write) = match (free.next(), free.next()) {
+			(Some(read), Some(write)) => (read, write),
+			_ => return Err(FsError::TooManyOpenFiles),
+		};
+		let (read_end, write_end) = crate::pipe::create()?;
+		self.install(read, OpenFile { mount: PIPE_MOUNT, node: read_end, offset: 0 });
+		self.install(write, OpenFile { mount: PIPE_MOUNT, node: write_end, offset: 0 });
+		Ok([read, write])
+	}
+
+	/// The copy keeps its own offset: open files are not shared objects yet.
+	pub fn dup(&mut self, descriptor: usize) -> Result<usize, FsError> {
+		let file = *self.file(descriptor)?;
+		let copy = self.free_descriptor()?;
+		self.install(copy, file);
+		Ok(copy)
+	}
+
+	/// Closes `target` first if it was open.
+	pub fn dup2(&mut self, descriptor: usize, target: usize) -> Result<usize, FsError> {
+		let file = *self.file(descriptor)?;
+		if target >= MAX_OPEN_FILES {
+			return Err(FsError::BadDescriptor);
+		}
+		if target != descriptor {
+			self.install(target, file);
+		}
+		Ok(target)
+	}
+
+	fn file(&mut self, descriptor: usize) -> Result<&mut OpenFile, FsError> {
+		self.files.get_mut(descriptor).and_then(|file| file.as_mut()).ok_or(FsError::BadDescriptor)
+	}
+
+	pub fn write(&mut self, descriptor: usize, buffer: &[u8]) -> Result<usize, FsError> {
+		let file = self.file(descriptor)?;
+		if file.node.kind == NodeKind::Directory {
+			return Err(FsError::IsADirectory);
+		}
+		let count = file.mount.fs.write(file.node, file.offset, buffer)?;
+		file.offset += count;
+		Ok(count)
+	}
+
+	pub fn close(&mut self, descriptor: usize) -> Result<(), FsError> {
+		let file = *self.file(descriptor)?;
+		self.files[descriptor] = None;
+		self.generations[descriptor] = self.generations[descriptor].wrapping_add(1);
+		file.mount.fs.closed(file.node);
+		Ok(())
+	}
+}
+
+/// There are no processes yet: everything shares the kernel's table.
+pub static FILES: Mutex<FileTable> = Mutex::new(FileTable::new());
+
+/// Reads from `descriptor` with FILES unlocked in between: a console read
+/// waits for a whole line, and holding the table would stop preemption and
+/// every other user of it.
+pub fn read(descriptor: usize, buffer: &mut [u8]) -> Result<usize, FsError> {
+	let (file, generation) = {
+		let mut files = FILES.lock();
+		(*files.file(descriptor)?, files.generations[descriptor])
+	};
+	if file.node.kind == NodeKind::Directory {
+		return Err(FsError::IsADirectory);
+	}
+	let count = file.mount.fs.read(file.node, file.offset, buffer)?;
+	let mut files = FILES.lock();
+	// Closed or replaced meanwhile: the offset belongs to another file now
+	if files.generations[descriptor] == generation {
+		files.file(descriptor)?.offset += count;
+	}
+	Ok(count)
+}
@@ -1,18 +1,34 @@
 use core::fmt;
 use lazy_static::lazy_static;
-use spin::Mutex;
-use crate::io::outb;
+use crate::sync::Mutex;
+use crate::bell;
+use crate::collections::FixedString;
+use crate::io::{ inb, outb };
 
-const NUM_SCREENS: usize = 4;
+pub const NUM_SCREENS: usize = 4;
 const VGA_BUFFER_SIZE: usize = VGA_COLUMNS * VGA_ROWS;
 
 const VGA_BUFFER_ADDRESS: usize = 0xb8000;
 pub const VGA_COLUMNS: usize = 80;
-const VGA_ROWS: usize = 25;
+pub const VGA_ROWS: usize = 25;
 pub const VGA_LAST_LINE: usize = VGA_ROWS - 1;
+const STATUS_BAR_LINE: usize = 0;
+pub const VGA_FIRST_LINE: usize = STATUS_BAR_LINE + 1;
+
+const SCREEN_TITLE_LENGTH: usize = 12;
 
 const VGA_CTRL_REGISTER: u16 = 0x3d4;
 const VGA_DATA_REGISTER: u16 = 0x3d5;
+const CURSOR_START_REGISTER: u8 = 0x0a;
+const CURSOR_DISABLE: u8 = 1 << 5;
+const SEQUENCER_INDEX: u16 = 0x3c4;
+const SEQUENCER_DATA: u16 = 0x3c5;
+const CLOCKING_MODE_REGISTER: u8 = 0x01;
+const SCREEN_OFF: u8 = 1 << 5;
+const CURSOR_END_REGISTER: u8 = 0x0b;
+/// Scanlines of the 16 line character cell the cursor covers.
+const UNDERLINE_CURSOR: (u8, u8) = (14, 15);
+const BLOCK_CURSOR: (u8, u8) = (0, 15);
 
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
@@ -20,26 +36,10 @@ lazy_static! {
         color: Color::new(ColorCode::Green, ColorCode::Black),
         buffer: unsafe { &mut *(VGA_BUFFER_ADDRESS as *mut VgaBuffer) },
         screen: [
-            ScreenState {
-                column_position: 0,
-                color: Color::new(ColorCode::Green, ColorCode::Black),
-                buffer: [0; VGA_BUFFER_SIZE],
-            },
-            ScreenState {
-                column_position: 0,
-                color: Color::new(ColorCode::Blue, ColorCode::Black),
-                buffer: [0; VGA_BUFFER_SIZE],
-            },
-            ScreenState {
-                column_position: 0,
-                color: Color::new(ColorCode::Red, ColorCode::Black),
-                buffer: [0; VGA_BUFFER_SIZE],
-            },
-            ScreenState {
-                column_position: 0,
-                color: Color::new(ColorCode::Yellow, ColorCode::Black),
-                buffer: [0; VGA_BUFFER_SIZE],
-            },
+            ScreenState::new("tty1", ScreenOwner::Shell, Color::new(ColorCode::Green, ColorCode::Black)),
+            ScreenState::new("tty2", ScreenOwner::Shell, Color::new(ColorCode::Blue, ColorCode::Black)),
+            ScreenState::new("tty3", ScreenOwner::Shell, Color::new(ColorCode::Red, ColorCode::Black)),
+            ScreenState::new("log", ScreenOwner::Logger, Color::new(ColorCode::LightGray, ColorCode::Black)),
         ],
         current_display: 0,
     });
@@ -109,10 +109,45 @@ impl VgaBuffer {
     }
 }
 
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenOwner {
+    Shell,
+    Logger,
+    Animation,
+}
+
 struct ScreenState {
     column_position: usize,
     color: Color,
     buffer: [u8; VGA_BUFFER_SIZE],
+    title: [u8; SCREEN_TITLE_LENGTH],
+    title_length: usize,
+    dirty: bool,
+    owner: ScreenOwner,
+    overwrite: bool,
+}
+
+impl ScreenState {
+    fn new(title: &str, owner: ScreenOwner, color: Color) -> ScreenState {
+        let mut screen = ScreenState {
+            column_position: 0,
+            color,
+            buffer: [0; VGA_BUFFER_SIZE],
+            title: [0; SCREEN_TITLE_LENGTH],
+            title_length: 0,
+            dirty: false,
+            owner,
+            overwrite: false,
+        };
+        screen.set_title(title);
+        screen
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title_length = title.len().min(SCREEN_TITLE_LENGTH);
+        self.title[..self.title_length].copy_from_slice(&title.as_bytes()[..self.title_length]);
+    }
 }
 
 pub struct Writer {
@@ -125,12 +160,12 @@ pub struct Writer {
 
 impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
-        if self.column_position == VGA_COLUMNS {
-            self.new_line();
-        }
         match byte {
             b'\n' => self.new_line(),
             byte => {
+                if self.column_position == VGA_COLUMNS {
+                    self.new_line();
+                }
                 self.buffer.write(
                     ScreenChar {
                         ascii_character: byte,
@@ -168,7 +203,7 @@ impl Writer {
     }
 
     fn new_line(&mut self) {
-        for row in 1..VGA_ROWS {
+        for row in VGA_FIRST_LINE + 1..VGA_ROWS {
             for column in 0..VGA_COLUMNS {
                 let character = self.buffer.read(row, column);
                 self.buffer.write(character, row - 1, column);
@@ -177,7 +212,18 @@ impl Writer {
         self.clear_row(VGA_LAST_LINE);
     }
 
-    fn clear_row(&mut self, row: usize) {
+    pub fn write_row(&mut self, row: usize, s: &[u8], foreground: ColorCode) {
+        let color = Color((self.color.0 & 0xf0) | foreground as u8);
+        for column in 0..VGA_COLUMNS {
+            let ascii_character = match s.get(column) {
+                Some(&byte) => convert_to_cp437(byte),
+                None => b' ',
+            };
+            self.buffer.write(ScreenChar { ascii_character, color }, row, column);
+        }
+    }
+
+    pub fn clear_row(&mut self, row: usize) {
         let blank = ScreenChar {
             ascii_character: b' ',
             color: self.color,
@@ -188,13 +234,111 @@ impl Writer {
         self.column_position = 0;
     }
 
-    pub fn clear_screen(&mut self) {
+    /// Swaps foreground and background of every cell; twice restores them.
+    pub fn invert_colors(&mut self) {
         for row in 0..VGA_ROWS {
+            for column in 0..VGA_COLUMNS {
+                let mut character = self.buffer.read(row, column);
+                character.color = Color(character.color.0.rotate_left(4));
+                self.buffer.write(character, row, column);
+            }
+        }
+    }
+
+    pub fn clear_screen(&mut self) {
+        for row in VGA_FIRST_LINE..VGA_ROWS {
             self.clear_row(row);
         }
+        self.draw_status_bar();
+        self.update_cursor_shape();
         self.update_cursor(VGA_LAST_LINE, self.column_position);
     }
 
+    fn draw_status_bar(&mut self) {
+        let bar = Color::new(ColorCode::Black, ColorCode::LightGray);
+        let active = Color::new(ColorCode::White, ColorCode::Blue);
+        let mut column = 0;
+
+        for screen in 0..NUM_SCREENS {
+            let color = if screen == self.current_display { active } else { bar };
+            let key = [b' ', b'F', b'1' + screen as u8, b' '];
+            let dirty = if self.screen[screen].dirty { b'*' } else { b' ' };
+            let title = self.screen[screen].title;
+            let title_length = self.screen[screen].title_length;
+
+            self.write_status(&mut column, &key, color);
+            self.write_status(&mut column, &title[..title_length], color);
+            self.write_status(&mut column, &[dirty], color);
+        }
+        let modifiers = crate::keyboard::modifiers();
+        let mut held = FixedString::<32>::new();
+        for (on, name) in [
+            (modifiers.shift, " SHIFT"),
+            (modifiers.ctrl, " CTRL"),
+            (modifiers.alt, " ALT"),
+            (modifiers.alt_gr, " ALTGR"),
+            (modifiers.caps_lock, " CAPS"),
+            (modifiers.num_lock, " NUM"),
+        ] {
+            if on {
+                held.push_str(name);
+            }
+        }
+        let mode: &[u8] = if self.screen[self.current_display].overwrite { b" OVR " } else { b" INS " };
+        while column < VGA_COLUMNS.saturating_sub(held.as_bytes().len() + mode.len()) {
+            self.write_status(&mut column, b" ", bar);
+        }
+        self.write_status(&mut column, held.as_bytes(), bar);
+        self.write_status(&mut column, mode, bar);
+    }
+
+    /// Underline while inserting, block while overwriting.
+    fn update_cursor_shape(&mut self) {
+        let (start, end) = if self.screen[self.current_display].overwrite { BLOCK_CURSOR } else { UNDERLINE_CURSOR };
+        unsafe {
+            outb(VGA_CTRL_REGISTER, CURSOR_START_REGISTER);
+            let current = inb(VGA_DATA_REGISTER);
+            outb(VGA_DATA_REGISTER, (current & 0xc0) | start);
+            outb(VGA_CTRL_REGISTER, CURSOR_END_REGISTER);
+            let current = inb(VGA_DATA_REGISTER);
+            outb(VGA_DATA_REGISTER, (current & 0xe0) | end);
+        }
+    }
+
+    /// Turns the display and the cursor off or back on, leaving the text
+    /// buffer as it is.
+    pub fn set_blanked(&mut self, blanked: bool) {
+        unsafe {
+            outb(SEQUENCER_INDEX, CLOCKING_MODE_REGISTER);
+            let clocking = inb(SEQUENCER_DATA) & !SCREEN_OFF;
+            outb(SEQUENCER_DATA, if blanked { clocking | SCREEN_OFF } else { clocking });
+            outb(VGA_CTRL_REGISTER, CURSOR_START_REGISTER);
+            let start = inb(VGA_DATA_REGISTER) & !CURSOR_DISABLE;
+            outb(VGA_DATA_REGISTER, if blanked { start | CURSOR_DISABLE } else { start });
+        }
+    }
+
+    pub fn current_owner(&self) -> ScreenOwner {
+        self.screen[self.current_display].owner
+    }
+
+    fn write_status(&mut self, column: &mut usize, s: &[u8], color: Color) {
+        for &byte in s {
+            if *column == VGA_COLUMNS {
+                return;
+            }
+            self.buffer.write(
+                ScreenChar {
+                    ascii_character: byte,
+                    color,
+                },
+                STATUS_BAR_LINE,
+                *column,
+            );
+            *column += 1;
+        }
+    }
+
     pub fn update_cursor(&mut self, row: usize, column: usize) {
         let position: u16 = (row * VGA_COLUMNS + column) as u16;
 
@@ -206,19 +350,10 @@ impl Writer {
         }
     }
 
-    pub fn move_cursor(&mut self, i: i8) {
-        if i < 0 {
-            self.column_position -= i.abs() as usize;
-        } else if i > 0 {
-            self.column_position += i as usize;
-        }
-        self.update_cursor(VGA_LAST_LINE, self.column_position);
-    }
-
     fn backup_display(&mut self) {
         self.screen[self.current_display].column_position = self.column_position;
         self.screen[self.current_display].color = self.color;
-        for row in 0..VGA_ROWS - 1 {
+        for row in VGA_FIRST_LINE..VGA_ROWS - 1 {
             for column in 0..VGA_COLUMNS {
                 self.screen[self.current_display].buffer[row * VGA_COLUMNS + column] =
                     self.buffer.read(row, column).ascii_character;
@@ -229,7 +364,7 @@ impl Writer {
     fn restore_display(&mut self, display: usize) {
         self.column_position = self.screen[display].column_position;
         self.color = self.screen[display].color;
-        for row in 0..VGA_ROWS - 1 {
+        for row in VGA_FIRST_LINE..VGA_ROWS - 1 {
             for column in 0..VGA_COLUMNS {
                 self.buffer.write(
                     ScreenChar {
@@ -244,7 +379,7 @@ impl Writer {
     }
 
     fn update_display(&mut self) {
-        for row in 0..VGA_ROWS {
+        for row in VGA_FIRST_LINE..VGA_ROWS {
             for column in 0..VGA_COLUMNS {
                 self.buffer.write(
                     ScreenChar {
@@ -260,14 +395,87 @@ impl Writer {
 }
 
 pub fn change_display(display: usize) {
-    use crate::prompt;
-    if WRITER.lock().current_display == display {
+    use crate::{ interrupts::InterruptGuard, log, prompt };
+    {
+        let _guard = InterruptGuard::new();
+        let mut writer = WRITER.lock();
+        if writer.current_display == display {
+            return;
+        }
+        writer.backup_display();
+        writer.restore_display(display);
+        writer.current_display = display;
+        writer.screen[display].dirty = false;
+        writer.draw_status_bar();
+        writer.update_cursor_shape();
+    }
+    match current_owner() {
+        ScreenOwner::Logger => log::render(),
+        _ => prompt::PROMPT.lock().init(),
+    }
+}
+
+/// Blanks the last character written on the bottom line, for TTY echo.
+pub fn backspace() {
+    let _guard = crate::interrupts::InterruptGuard::new();
+    let mut writer = WRITER.lock();
+    if writer.column_position == 0 {
         return;
     }
-    WRITER.lock().backup_display();
-    WRITER.lock().restore_display(display);
-    WRITER.lock().current_display = display;
-    prompt::PROMPT.lock().init();
+    writer.column_position -= 1;
+    let (column, color) = (writer.column_position, writer.color);
+    writer.buffer.write(ScreenChar { ascii_character: b' ', color }, VGA_LAST_LINE, column);
+    writer.update_cursor(VGA_LAST_LINE, column);
+}
+
+/// Insert key: the mode belongs to the screen it was toggled on.
+pub fn toggle_overwrite() {
+    let mut writer = WRITER.lock();
+    let current = writer.current_display;
+    writer.screen[current].overwrite = !writer.screen[current].overwrite;
+    writer.draw_status_bar();
+    writer.update_cursor_shape();
+}
+
+pub fn overwrite() -> bool {
+    let writer = WRITER.lock();
+    writer.screen[writer.current_display].overwrite
+}
+
+/// Redraws the status bar after the keyboard modifiers changed.
+pub fn refresh_status_bar() {
+    WRITER.lock().draw_status_bar();
+}
+
+pub fn set_title(screen: usize, title: &str) {
+    let mut writer = WRITER.lock();
+    writer.screen[screen].set_title(title);
+    writer.draw_status_bar();
+}
+
+pub fn mark_dirty(screen: usize) {
+    let mut writer = WRITER.lock();
+    if writer.current_display != screen && !writer.screen[screen].dirty {
+        writer.screen[screen].dirty = true;
+        writer.draw_status_bar();
+    }
+}
+
+pub fn current_owner() -> ScreenOwner {
+    WRITER.lock().current_owner()
+}
+
+/// Prints whatever `print` calls `f` makes in `foreground`, then puts the
+/// previous color back.
+pub fn with_foreground(foreground: ColorCode, f: impl FnOnce()) {
+    let previous = {
+        let mut writer = WRITER.lock();
+        let previous = writer.color;
+        writer.color = Color((previous.0 & 0xf0) | foreground as u8);
+        previous
+    };
+    f();
+    WRITER.lock().color = previous;
 }
 
 pub fn change_color(foreground: bool) {
@@ -311,8 +519,19 @@ fn convert_to_cp437(byte: u8) -> u8 {
 }
 
 impl fmt::Write for Writer {
+    /// Program output: BEL rings the bell instead of being printed. Typed
+    /// characters take write_string directly, where 0x07 is still 'ç'.
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.write_string(s);
+        let mut parts = s.split('\x07');
+        if let Some(first) = parts.next() {
+            self.write_string(first);
+        }
+        for part in parts {
+            if bell::ring() {
+                self.invert_colors();
+            }
+            self.write_string(part);
+        }
         Ok(())
     }
 }
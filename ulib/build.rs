@@ -0,0 +1,5 @@
+fn main() {
+	let directory = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+	println!("cargo:rustc-link-arg-examples=-T{}/user.ld", directory);
+	println!("cargo:rerun-if-changed=user.ld");
+}
@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+
+use ulib::println;
+
+#[no_mangle]
+fn main() -> i32 {
+	println!("hello from ring 3");
+	for count in (1..=3).rev() {
+		println!("{}...", count);
+		let _ = ulib::sleep(1000);
+	}
+	0
+}
@@ -0,0 +1,149 @@
+//! What a user program for the kernel needs: the `_start` entry point,
+//! wrappers around `int 0x80` and `print!`. Programs are `#![no_std]`,
+//! `#![no_main]` and define `#[no_mangle] fn main() -> i32`.
+#![no_std]
+
+use core::arch::asm;
+use core::ffi::CStr;
+use core::fmt;
+
+const SYS_EXIT: u32 = 1;
+const SYS_READ: u32 = 3;
+const SYS_WRITE: u32 = 4;
+const SYS_OPEN: u32 = 5;
+const SYS_CLOSE: u32 = 6;
+const SYS_GETPID: u32 = 20;
+const SYS_DUP: u32 = 41;
+const SYS_PIPE: u32 = 42;
+const SYS_DUP2: u32 = 63;
+const SYS_NANOSLEEP: u32 = 162;
+
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+pub const STDERR: usize = 2;
+
+/// Errno the kernel answered with, as a positive number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub i32);
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Linux i386 convention, the same the kernel's dispatcher follows. ebx is
+/// reserved by LLVM so it is swapped in and out around the call.
+unsafe fn syscall(number: u32, first: usize, second: usize, third: usize) -> isize {
+	let result: isize;
+	asm!(
+		"xchg {first}, ebx",
+		"int 0x80",
+		"xchg {first}, ebx",
+		first = inout(reg) first => _,
+		inlateout("eax") number as isize => result,
+		in("ecx") second,
+		in("edx") third,
+	);
+	result
+}
+
+fn check(result: isize) -> Result<usize> {
+	if result < 0 {
+		Err(Error(-result as i32))
+	} else {
+		Ok(result as usize)
+	}
+}
+
+pub fn read(fd: usize, buffer: &mut [u8]) -> Result<usize> {
+	check(unsafe { syscall(SYS_READ, fd, buffer.as_mut_ptr() as usize, buffer.len()) })
+}
+
+pub fn write(fd: usize, buffer: &[u8]) -> Result<usize> {
+	check(unsafe { syscall(SYS_WRITE, fd, buffer.as_ptr() as usize, buffer.len()) })
+}
+
+pub fn open(path: &CStr) -> Result<usize> {
+	check(unsafe { syscall(SYS_OPEN, path.as_ptr() as usize, 0, 0) })
+}
+
+pub fn close(fd: usize) -> Result<()> {
+	check(unsafe { syscall(SYS_CLOSE, fd, 0, 0) }).map(|_| ())
+}
+
+pub fn getpid() -> usize {
+	unsafe { syscall(SYS_GETPID, 0, 0, 0) as usize }
+}
+
+pub fn dup(fd: usize) -> Result<usize> {
+	check(unsafe { syscall(SYS_DUP, fd, 0, 0) })
+}
+
+/// Read and write ends.
+pub fn pipe() -> Result<[usize; 2]> {
+	let mut descriptors = [0u32; 2];
+	check(unsafe { syscall(SYS_PIPE, descriptors.as_mut_ptr() as usize, 0, 0) })?;
+	Ok([descriptors[0] as usize, descriptors[1] as usize])
+}
+
+pub fn dup2(fd: usize, target: usize) -> Result<usize> {
+	check(unsafe { syscall(SYS_DUP2, fd, target, 0) })
+}
+
+pub fn exit(code: i32) -> ! {
+	unsafe {
+		syscall(SYS_EXIT, code as usize, 0, 0);
+	}
+	// Until the kernel has processes to end, there is nowhere to return to
+	loop {
+		core::hint::spin_loop();
+	}
+}
+
+#[repr(C)]
+struct Timespec {
+	seconds: i32,
+	nanoseconds: i32,
+}
+
+pub fn sleep(ms: u32) -> Result<()> {
+	let duration = Timespec { seconds: (ms / 1000) as i32, nanoseconds: (ms % 1000 * 1_000_000) as i32 };
+	check(unsafe { syscall(SYS_NANOSLEEP, &duration as *const Timespec as usize, 0, 0) }).map(|_| ())
+}
+
+pub struct Stdout;
+
+impl fmt::Write for Stdout {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		write(STDOUT, s.as_bytes()).map(|_| ()).map_err(|_| fmt::Error)
+	}
+}
+
+#[doc(hidden)]
+pub fn print(args: fmt::Arguments) {
+	let _ = fmt::Write::write_fmt(&mut Stdout, args);
+}
+
+#[macro_export]
+macro_rules! print {
+	($($arg:tt)*) => ($crate::print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+	() => ($crate::print!("\n"));
+	($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+extern "Rust" {
+	fn main() -> i32;
+}
+
+/// crt0: the loader enters here with the user stack already set up.
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+	exit(unsafe { main() })
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+	let _ = fmt::Write::write_fmt(&mut Stdout, format_args!("panic: {}\n", info));
+	exit(101)
+}